@@ -0,0 +1,133 @@
+use crate::error::Error;
+use crate::private_rest::PrivateRestClient;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256, Sha512};
+use std::fmt;
+
+/// An API key and its decoded secret.
+#[derive(Clone)]
+pub struct Credentials {
+    api_key: String,
+    secret: Vec<u8>,
+}
+
+// The secret never ends up in logs
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Credentials")
+            .field("api_key", &self.api_key)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Credentials {
+    // `api_secret` is the base64 string shown by Kraken when the key is created
+    pub fn new(api_key: &str, api_secret: &str) -> Result<Self, Error> {
+        Ok(Credentials {
+            api_key: api_key.to_string(),
+            secret: BASE64.decode(api_secret)?,
+        })
+    }
+
+    pub fn api_key(&self) -> &str {
+        &self.api_key
+    }
+
+    // API-Sign: HMAC-SHA512 of the URI path followed by SHA256(nonce + POST data),
+    // keyed with the decoded secret
+    pub fn sign(&self, path: &str, nonce: u64, post_data: &str) -> String {
+        let digest = Sha256::new()
+            .chain_update(nonce.to_string())
+            .chain_update(post_data)
+            .finalize();
+        let mut mac =
+            Hmac::<Sha512>::new_from_slice(&self.secret).expect("HMAC accepts keys of any length");
+        mac.update(path.as_bytes());
+        mac.update(&digest);
+        BASE64.encode(mac.finalize().into_bytes())
+    }
+}
+
+// Token for the authenticated WebSocket, see `PrivateRestClient::websockets_token`
+pub async fn get_websockets_token(
+    base_url: &str,
+    credentials: &Credentials,
+) -> Result<String, Error> {
+    PrivateRestClient::with_base_url(base_url, credentials.clone())
+        .websockets_token()
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ApiError;
+    use crate::test_support::serve_http_once;
+
+    // Example from Kraken's REST authentication guide
+    const SECRET: &str =
+        "kQH5HW/8p1uGOVjbgWA7FunAmGO8lsSUXNsu3eow76sz84Q18fWxnyRzBHCd3pd5nE9qa99HAZtuZuj6F1huXg==";
+
+    #[test]
+    fn test_sign_matches_kraken_example() {
+        let credentials = Credentials::new("key", SECRET).unwrap();
+        let signature = credentials.sign(
+            "/0/private/AddOrder",
+            1616492376594,
+            "nonce=1616492376594&ordertype=limit&pair=XBTUSD&price=37500&type=buy&volume=1.25",
+        );
+        assert_eq!(
+            signature,
+            "4/dpxb3iT4tp/ZCVEwSnEsLxx0bqyhLpdfOpc6fn7OR8+UClSV5n9E6aSS8MPtnRfp32bAb0nmbRn6H8ndwLUQ=="
+        );
+        assert!(!format!("{:?}", credentials).contains("secret"));
+        assert!(matches!(
+            Credentials::new("key", "not base64!"),
+            Err(Error::InvalidSecret(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_get_websockets_token() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let ok = serve_http_once(
+                &listener,
+                r#"{"error":[],"result":{"token":"1Dwc4lzSwNWOAwkMdqhssNNFhs1ed606d1WcF3XfEMw","expires":900}}"#,
+            )
+            .await;
+            let rejected = serve_http_once(&listener, r#"{"error":["EAPI:Invalid key"]}"#).await;
+            (ok, rejected)
+        });
+
+        let credentials = Credentials::new("my-key", SECRET).unwrap();
+        let token = get_websockets_token(&base_url, &credentials).await.unwrap();
+        assert_eq!(token, "1Dwc4lzSwNWOAwkMdqhssNNFhs1ed606d1WcF3XfEMw");
+        match get_websockets_token(&base_url, &credentials).await {
+            Err(Error::Api(errors)) => assert_eq!(errors, vec![ApiError::InvalidKey]),
+            other => panic!("expected an API error, got {:?}", other),
+        }
+
+        let (request, _) = server.await.unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/0/private/GetWebSocketsToken");
+        assert_eq!(request.header("api-key"), Some("my-key"));
+        let nonce: u64 = request
+            .body
+            .strip_prefix("nonce=")
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert_eq!(
+            request.header("api-sign"),
+            Some(
+                credentials
+                    .sign(&request.path, nonce, &request.body)
+                    .as_str()
+            )
+        );
+    }
+}
@@ -0,0 +1,232 @@
+use crate::messages::{ChannelData, ChannelMessage};
+use crate::order_book::{BookStatus, ChecksumMismatch, OrderBook};
+use std::collections::{BTreeMap, HashMap};
+
+/// Order books for many pairs sharing one connection.
+///
+/// Channel messages are routed by Kraken's `channelID` once the subscription
+/// has been confirmed, and by pair name before that. Each pair keeps its own
+/// depth, precision and checksum state.
+#[derive(Debug, Clone, Default)]
+pub struct BookManager {
+    books: HashMap<String, OrderBook>,
+    channels: HashMap<u64, String>,
+}
+
+// What a routed book message did to its book
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BookEvent {
+    Initialized {
+        pair: String,
+    },
+    Updated {
+        pair: String,
+    },
+    // Update dropped because the book is waiting for a snapshot
+    Stale {
+        pair: String,
+    },
+    ChecksumMismatch {
+        pair: String,
+        mismatch: ChecksumMismatch,
+    },
+}
+
+impl BookManager {
+    pub fn new() -> Self {
+        BookManager::default()
+    }
+
+    // Tracks a pair with a book configured for its depth and precision
+    pub fn add_book(&mut self, pair: &str, book: OrderBook) {
+        self.books.insert(pair.to_string(), book);
+    }
+
+    pub fn get(&self, pair: &str) -> Option<&OrderBook> {
+        self.books.get(pair)
+    }
+
+    // For feeds that are not routed through `handle`, e.g. Kraken Futures
+    pub fn get_mut(&mut self, pair: &str) -> Option<&mut OrderBook> {
+        self.books.get_mut(pair)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &OrderBook)> {
+        self.books.iter().map(|(pair, book)| (pair.as_str(), book))
+    }
+
+    // True when no book is waiting for a snapshot or stale
+    pub fn all_synced(&self) -> bool {
+        self.books
+            .values()
+            .all(|book| book.status() == BookStatus::Synced)
+    }
+
+    // Pairs grouped by depth, so each group can share one subscribe request
+    pub fn pairs_by_depth(&self) -> BTreeMap<usize, Vec<String>> {
+        let mut groups: BTreeMap<usize, Vec<String>> = BTreeMap::new();
+        for (pair, book) in &self.books {
+            groups.entry(book.depth()).or_default().push(pair.clone());
+        }
+        for pairs in groups.values_mut() {
+            pairs.sort();
+        }
+        groups
+    }
+
+    // Records the channel Kraken assigned in a `subscribed` subscriptionStatus
+    pub fn register_channel(&mut self, channel_id: u64, pair: &str) {
+        if self.books.contains_key(pair) {
+            self.channels.insert(channel_id, pair.to_string());
+        }
+    }
+
+    pub fn unregister_channel(&mut self, channel_id: u64) {
+        self.channels.remove(&channel_id);
+    }
+
+    // Resets every book and forgets channel IDs, which Kraken assigns per connection
+    pub fn reset_all(&mut self) {
+        for book in self.books.values_mut() {
+            book.reset();
+        }
+        self.channels.clear();
+    }
+
+    // Applies a book snapshot or update to the book it belongs to.
+    // Returns `None` for other channels and for books this manager does not track.
+    pub fn handle(&mut self, message: &ChannelMessage) -> Option<BookEvent> {
+        let depth = book_channel_depth(&message.channel_name)?;
+        let pair = match self.channels.get(&message.channel_id) {
+            Some(pair) => pair.clone(),
+            None => message.pair.clone(),
+        };
+        let book = self.books.get_mut(&pair)?;
+        if book.depth() != depth {
+            return None;
+        }
+
+        match &message.data {
+            ChannelData::BookSnapshot(snapshot) => match book.apply_snapshot(snapshot) {
+                Ok(()) => Some(BookEvent::Initialized { pair }),
+                Err(mismatch) => Some(BookEvent::ChecksumMismatch { pair, mismatch }),
+            },
+            ChannelData::BookUpdate(update) => {
+                let was_synced = book.status() == BookStatus::Synced;
+                match book.apply_update(update) {
+                    Ok(()) if was_synced => Some(BookEvent::Updated { pair }),
+                    Ok(()) => Some(BookEvent::Stale { pair }),
+                    Err(mismatch) => Some(BookEvent::ChecksumMismatch { pair, mismatch }),
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+// `book-25` -> 25
+fn book_channel_depth(channel_name: &str) -> Option<usize> {
+    channel_name.strip_prefix("book-")?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn channel_message(text: &str) -> ChannelMessage {
+        serde_json::from_str(text).unwrap()
+    }
+
+    fn manager() -> BookManager {
+        let mut manager = BookManager::new();
+        manager.add_book("XBT/USD", OrderBook::new(10));
+        manager.add_book("ETH/XBT", OrderBook::new(25));
+        manager
+    }
+
+    #[test]
+    fn test_routes_by_pair_and_channel_id() {
+        let mut manager = manager();
+
+        let event = manager.handle(&channel_message(
+            r#"[336,{"as":[["62001.5","0.01250000","1700000000.000000"]],"bs":[["62001.4","1.50000000","1700000000.000000"]]},"book-10","XBT/USD"]"#,
+        ));
+        assert_eq!(
+            event,
+            Some(BookEvent::Initialized {
+                pair: "XBT/USD".to_string()
+            })
+        );
+
+        // Once registered, the channel ID wins over the pair name in the frame
+        manager.register_channel(560, "ETH/XBT");
+        let event = manager.handle(&channel_message(
+            r#"[560,{"as":[["0.05301","2.10000000","1700000000.000000"]],"bs":[["0.05300","0.75000000","1700000000.000000"]]},"book-25","XETHXXBT"]"#,
+        ));
+        assert_eq!(
+            event,
+            Some(BookEvent::Initialized {
+                pair: "ETH/XBT".to_string()
+            })
+        );
+
+        let xbt = manager.get("XBT/USD").unwrap();
+        let eth = manager.get("ETH/XBT").unwrap();
+        assert_eq!(xbt.asks().next().unwrap().price, "62001.5".parse().unwrap());
+        assert_eq!(eth.bids().next().unwrap().price, "0.053".parse().unwrap());
+        assert_eq!(xbt.depth(), 10);
+        assert_eq!(eth.depth(), 25);
+    }
+
+    #[test]
+    fn test_ignores_other_channels_and_unknown_pairs() {
+        let mut manager = manager();
+        assert_eq!(
+            manager.handle(&channel_message(
+                r#"[0,{"as":[],"bs":[]},"book-10","DOGE/USD"]"#
+            )),
+            None
+        );
+        // Depth has to match the book tracked for the pair
+        assert_eq!(
+            manager.handle(&channel_message(
+                r#"[0,{"as":[],"bs":[]},"book-100","XBT/USD"]"#
+            )),
+            None
+        );
+        assert_eq!(
+            manager.handle(&channel_message(
+                r#"[0,[["5541.20000","0.15850568","1534614057.321597","s","l",""]],"trade","XBT/USD"]"#
+            )),
+            None
+        );
+    }
+
+    #[test]
+    fn test_independent_checksum_state() {
+        let mut manager = manager();
+        manager.handle(&channel_message(
+            r#"[1,{"as":[["62001.5","0.01250000","1700000000.000000"]],"bs":[]},"book-10","XBT/USD"]"#,
+        ));
+        manager.handle(&channel_message(
+            r#"[2,{"as":[["0.05301","2.10000000","1700000000.000000"]],"bs":[]},"book-25","ETH/XBT"]"#,
+        ));
+
+        let event = manager.handle(&channel_message(
+            r#"[1,{"a":[["62001.6","1.00000000","1700000001.000000"]],"c":"1"},"book-10","XBT/USD"]"#,
+        ));
+        assert!(
+            matches!(event, Some(BookEvent::ChecksumMismatch { ref pair, .. }) if pair == "XBT/USD")
+        );
+        assert_eq!(manager.get("XBT/USD").unwrap().status(), BookStatus::Stale);
+        assert_eq!(manager.get("ETH/XBT").unwrap().status(), BookStatus::Synced);
+
+        assert_eq!(
+            manager.pairs_by_depth(),
+            BTreeMap::from([
+                (10, vec!["XBT/USD".to_string()]),
+                (25, vec!["ETH/XBT".to_string()]),
+            ])
+        );
+    }
+}
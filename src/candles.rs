@@ -0,0 +1,158 @@
+use crate::messages::Ohlc;
+use std::collections::{HashMap, VecDeque};
+
+// Closed candles kept per pair and interval: half a day of 1 minute candles
+pub const DEFAULT_CANDLE_HISTORY: usize = 720;
+
+/// Candles for every pair and interval received on the `ohlc` channel.
+///
+/// Kraken resends the open candle on every trade; frames with the same end time
+/// replace it in place, and the first frame with a later end time closes it.
+#[derive(Debug, Clone)]
+pub struct CandleStore {
+    history_limit: usize,
+    series: HashMap<(String, u32), CandleSeries>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct CandleSeries {
+    open: Option<Ohlc>,
+    // Oldest first
+    closed: VecDeque<Ohlc>,
+}
+
+impl Default for CandleStore {
+    fn default() -> Self {
+        CandleStore::new(DEFAULT_CANDLE_HISTORY)
+    }
+}
+
+impl CandleStore {
+    pub fn new(history_limit: usize) -> Self {
+        CandleStore {
+            history_limit,
+            series: HashMap::new(),
+        }
+    }
+
+    // The candle still in progress
+    pub fn open_candle(&self, pair: &str, interval: u32) -> Option<&Ohlc> {
+        self.series(pair, interval)?.open.as_ref()
+    }
+
+    // Closed candles, oldest first
+    pub fn history(&self, pair: &str, interval: u32) -> impl Iterator<Item = &Ohlc> {
+        self.series(pair, interval)
+            .into_iter()
+            .flat_map(|series| series.closed.iter())
+    }
+
+    // Applies one ohlc frame and returns the candle it closed, if any.
+    // Frames older than the open candle are ignored.
+    pub fn update(&mut self, pair: &str, interval: u32, candle: Ohlc) -> Option<Ohlc> {
+        let series = self.series.entry((pair.to_string(), interval)).or_default();
+        let open = match series.open.take() {
+            Some(open) => open,
+            None => {
+                series.open = Some(candle);
+                return None;
+            }
+        };
+
+        if candle.end_time < open.end_time {
+            series.open = Some(open);
+            return None;
+        }
+        if candle.end_time == open.end_time {
+            series.open = Some(candle);
+            return None;
+        }
+
+        series.open = Some(candle);
+        series.closed.push_back(open.clone());
+        while series.closed.len() > self.history_limit {
+            series.closed.pop_front();
+        }
+        Some(open)
+    }
+
+    fn series(&self, pair: &str, interval: u32) -> Option<&CandleSeries> {
+        self.series.get(&(pair.to_string(), interval))
+    }
+}
+
+// `ohlc-5` -> 5
+pub(crate) fn ohlc_channel_interval(channel_name: &str) -> Option<u32> {
+    channel_name.strip_prefix("ohlc-")?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A 1 minute candle ending at `end_time`, last updated at `time`
+    fn candle(time: &str, end_time: &str, close: &str) -> Ohlc {
+        serde_json::from_value(serde_json::json!([
+            time, end_time, "5000.0", "5010.0", "4990.0", close, "5001.0", "1.5", 3
+        ]))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_updates_open_candle_in_place() {
+        let mut store = CandleStore::default();
+        assert_eq!(
+            store.update("XBT/USD", 1, candle("60.1", "120.0", "5001.0")),
+            None
+        );
+        assert_eq!(
+            store.update("XBT/USD", 1, candle("75.3", "120.0", "5005.0")),
+            None
+        );
+
+        let open = store.open_candle("XBT/USD", 1).unwrap();
+        assert_eq!(open.close, "5005".parse().unwrap());
+        assert_eq!(store.history("XBT/USD", 1).count(), 0);
+        assert!(store.open_candle("XBT/USD", 5).is_none());
+    }
+
+    #[test]
+    fn test_new_interval_closes_candle() {
+        let mut store = CandleStore::default();
+        store.update("XBT/USD", 1, candle("75.3", "120.0", "5005.0"));
+
+        let closed = store.update("XBT/USD", 1, candle("121.0", "180.0", "5006.0"));
+        assert_eq!(closed, Some(candle("75.3", "120.0", "5005.0")));
+        assert_eq!(
+            store.open_candle("XBT/USD", 1).unwrap().end_time,
+            "180".parse().unwrap()
+        );
+
+        // A late frame for the closed interval changes nothing
+        assert_eq!(
+            store.update("XBT/USD", 1, candle("119.0", "120.0", "4000.0")),
+            None
+        );
+        assert_eq!(
+            store.history("XBT/USD", 1).collect::<Vec<_>>(),
+            vec![&candle("75.3", "120.0", "5005.0")]
+        );
+    }
+
+    #[test]
+    fn test_history_is_bounded() {
+        let mut store = CandleStore::new(2);
+        for minute in 1..=5 {
+            let end_time = format!("{}.0", minute * 60);
+            store.update("ETH/USD", 1, candle(&end_time, &end_time, "180.0"));
+        }
+
+        let ends: Vec<_> = store
+            .history("ETH/USD", 1)
+            .map(|candle| candle.end_time.to_string())
+            .collect();
+        assert_eq!(ends, vec!["180.0", "240.0"]);
+        assert_eq!(ohlc_channel_interval("ohlc-1440"), Some(1440));
+        assert_eq!(ohlc_channel_interval("spread"), None);
+    }
+}
@@ -0,0 +1,2143 @@
+use crate::auth::{get_websockets_token, Credentials};
+use crate::book_manager::{BookEvent, BookManager};
+use crate::candles::{ohlc_channel_interval, CandleStore, DEFAULT_CANDLE_HISTORY};
+use crate::dead_mans_switch::{BookWatch, DeadMansSwitch, DeadMansSwitchConfig, SwitchState};
+use crate::driver::{self, Driver, Link, Session};
+use crate::error::Error;
+use crate::heartbeat::HeartbeatConfig;
+use crate::level3::Level3Book;
+use crate::messages::{
+    Channel, ChannelData, ChannelMessage, EventMessage, Ohlc, Request, Subscription, Trade,
+    WsMessage, OHLC_INTERVALS,
+};
+use crate::order_book::{BookStatus, OrderBook};
+use crate::orders::{self, AddOrder, AddOrderResult, EditOrder, EditOrderResult, PendingReplies};
+use crate::private_messages::{OrderUpdate, OwnTrade, PrivateData};
+use crate::reconnect::ReconnectConfig;
+use crate::rest::REST_URL;
+use crate::spreads::{
+    check_spread, DriftConfig, DriftFilter, SpreadDrift, SpreadHistory, DEFAULT_SPREAD_HISTORY,
+};
+use crate::subscriptions::{self, SubscriptionRegistry};
+use crate::ticker_cache::TickerCache;
+use crate::v2;
+use futures_util::Stream;
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock, RwLockReadGuard};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+
+pub const PUBLIC_URL: &str = "wss://ws.kraken.com/";
+pub const AUTH_URL: &str = "wss://ws-auth.kraken.com/";
+pub const PUBLIC_URL_V2: &str = "wss://ws.kraken.com/v2";
+
+/// Wire format spoken on a connection. v2 is translated to the same events,
+/// books and stores as v1, but only covers the `book`, `level3` and `trade`
+/// channels so far. Subscribing to anything else, sending orders or asking for
+/// a dead man's switch over v2 fails with `Error::Unsupported` before anything
+/// is sent.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Protocol {
+    #[default]
+    V1,
+    V2,
+}
+
+/// Something the client received or did on the connection.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ClientEvent {
+    Event(EventMessage),
+    Book(BookEvent),
+    // What a `level3` message did to the pair's book in `level3_books()`
+    Level3(BookEvent),
+    // One trade frame, oldest trade first
+    Trades {
+        pair: String,
+        trades: Vec<Trade>,
+    },
+    // The open candle in the candle store changed
+    Candle {
+        pair: String,
+        interval: u32,
+    },
+    // A new interval started; `candle` is final and now part of the history
+    CandleClosed {
+        pair: String,
+        interval: u32,
+        candle: Ohlc,
+    },
+    // A spread record was added to the pair's history
+    Spread {
+        pair: String,
+    },
+    // The spread channel has kept disagreeing with the top of the pair's synced book
+    SpreadDrift {
+        pair: String,
+        drift: SpreadDrift,
+    },
+    // The pair's entry in the ticker cache was replaced
+    Ticker {
+        pair: String,
+    },
+    // Fills from the private `ownTrades` channel
+    OwnTrades {
+        trades: Vec<OwnTrade>,
+        sequence: Option<u64>,
+    },
+    // Order status changes from the private `openOrders` channel
+    OpenOrders {
+        orders: Vec<OrderUpdate>,
+        sequence: Option<u64>,
+    },
+    // Channel data the client does not process itself
+    Channel(ChannelMessage),
+    Connection(ConnectionEvent),
+}
+
+/// Connection state changes; data may have been missed between `Disconnected`
+/// and `Reconnected`, and all books wait for new snapshots after a reconnect.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConnectionEvent {
+    Disconnected { reason: String },
+    Reconnecting { attempt: u32, delay: Duration },
+    Reconnected,
+    // Round trip of a `ping` answered by a matching `pong`
+    Latency { round_trip: Duration },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientConfig {
+    // Has to match the url connected to, e.g. `PUBLIC_URL_V2` for `Protocol::V2`
+    pub protocol: Protocol,
+    pub reconnect: ReconnectConfig,
+    pub heartbeat: HeartbeatConfig,
+    // Closed candles kept per pair and interval
+    pub candle_history: usize,
+    // Spread records kept per pair
+    pub spread_history: usize,
+    // When a disagreement between spread channel and book is reported
+    pub spread_drift: DriftConfig,
+    // Only useful on an authenticated connection
+    pub dead_mans_switch: Option<DeadMansSwitchConfig>,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        ClientConfig {
+            protocol: Protocol::default(),
+            reconnect: ReconnectConfig::default(),
+            heartbeat: HeartbeatConfig::default(),
+            candle_history: DEFAULT_CANDLE_HISTORY,
+            spread_history: DEFAULT_SPREAD_HISTORY,
+            spread_drift: DriftConfig::default(),
+            dead_mans_switch: None,
+        }
+    }
+}
+
+/// Async client for the Kraken WebSocket, public or authenticated.
+///
+/// The socket is owned by a background task; requests are queued to it and
+/// everything it receives comes back through the client's `Stream`. Book
+/// channels are applied to a shared `BookManager`, which is resynchronised
+/// automatically when a checksum does not match. Tickers, candles and spreads
+/// are kept in shared `TickerCache`, `CandleStore` and `SpreadHistory`.
+pub struct KrakenWsClient {
+    requests: mpsc::UnboundedSender<Command>,
+    events: mpsc::UnboundedReceiver<Result<ClientEvent, Error>>,
+    books: Arc<RwLock<BookManager>>,
+    level3_books: Arc<RwLock<HashMap<String, Level3Book>>>,
+    tickers: Arc<RwLock<TickerCache>>,
+    candles: Arc<RwLock<CandleStore>>,
+    spreads: Arc<RwLock<SpreadHistory>>,
+    subscriptions: Arc<RwLock<SubscriptionRegistry>>,
+    switch_state: Option<Arc<RwLock<SwitchState>>>,
+    protocol: Protocol,
+    next_reqid: Arc<AtomicU64>,
+}
+
+// A request for the connection task, with whoever waits for Kraken's answer
+struct Command {
+    request: Request,
+    reply: Option<Reply>,
+}
+
+enum Reply {
+    Subscription(subscriptions::Responder),
+    // Resolved once `expected` order statuses with the request's reqid arrived
+    Order {
+        expected: usize,
+        responder: orders::Responder,
+    },
+}
+
+impl KrakenWsClient {
+    pub async fn connect(url: &str) -> Result<Self, Error> {
+        KrakenWsClient::connect_with_config(url, ClientConfig::default()).await
+    }
+
+    // Only the first connection attempt is reported as an error; later drops are retried
+    pub async fn connect_with_config(url: &str, config: ClientConfig) -> Result<Self, Error> {
+        KrakenWsClient::start(url, None, config).await
+    }
+
+    // Fetches a WebSockets token over REST and connects to the authenticated endpoint
+    pub async fn connect_private(
+        credentials: &Credentials,
+        config: ClientConfig,
+    ) -> Result<Self, Error> {
+        let token = get_websockets_token(REST_URL, credentials).await?;
+        KrakenWsClient::connect_authenticated(AUTH_URL, &token, config).await
+    }
+
+    // The token is added to every private subscription, including those replayed
+    // after a reconnect; Kraken rejects them once an unused token has expired
+    pub async fn connect_authenticated(
+        url: &str,
+        token: &str,
+        config: ClientConfig,
+    ) -> Result<Self, Error> {
+        KrakenWsClient::start(url, Some(token.to_string()), config).await
+    }
+
+    async fn start(url: &str, token: Option<String>, config: ClientConfig) -> Result<Self, Error> {
+        if config.protocol == Protocol::V2 && config.dead_mans_switch.is_some() {
+            return Err(Error::Unsupported("dead man's switch over v2".to_string()));
+        }
+        config.heartbeat.validate()?;
+        if let Some(switch) = &config.dead_mans_switch {
+            switch.validate()?;
+        }
+        let socket = driver::connect(url).await?;
+
+        let (requests_tx, requests_rx) = mpsc::unbounded_channel();
+        let (events_tx, events_rx) = mpsc::unbounded_channel();
+        let books = Arc::new(RwLock::new(BookManager::new()));
+        let level3_books = Arc::new(RwLock::new(HashMap::new()));
+        let tickers = Arc::new(RwLock::new(TickerCache::new()));
+        let candles = Arc::new(RwLock::new(CandleStore::new(config.candle_history)));
+        let spreads = Arc::new(RwLock::new(SpreadHistory::new(config.spread_history)));
+        let subscriptions = Arc::new(RwLock::new(SubscriptionRegistry::new()));
+        let next_reqid = Arc::new(AtomicU64::new(1));
+        let dead_mans_switch = config.dead_mans_switch.map(DeadMansSwitch::new);
+        let switch_state = dead_mans_switch.as_ref().map(DeadMansSwitch::state);
+
+        let connection = Connection {
+            token,
+            next_reqid: Arc::clone(&next_reqid),
+            events: events_tx,
+            books: Arc::clone(&books),
+            level3_books: Arc::clone(&level3_books),
+            tickers: Arc::clone(&tickers),
+            candles: Arc::clone(&candles),
+            spreads: Arc::clone(&spreads),
+            drift_filter: DriftFilter::new(config.spread_drift),
+            subscriptions: Arc::clone(&subscriptions),
+            order_replies: PendingReplies::default(),
+            dead_mans_switch,
+            decoder: (config.protocol == Protocol::V2).then(v2::Decoder::new),
+        };
+        let driver = Driver::new(
+            socket,
+            config.reconnect,
+            config.heartbeat,
+            requests_rx,
+            connection,
+        );
+        tokio::spawn(driver.run());
+
+        Ok(KrakenWsClient {
+            requests: requests_tx,
+            events: events_rx,
+            books,
+            level3_books,
+            tickers,
+            candles,
+            spreads,
+            subscriptions,
+            switch_state,
+            protocol: config.protocol,
+            next_reqid,
+        })
+    }
+
+    // Resolves once Kraken confirmed every pair, or with its `errorMessage` if any pair failed.
+    // Book subscriptions get a v1 book (see `OrderBook::new`) for pairs that are not tracked yet;
+    // over v2 the book needs the pair's precision, so it has to come from `subscribe_book`.
+    // Ohlc intervals outside `OHLC_INTERVALS` are refused before anything is sent.
+    pub async fn subscribe(&self, pairs: &[&str], channel: Channel) -> Result<(), Error> {
+        match channel {
+            Channel::Book { depth } => {
+                let mut books = self.books.write().unwrap();
+                let untracked: Vec<&str> = pairs
+                    .iter()
+                    .copied()
+                    .filter(|pair| books.get(pair).is_none())
+                    .collect();
+                if self.protocol == Protocol::V2 && !untracked.is_empty() {
+                    return Err(Error::Config(format!(
+                        "v2 books need the pair's precision; use `subscribe_book` with \
+                         `OrderBook::with_precision` for {}",
+                        untracked.join(", ")
+                    )));
+                }
+                for pair in untracked {
+                    books.add_book(pair, OrderBook::new(depth));
+                }
+            }
+            Channel::Ohlc { interval } if !OHLC_INTERVALS.contains(&interval) => {
+                return Err(Error::Config(format!(
+                    "unsupported ohlc interval {}; expected one of {:?}",
+                    interval, OHLC_INTERVALS
+                )));
+            }
+            _ => (),
+        }
+        self.request(Request::Subscribe {
+            reqid: Some(self.next_reqid()),
+            pair: pairs.iter().map(|pair| pair.to_string()).collect(),
+            subscription: channel.subscription(),
+        })
+        .await
+    }
+
+    // Trades for these pairs arrive as `ClientEvent::Trades`
+    pub async fn subscribe_trades(&self, pairs: &[&str]) -> Result<(), Error> {
+        self.subscribe(pairs, Channel::Trade).await
+    }
+
+    // Tickers for these pairs are kept in `tickers()`
+    pub async fn subscribe_ticker(&self, pairs: &[&str]) -> Result<(), Error> {
+        self.subscribe(pairs, Channel::Ticker).await
+    }
+
+    // `interval` is one of `OHLC_INTERVALS`; candles are kept in `candles()`
+    pub async fn subscribe_ohlc(&self, pairs: &[&str], interval: u32) -> Result<(), Error> {
+        self.subscribe(pairs, Channel::Ohlc { interval }).await
+    }
+
+    // Spreads are kept in `spreads()` and checked against the pair's book, if tracked
+    pub async fn subscribe_spread(&self, pairs: &[&str]) -> Result<(), Error> {
+        self.subscribe(pairs, Channel::Spread).await
+    }
+
+    // Places an order; needs an authenticated connection
+    pub async fn add_order(&self, order: AddOrder) -> Result<AddOrderResult, Error> {
+        let request = Request::AddOrder {
+            token: None,
+            reqid: Some(self.next_reqid()),
+            order,
+        };
+        match self.order_request(request, 1).await? {
+            EventMessage::AddOrderStatus { txid, descr, .. } => Ok(AddOrderResult {
+                txid,
+                description: descr,
+            }),
+            other => Err(unexpected_reply(other)),
+        }
+    }
+
+    pub async fn edit_order(&self, edit: EditOrder) -> Result<EditOrderResult, Error> {
+        let request = Request::EditOrder {
+            token: None,
+            reqid: Some(self.next_reqid()),
+            edit,
+        };
+        match self.order_request(request, 1).await? {
+            EventMessage::EditOrderStatus {
+                txid,
+                original_txid,
+                descr,
+                ..
+            } => Ok(EditOrderResult {
+                txid,
+                original_txid,
+                description: descr,
+            }),
+            other => Err(unexpected_reply(other)),
+        }
+    }
+
+    // Resolves once every order was canceled, or with the first error
+    pub async fn cancel_order(&self, txids: &[&str]) -> Result<(), Error> {
+        // Kraken would not answer a cancel for no orders at all
+        if txids.is_empty() {
+            return Err(Error::Order("no orders to cancel".to_string()));
+        }
+        let request = Request::CancelOrder {
+            token: None,
+            reqid: Some(self.next_reqid()),
+            txid: txids.iter().map(|txid| txid.to_string()).collect(),
+        };
+        self.order_request(request, txids.len()).await.map(|_| ())
+    }
+
+    // Cancels every open order and returns how many there were
+    pub async fn cancel_all(&self) -> Result<u32, Error> {
+        let request = Request::CancelAll {
+            token: None,
+            reqid: Some(self.next_reqid()),
+        };
+        match self.order_request(request, 1).await? {
+            EventMessage::CancelAllStatus { count, .. } => Ok(count.unwrap_or_default()),
+            other => Err(unexpected_reply(other)),
+        }
+    }
+
+    // Fills arrive as `ClientEvent::OwnTrades`; needs an authenticated connection
+    pub async fn subscribe_own_trades(&self) -> Result<(), Error> {
+        self.subscribe(&[], Channel::OwnTrades).await
+    }
+
+    // Order changes arrive as `ClientEvent::OpenOrders`; needs an authenticated connection
+    pub async fn subscribe_open_orders(&self) -> Result<(), Error> {
+        self.subscribe(&[], Channel::OpenOrders).await
+    }
+
+    // Subscribes to the book channel for one pair using the given book's depth and precision
+    pub async fn subscribe_book(&self, pair: &str, book: OrderBook) -> Result<(), Error> {
+        let depth = book.depth();
+        self.books.write().unwrap().add_book(pair, book);
+        self.subscribe(&[pair], Channel::Book { depth }).await
+    }
+
+    // Individual orders for one pair, kept in `level3_books()`; needs an authenticated
+    // v2 connection
+    pub async fn subscribe_level3(&self, pair: &str, book: Level3Book) -> Result<(), Error> {
+        if self.protocol != Protocol::V2 {
+            return Err(Error::Unsupported("level3 channel over v1".to_string()));
+        }
+        let depth = book.depth();
+        self.level3_books
+            .write()
+            .unwrap()
+            .insert(pair.to_string(), book);
+        self.subscribe(&[pair], Channel::Level3 { depth }).await
+    }
+
+    pub async fn unsubscribe(&self, pairs: &[&str], channel: Channel) -> Result<(), Error> {
+        self.request(Request::Unsubscribe {
+            reqid: Some(self.next_reqid()),
+            pair: pairs.iter().map(|pair| pair.to_string()).collect(),
+            subscription: channel.subscription(),
+        })
+        .await
+    }
+
+    pub fn books(&self) -> RwLockReadGuard<'_, BookManager> {
+        self.books.read().unwrap()
+    }
+
+    pub fn level3_books(&self) -> RwLockReadGuard<'_, HashMap<String, Level3Book>> {
+        self.level3_books.read().unwrap()
+    }
+
+    pub fn tickers(&self) -> RwLockReadGuard<'_, TickerCache> {
+        self.tickers.read().unwrap()
+    }
+
+    pub fn candles(&self) -> RwLockReadGuard<'_, CandleStore> {
+        self.candles.read().unwrap()
+    }
+
+    pub fn spreads(&self) -> RwLockReadGuard<'_, SpreadHistory> {
+        self.spreads.read().unwrap()
+    }
+
+    // For the dead man's switch of another, authenticated client
+    pub fn book_watch(&self) -> BookWatch {
+        BookWatch::new(Arc::clone(&self.books))
+    }
+
+    // `None` unless `ClientConfig::dead_mans_switch` was set
+    pub fn dead_mans_switch(&self) -> Option<SwitchState> {
+        self.switch_state
+            .as_ref()
+            .map(|state| state.read().unwrap().clone())
+    }
+
+    // State of every subscription, including the channel IDs Kraken assigned
+    pub fn subscriptions(&self) -> RwLockReadGuard<'_, SubscriptionRegistry> {
+        self.subscriptions.read().unwrap()
+    }
+
+    fn next_reqid(&self) -> u64 {
+        self.next_reqid.fetch_add(1, Ordering::Relaxed)
+    }
+
+    // Queues the request and waits for the connection task to resolve it
+    async fn request(&self, request: Request) -> Result<(), Error> {
+        let (responder, response) = oneshot::channel();
+        self.send(request, Reply::Subscription(responder))?;
+        // A dropped responder means the connection task is gone
+        response.await.unwrap_or(Err(Error::ConnectionClosed))
+    }
+
+    // Like `request`, for order requests answered by `expected` status events
+    async fn order_request(
+        &self,
+        request: Request,
+        expected: usize,
+    ) -> Result<EventMessage, Error> {
+        let (responder, response) = oneshot::channel();
+        self.send(
+            request,
+            Reply::Order {
+                expected,
+                responder,
+            },
+        )?;
+        response.await.unwrap_or(Err(Error::ConnectionClosed))
+    }
+
+    fn send(&self, request: Request, reply: Reply) -> Result<(), Error> {
+        // Requests the connection could not translate fail here rather than on the socket
+        if self.protocol == Protocol::V2 {
+            v2::encode(&request)?;
+        }
+        self.requests
+            .send(Command {
+                request,
+                reply: Some(reply),
+            })
+            .map_err(|_| Error::ConnectionClosed)
+    }
+}
+
+fn unexpected_reply(event: EventMessage) -> Error {
+    Error::Order(format!("unexpected reply: {:?}", event))
+}
+
+impl Stream for KrakenWsClient {
+    type Item = Result<ClientEvent, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.events.poll_recv(cx)
+    }
+}
+
+// The spot session, run by a `Driver` that owns the socket
+struct Connection {
+    // WebSockets token for private subscriptions
+    token: Option<String>,
+    next_reqid: Arc<AtomicU64>,
+    events: mpsc::UnboundedSender<Result<ClientEvent, Error>>,
+    books: Arc<RwLock<BookManager>>,
+    level3_books: Arc<RwLock<HashMap<String, Level3Book>>>,
+    tickers: Arc<RwLock<TickerCache>>,
+    candles: Arc<RwLock<CandleStore>>,
+    spreads: Arc<RwLock<SpreadHistory>>,
+    drift_filter: DriftFilter,
+    // Also the source of the subscriptions replayed after a reconnect
+    subscriptions: Arc<RwLock<SubscriptionRegistry>>,
+    order_replies: PendingReplies,
+    dead_mans_switch: Option<DeadMansSwitch>,
+    // Set when the connection speaks v2
+    decoder: Option<v2::Decoder>,
+}
+
+impl Session for Connection {
+    type Command = Command;
+
+    fn command(&mut self, command: Command, link: &mut Link) -> Result<(), Error> {
+        self.track_request(&command.request, command.reply);
+        self.queue(link, &command.request)
+    }
+
+    fn text(&mut self, text: &str, link: &mut Link) -> Result<(), Error> {
+        let parsed = match &mut self.decoder {
+            Some(decoder) => decoder.decode(text),
+            None => serde_json::from_str::<WsMessage>(text).map(|message| vec![message]),
+        };
+        match parsed {
+            Ok(messages) => {
+                for message in messages {
+                    self.handle_message(message, link)?;
+                }
+            }
+            // A message we cannot parse is reported but does not end the connection
+            Err(e) => self.emit(Err(e.into())),
+        }
+        Ok(())
+    }
+
+    fn ping(&mut self, link: &mut Link) -> Result<(), Error> {
+        let reqid = self.next_reqid.fetch_add(1, Ordering::Relaxed);
+        link.liveness().ping_sent(reqid);
+        self.queue(link, &Request::Ping { reqid: Some(reqid) })
+    }
+
+    fn timer_interval(&self) -> Option<Duration> {
+        self.dead_mans_switch
+            .as_ref()
+            .map(DeadMansSwitch::refresh_interval)
+    }
+
+    // Refreshes the dead man's switch
+    fn timer(&mut self, link: &mut Link) -> Result<(), Error> {
+        let reqid = self.next_reqid.fetch_add(1, Ordering::Relaxed);
+        let refresh = self
+            .dead_mans_switch
+            .as_ref()
+            .and_then(|switch| switch.refresh(&self.books.read().unwrap(), reqid));
+        match refresh {
+            Some(request) => self.queue(link, &request),
+            None => Ok(()),
+        }
+    }
+
+    fn disconnected(&mut self, reason: &Error) {
+        self.emit(Ok(ClientEvent::Connection(ConnectionEvent::Disconnected {
+            reason: reason.to_string(),
+        })));
+        self.books.write().unwrap().reset_all();
+        for book in self.level3_books.write().unwrap().values_mut() {
+            book.reset();
+        }
+        // Whether these orders went through is unknown; `openOrders` will tell
+        self.order_replies.fail_all();
+        if let Some(switch) = &self.dead_mans_switch {
+            switch.disconnected();
+        }
+    }
+
+    fn reconnecting(&mut self, attempt: u32, delay: Duration) {
+        self.emit(Ok(ClientEvent::Connection(ConnectionEvent::Reconnecting {
+            attempt,
+            delay,
+        })));
+    }
+
+    fn report(&mut self, error: Error) {
+        self.emit(Err(error));
+    }
+
+    // Replaying again after a failed attempt sends the same requests
+    fn replay(&mut self, link: &mut Link) -> Result<(), Error> {
+        let requests = self.subscriptions.write().unwrap().replay_requests();
+        for request in requests {
+            self.queue(link, &request)?;
+        }
+        Ok(())
+    }
+
+    fn reconnected(&mut self) {
+        self.emit(Ok(ClientEvent::Connection(ConnectionEvent::Reconnected)));
+    }
+
+    fn abandoned(&self) -> bool {
+        self.events.is_closed()
+    }
+
+    fn closed(&mut self) {
+        self.subscriptions.write().unwrap().fail_pending();
+        self.order_replies.fail_all();
+    }
+}
+
+impl Connection {
+    fn queue(&self, link: &mut Link, request: &Request) -> Result<(), Error> {
+        let request = self.authorize(request);
+        let text = match self.decoder {
+            Some(_) => v2::encode(&request)?,
+            None => serde_json::to_string(&request)?,
+        };
+        link.send_text(text);
+        Ok(())
+    }
+
+    fn track_request(&mut self, request: &Request, reply: Option<Reply>) {
+        match (request, reply) {
+            (
+                Request::AddOrder {
+                    reqid: Some(reqid), ..
+                }
+                | Request::EditOrder {
+                    reqid: Some(reqid), ..
+                }
+                | Request::CancelOrder {
+                    reqid: Some(reqid), ..
+                }
+                | Request::CancelAll {
+                    reqid: Some(reqid), ..
+                },
+                Some(Reply::Order {
+                    expected,
+                    responder,
+                }),
+            ) => self.order_replies.insert(*reqid, expected, responder),
+            (request, Some(Reply::Subscription(responder))) => self
+                .subscriptions
+                .write()
+                .unwrap()
+                .register(request, Some(responder)),
+            (request, _) => self.subscriptions.write().unwrap().register(request, None),
+        }
+    }
+
+    // Private and level3 subscriptions and order requests carry the token, which is
+    // kept out of the registry
+    fn authorize(&self, request: &Request) -> Request {
+        let mut request = request.clone();
+        let Some(token) = &self.token else {
+            return request;
+        };
+        match &mut request {
+            Request::Subscribe { subscription, .. } | Request::Unsubscribe { subscription, .. } => {
+                if subscription.needs_token() {
+                    subscription.token = Some(token.clone());
+                }
+            }
+            Request::AddOrder { token: slot, .. }
+            | Request::EditOrder { token: slot, .. }
+            | Request::CancelOrder { token: slot, .. }
+            | Request::CancelAll { token: slot, .. }
+            | Request::CancelAllOrdersAfter { token: slot, .. } => *slot = Some(token.clone()),
+            Request::Ping { .. } => (),
+        }
+        request
+    }
+
+    fn handle_message(&mut self, message: WsMessage, link: &mut Link) -> Result<(), Error> {
+        match message {
+            WsMessage::Event(EventMessage::Pong { reqid: Some(reqid) }) => {
+                match link.liveness().pong_received(reqid) {
+                    Some(round_trip) => {
+                        self.emit(Ok(ClientEvent::Connection(ConnectionEvent::Latency {
+                            round_trip,
+                        })))
+                    }
+                    // Not one of our pings
+                    None => self.emit(Ok(ClientEvent::Event(EventMessage::Pong {
+                        reqid: Some(reqid),
+                    }))),
+                }
+            }
+            WsMessage::Event(event) => {
+                self.order_replies.resolve(&event);
+                if let Some(switch) = &self.dead_mans_switch {
+                    switch.handle_event(&event);
+                }
+                self.track_subscription(&event);
+                self.emit(Ok(ClientEvent::Event(event)));
+            }
+            WsMessage::Private(message) => match message.data {
+                PrivateData::OwnTrades(trades) => self.emit(Ok(ClientEvent::OwnTrades {
+                    trades,
+                    sequence: message.sequence,
+                })),
+                PrivateData::OpenOrders(orders) => self.emit(Ok(ClientEvent::OpenOrders {
+                    orders,
+                    sequence: message.sequence,
+                })),
+            },
+            WsMessage::Channel(message) => {
+                if let ChannelData::Level3Snapshot(_) | ChannelData::Level3Update(_) = message.data
+                {
+                    return self.handle_level3(message, link);
+                }
+                let book_event = self.books.write().unwrap().handle(&message);
+                match book_event {
+                    Some(BookEvent::ChecksumMismatch { pair, mismatch }) => {
+                        self.resync_book(&pair, link)?;
+                        self.emit(Ok(ClientEvent::Book(BookEvent::ChecksumMismatch {
+                            pair,
+                            mismatch,
+                        })));
+                    }
+                    Some(book_event) => self.emit(Ok(ClientEvent::Book(book_event))),
+                    None => self.handle_channel(message),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Level3 data is matched by symbol; v2 has no channel IDs
+    fn handle_level3(&mut self, message: ChannelMessage, link: &mut Link) -> Result<(), Error> {
+        let pair = message.pair;
+        let (event, depth) = {
+            let mut books = self.level3_books.write().unwrap();
+            let Some(book) = books.get_mut(&pair) else {
+                return Ok(());
+            };
+            let event = match &message.data {
+                ChannelData::Level3Snapshot(snapshot) => match book.apply_snapshot(snapshot) {
+                    Ok(()) => BookEvent::Initialized { pair },
+                    Err(mismatch) => BookEvent::ChecksumMismatch { pair, mismatch },
+                },
+                ChannelData::Level3Update(update) => {
+                    let was_synced = book.status() == BookStatus::Synced;
+                    match book.apply_update(update) {
+                        Ok(()) if was_synced => BookEvent::Updated { pair },
+                        Ok(()) => BookEvent::Stale { pair },
+                        Err(mismatch) => BookEvent::ChecksumMismatch { pair, mismatch },
+                    }
+                }
+                _ => return Ok(()),
+            };
+            (event, book.depth())
+        };
+
+        if let BookEvent::ChecksumMismatch { pair, .. } = &event {
+            self.resubscribe(pair, Channel::Level3 { depth }.subscription(), link)?;
+        }
+        self.emit(Ok(ClientEvent::Level3(event)));
+        Ok(())
+    }
+
+    // Non-book channel data, keyed by the pair it was subscribed under
+    fn handle_channel(&mut self, message: ChannelMessage) {
+        let pair = match self
+            .subscriptions
+            .read()
+            .unwrap()
+            .by_channel(message.channel_id)
+        {
+            Some(entry) => entry.pair.clone(),
+            None => message.pair.clone(),
+        };
+        if let (ChannelData::Ohlc(candle), Some(interval)) =
+            (&message.data, ohlc_channel_interval(&message.channel_name))
+        {
+            let closed = self
+                .candles
+                .write()
+                .unwrap()
+                .update(&pair, interval, candle.clone());
+            if let Some(candle) = closed {
+                self.emit(Ok(ClientEvent::CandleClosed {
+                    pair: pair.clone(),
+                    interval,
+                    candle,
+                }));
+            }
+            return self.emit(Ok(ClientEvent::Candle { pair, interval }));
+        }
+
+        match message.data {
+            ChannelData::Trade(trades) => self.emit(Ok(ClientEvent::Trades { pair, trades })),
+            ChannelData::Ticker(ticker) => {
+                self.tickers.write().unwrap().update(&pair, *ticker);
+                self.emit(Ok(ClientEvent::Ticker { pair }));
+            }
+            ChannelData::Spread(spread) => {
+                let drift = match self.books.read().unwrap().get(&pair) {
+                    Some(book) => check_spread(&spread, book),
+                    None => None,
+                };
+                let drift = self.drift_filter.observe(&pair, drift);
+                self.spreads.write().unwrap().push(&pair, spread);
+                self.emit(Ok(ClientEvent::Spread { pair: pair.clone() }));
+                if let Some(drift) = drift {
+                    self.emit(Ok(ClientEvent::SpreadDrift { pair, drift }));
+                }
+            }
+            _ => self.emit(Ok(ClientEvent::Channel(message))),
+        }
+    }
+
+    fn track_subscription(&mut self, event: &EventMessage) {
+        self.subscriptions.write().unwrap().handle_event(event);
+        if let EventMessage::SubscriptionStatus {
+            channel_id: Some(channel_id),
+            channel_name: Some(channel_name),
+            pair: Some(pair),
+            status,
+            ..
+        } = event
+        {
+            match (channel_name.as_str(), status.as_str()) {
+                (name, "subscribed") if name.starts_with("book") => self
+                    .books
+                    .write()
+                    .unwrap()
+                    .register_channel(*channel_id, pair),
+                (name, "unsubscribed") if name.starts_with("book") => {
+                    self.books.write().unwrap().unregister_channel(*channel_id)
+                }
+                // A ticker nobody updates any more should not look current
+                ("ticker", "unsubscribed") => {
+                    self.tickers.write().unwrap().remove(pair);
+                }
+                _ => (),
+            }
+        }
+    }
+
+    fn resync_book(&mut self, pair: &str, link: &mut Link) -> Result<(), Error> {
+        let depth = match self.books.read().unwrap().get(pair) {
+            Some(book) => book.depth(),
+            None => return Ok(()),
+        };
+        self.resubscribe(pair, Channel::Book { depth }.subscription(), link)
+    }
+
+    // Resubscribing makes Kraken send a fresh snapshot for the pair. Both requests
+    // go through the registry like the client's own, so their statuses are
+    // matched and a reconnect in between replays the right state.
+    fn resubscribe(
+        &mut self,
+        pair: &str,
+        subscription: Subscription,
+        link: &mut Link,
+    ) -> Result<(), Error> {
+        let requests = [
+            Request::Unsubscribe {
+                reqid: Some(self.next_reqid.fetch_add(1, Ordering::Relaxed)),
+                pair: vec![pair.to_string()],
+                subscription: subscription.clone(),
+            },
+            Request::Subscribe {
+                reqid: Some(self.next_reqid.fetch_add(1, Ordering::Relaxed)),
+                pair: vec![pair.to_string()],
+                subscription,
+            },
+        ];
+        for request in &requests {
+            self.track_request(request, None);
+            self.queue(link, request)?;
+        }
+        Ok(())
+    }
+
+    // Events are dropped once the client stops listening
+    fn emit(&self, event: Result<ClientEvent, Error>) {
+        let _ = self.events.send(event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::{OrderType, Side};
+    use crate::order_book::BookStatus;
+    use crate::private_messages::OrderStatus;
+    use crate::subscriptions::SubscriptionState;
+    use crate::test_support::{bind, next_json, send_json, subscription_status};
+    use futures_util::StreamExt;
+    use serde_json::json;
+
+    fn snapshot_frame() -> serde_json::Value {
+        json!([42, {
+            "as": [
+                ["5711.80000", "8.13439401", "1557070784.848047"],
+                ["5712.20000", "2.00000000", "1557070757.056750"],
+                ["5712.80000", "0.30000000", "1557070783.806432"],
+                ["5713.00000", "3.29800000", "1557070774.281619"],
+                ["5713.10000", "1.00000000", "1557070741.315583"],
+                ["5713.90000", "1.00000000", "1557070698.840502"],
+                ["5714.70000", "0.50000000", "1557070743.861074"],
+                ["5715.20000", "1.00000000", "1557070697.871150"],
+                ["5716.60000", "1.22700000", "1557070775.294557"],
+                ["5716.80000", "0.35000000", "1557070749.823148"]
+            ],
+            "bs": [
+                ["5711.70000", "0.00749800", "1557070712.848376"],
+                ["5709.20000", "3.30000000", "1557070766.260894"],
+                ["5708.30000", "0.75483907", "1557070781.425374"],
+                ["5708.20000", "5.00000000", "1557070780.762871"],
+                ["5707.80000", "2.50000000", "1557070722.912548"],
+                ["5707.40000", "4.33000000", "1557070732.546143"],
+                ["5707.00000", "0.00200000", "1557070604.962840"],
+                ["5706.90000", "1.17300000", "1557070715.529722"],
+                ["5706.40000", "0.85600000", "1557070777.204262"],
+                ["5706.30000", "1.00000000", "1557070753.118938"]
+            ]
+        }, "book-10", "XBT/USD"])
+    }
+
+    fn update_frame(checksum: &str) -> serde_json::Value {
+        json!([42,
+            {"a": [["5711.80000", "0.00000000", "1557070786.512345"], ["5712.50000", "1.25000000", "1557070786.512345"]]},
+            {"b": [["5711.70000", "0.50000000", "1557070786.512399"], ["5711.75000", "0.10000000", "1557070786.512399"]], "c": checksum},
+            "book-10", "XBT/USD"])
+    }
+
+    async fn next_event(client: &mut KrakenWsClient) -> ClientEvent {
+        client.next().await.unwrap().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_client_streams_typed_events() {
+        let (url, listener) = bind().await;
+        let server = tokio::spawn(async move {
+            let mut ws = crate::test_support::accept(&listener).await;
+            let subscribe = next_json(&mut ws).await;
+            assert_eq!(
+                subscribe,
+                json!({"event": "subscribe", "reqid": 1, "pair": ["XBT/USD"], "subscription": {"name": "book", "depth": 10}})
+            );
+            send_json(&mut ws, subscription_status(&subscribe, "subscribed", 42)).await;
+            send_json(&mut ws, snapshot_frame()).await;
+            send_json(&mut ws, update_frame("3470003306")).await;
+            send_json(
+                &mut ws,
+                json!([
+                    7,
+                    [[
+                        "5541.20000",
+                        "0.15850568",
+                        "1534614057.321597",
+                        "s",
+                        "l",
+                        ""
+                    ]],
+                    "trade",
+                    "XBT/USD"
+                ]),
+            )
+            .await;
+            send_json(&mut ws, json!({"event": "heartbeat"})).await;
+            ws
+        });
+
+        let mut client = KrakenWsClient::connect(&url).await.unwrap();
+        client
+            .subscribe(&["XBT/USD"], Channel::Book { depth: 10 })
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            next_event(&mut client).await,
+            ClientEvent::Event(EventMessage::SubscriptionStatus { .. })
+        ));
+        assert_eq!(
+            next_event(&mut client).await,
+            ClientEvent::Book(BookEvent::Initialized {
+                pair: "XBT/USD".to_string()
+            })
+        );
+        assert_eq!(
+            next_event(&mut client).await,
+            ClientEvent::Book(BookEvent::Updated {
+                pair: "XBT/USD".to_string()
+            })
+        );
+        match next_event(&mut client).await {
+            ClientEvent::Trades { pair, trades } => {
+                assert_eq!(pair, "XBT/USD");
+                assert_eq!(trades.len(), 1);
+                assert_eq!(trades[0].price, "5541.2".parse().unwrap());
+                assert_eq!(trades[0].volume, "0.15850568".parse().unwrap());
+                assert_eq!(trades[0].side, Side::Sell);
+            }
+            other => panic!("expected trades, got {:?}", other),
+        }
+        assert_eq!(
+            next_event(&mut client).await,
+            ClientEvent::Event(EventMessage::Heartbeat)
+        );
+
+        {
+            let books = client.books();
+            let book = books.get("XBT/USD").unwrap();
+            assert_eq!(book.status(), BookStatus::Synced);
+            assert_eq!(book.asks().next().unwrap().price, "5712.2".parse().unwrap());
+        }
+
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_client_routes_trades_by_channel_id() {
+        let (url, listener) = bind().await;
+        let server = tokio::spawn(async move {
+            let mut ws = crate::test_support::accept(&listener).await;
+            let subscribe = next_json(&mut ws).await;
+            assert_eq!(subscribe["subscription"], json!({"name": "trade"}));
+            send_json(&mut ws, subscription_status(&subscribe, "subscribed", 9)).await;
+            // The frame names the pair differently than the subscription did
+            send_json(
+                &mut ws,
+                json!([
+                    9,
+                    [
+                        [
+                            "5541.20000",
+                            "0.15850568",
+                            "1534614057.321597",
+                            "s",
+                            "l",
+                            ""
+                        ],
+                        [
+                            "6060.00000",
+                            "0.02455000",
+                            "1534614057.324998",
+                            "b",
+                            "m",
+                            ""
+                        ]
+                    ],
+                    "trade",
+                    "XXBTZUSD"
+                ]),
+            )
+            .await;
+            ws
+        });
+
+        let mut client = KrakenWsClient::connect(&url).await.unwrap();
+        client.subscribe_trades(&["XBT/USD"]).await.unwrap();
+        next_event(&mut client).await;
+
+        match next_event(&mut client).await {
+            ClientEvent::Trades { pair, trades } => {
+                assert_eq!(pair, "XBT/USD");
+                assert_eq!(
+                    trades.iter().map(|trade| trade.side).collect::<Vec<_>>(),
+                    vec![Side::Sell, Side::Buy]
+                );
+                assert_eq!(trades[1].price, "6060".parse().unwrap());
+                assert_eq!(trades[1].order_type, OrderType::Market);
+            }
+            other => panic!("expected trades, got {:?}", other),
+        }
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_client_caches_tickers() {
+        let (url, listener) = bind().await;
+        let server = tokio::spawn(async move {
+            let mut ws = crate::test_support::accept(&listener).await;
+            let subscribe = next_json(&mut ws).await;
+            assert_eq!(subscribe["subscription"], json!({"name": "ticker"}));
+            send_json(&mut ws, subscription_status(&subscribe, "subscribed", 340)).await;
+            send_json(
+                &mut ws,
+                json!([340, {
+                    "a": ["5525.40000", 1, "1.000"],
+                    "b": ["5525.10000", 1, "1.000"],
+                    "c": ["5525.10000", "0.00398963"],
+                    "v": ["2634.11501494", "3591.17907851"],
+                    "p": ["5631.44067", "5653.78939"],
+                    "t": [11493, 16267],
+                    "l": ["5505.00000", "5505.00000"],
+                    "h": ["5783.00000", "5783.00000"],
+                    "o": ["5760.70000", "5763.40000"]
+                }, "ticker", "XBT/USD"]),
+            )
+            .await;
+            ws
+        });
+
+        let mut client = KrakenWsClient::connect(&url).await.unwrap();
+        client.subscribe_ticker(&["XBT/USD"]).await.unwrap();
+        assert!(client.tickers().get("XBT/USD").is_none());
+        next_event(&mut client).await;
+
+        assert_eq!(
+            next_event(&mut client).await,
+            ClientEvent::Ticker {
+                pair: "XBT/USD".to_string()
+            }
+        );
+        {
+            let tickers = client.tickers();
+            let ticker = tickers.get("XBT/USD").unwrap();
+            assert_eq!(ticker.ask.price, "5525.4".parse().unwrap());
+            assert_eq!(ticker.bid.whole_lot_volume, 1);
+            assert_eq!(ticker.vwap.today, "5631.44067".parse().unwrap());
+            assert_eq!(ticker.trades.today, 11493);
+            assert_eq!(ticker.open.last_24h, "5763.4".parse().unwrap());
+        }
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_client_builds_candles() {
+        let (url, listener) = bind().await;
+        let server = tokio::spawn(async move {
+            let mut ws = crate::test_support::accept(&listener).await;
+            let subscribe = next_json(&mut ws).await;
+            assert_eq!(
+                subscribe["subscription"],
+                json!({"name": "ohlc", "interval": 5})
+            );
+            send_json(&mut ws, subscription_status(&subscribe, "subscribed", 21)).await;
+            for (time, end_time, close) in [
+                ("1542057314.748456", "1542057600.000000", "3586.70000"),
+                ("1542057360.435743", "1542057600.000000", "3586.60000"),
+                ("1542057601.120000", "1542057900.000000", "3587.00000"),
+            ] {
+                send_json(
+                    &mut ws,
+                    json!([
+                        21,
+                        [
+                            time,
+                            end_time,
+                            "3586.70000",
+                            "3586.70000",
+                            "3586.60000",
+                            close,
+                            "3586.68894",
+                            "0.03373000",
+                            2
+                        ],
+                        "ohlc-5",
+                        "XBT/USD"
+                    ]),
+                )
+                .await;
+            }
+            ws
+        });
+
+        let mut client = KrakenWsClient::connect(&url).await.unwrap();
+        // Kraken would reject the interval; nothing is sent or tracked for it
+        assert!(matches!(
+            client.subscribe_ohlc(&["XBT/USD"], 7).await,
+            Err(Error::Config(_))
+        ));
+        assert!(client.subscriptions().entries().next().is_none());
+        client.subscribe_ohlc(&["XBT/USD"], 5).await.unwrap();
+        next_event(&mut client).await;
+
+        let updated = ClientEvent::Candle {
+            pair: "XBT/USD".to_string(),
+            interval: 5,
+        };
+        assert_eq!(next_event(&mut client).await, updated);
+        assert_eq!(next_event(&mut client).await, updated);
+        match next_event(&mut client).await {
+            ClientEvent::CandleClosed {
+                pair,
+                interval,
+                candle,
+            } => {
+                assert_eq!((pair.as_str(), interval), ("XBT/USD", 5));
+                assert_eq!(candle.close, "3586.6".parse().unwrap());
+            }
+            other => panic!("expected a closed candle, got {:?}", other),
+        }
+        assert_eq!(next_event(&mut client).await, updated);
+
+        {
+            let candles = client.candles();
+            assert_eq!(candles.history("XBT/USD", 5).count(), 1);
+            assert_eq!(
+                candles.open_candle("XBT/USD", 5).unwrap().close,
+                "3587".parse().unwrap()
+            );
+        }
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_client_checks_spreads_against_book() {
+        let (url, listener) = bind().await;
+        let server = tokio::spawn(async move {
+            let mut ws = crate::test_support::accept(&listener).await;
+            let book = next_json(&mut ws).await;
+            send_json(&mut ws, subscription_status(&book, "subscribed", 42)).await;
+            let spread = next_json(&mut ws).await;
+            assert_eq!(spread["subscription"], json!({"name": "spread"}));
+            send_json(&mut ws, subscription_status(&spread, "subscribed", 43)).await;
+
+            send_json(&mut ws, snapshot_frame()).await;
+            // Matches the snapshot's best bid and ask, disagrees once, then keeps disagreeing
+            for ask in ["5711.80000", "5712.20000", "5711.80000"]
+                .into_iter()
+                .chain(["5712.20000"; 3])
+            {
+                send_json(
+                    &mut ws,
+                    json!([
+                        43,
+                        [
+                            "5711.70000",
+                            ask,
+                            "1557070786.004100",
+                            "0.00749800",
+                            "2.00000000"
+                        ],
+                        "spread",
+                        "XBT/USD"
+                    ]),
+                )
+                .await;
+            }
+            ws
+        });
+
+        let mut client = KrakenWsClient::connect(&url).await.unwrap();
+        client
+            .subscribe_book("XBT/USD", OrderBook::new(10))
+            .await
+            .unwrap();
+        client.subscribe_spread(&["XBT/USD"]).await.unwrap();
+        for _ in 0..3 {
+            // Two subscription statuses and the snapshot
+            next_event(&mut client).await;
+        }
+
+        let spread_event = ClientEvent::Spread {
+            pair: "XBT/USD".to_string(),
+        };
+        // The one-off disagreement is not reported
+        for _ in 0..6 {
+            assert_eq!(next_event(&mut client).await, spread_event);
+        }
+        match next_event(&mut client).await {
+            ClientEvent::SpreadDrift { pair, drift } => {
+                assert_eq!(pair, "XBT/USD");
+                assert_eq!(drift.spread_ask, "5712.2".parse().unwrap());
+                assert_eq!(drift.book_ask, Some("5711.8".parse().unwrap()));
+            }
+            other => panic!("expected a drift, got {:?}", other),
+        }
+        assert_eq!(client.spreads().history("XBT/USD").count(), 6);
+        assert_eq!(
+            client.spreads().latest("XBT/USD").unwrap().ask,
+            "5712.2".parse().unwrap()
+        );
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_client_private_feeds() {
+        let (url, listener) = bind().await;
+        let server = tokio::spawn(async move {
+            let mut ws = crate::test_support::accept(&listener).await;
+            for name in ["ownTrades", "openOrders"] {
+                let subscribe = next_json(&mut ws).await;
+                assert_eq!(
+                    subscribe["subscription"],
+                    json!({"name": name, "token": "secret-token"})
+                );
+                assert!(subscribe.get("pair").is_none());
+                send_json(
+                    &mut ws,
+                    json!({"channelName": name, "event": "subscriptionStatus", "reqid": subscribe["reqid"], "status": "subscribed", "subscription": {"name": name}}),
+                )
+                .await;
+            }
+            // Recorded private frames
+            send_json(
+                &mut ws,
+                json!([[{"TDLH43-DVQXD-2KHVYY": {"cost": "1000000.00000", "fee": "1600.00000", "margin": "0.00000", "ordertxid": "TDLH43-DVQXD-2KHVYY", "ordertype": "limit", "pair": "XBT/EUR", "postxid": "OGTT3Y-C6I3P-XRI6HX", "price": "100000.00000", "time": "1560516023.070651", "type": "sell", "vol": "1000000000.00000000"}}], "ownTrades", {"sequence": 2}]),
+            )
+            .await;
+            send_json(
+                &mut ws,
+                json!([[{"OGTT3Y-C6I3P-XRI6HX": {"status": "closed", "cost": "1000000.00000", "vol_exec": "1000000000.00000000", "fee": "1600.00000", "avg_price": "100000.00000", "userref": 0}}], "openOrders", {"sequence": 59342}]),
+            )
+            .await;
+            ws
+        });
+
+        let mut client =
+            KrakenWsClient::connect_authenticated(&url, "secret-token", ClientConfig::default())
+                .await
+                .unwrap();
+        client.subscribe_own_trades().await.unwrap();
+        client.subscribe_open_orders().await.unwrap();
+        assert_eq!(
+            client
+                .subscriptions()
+                .get("", &Channel::OwnTrades.subscription())
+                .unwrap()
+                .state,
+            SubscriptionState::Subscribed
+        );
+        next_event(&mut client).await;
+        next_event(&mut client).await;
+
+        match next_event(&mut client).await {
+            ClientEvent::OwnTrades { trades, sequence } => {
+                assert_eq!(sequence, Some(2));
+                assert_eq!(trades[0].trade_id, "TDLH43-DVQXD-2KHVYY");
+                assert_eq!(trades[0].price, "100000".parse().unwrap());
+                assert_eq!(trades[0].side, Side::Sell);
+            }
+            other => panic!("expected fills, got {:?}", other),
+        }
+        match next_event(&mut client).await {
+            ClientEvent::OpenOrders { orders, sequence } => {
+                assert_eq!(sequence, Some(59342));
+                assert_eq!(orders[0].order_id, "OGTT3Y-C6I3P-XRI6HX");
+                assert_eq!(orders[0].status, Some(OrderStatus::Closed));
+                assert_eq!(orders[0].userref, Some(0));
+            }
+            other => panic!("expected order updates, got {:?}", other),
+        }
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_client_order_entry() {
+        let (url, listener) = bind().await;
+        let server = tokio::spawn(async move {
+            let mut ws = crate::test_support::accept(&listener).await;
+            let add = next_json(&mut ws).await;
+            assert_eq!(
+                add,
+                json!({"event": "addOrder", "token": "secret-token", "reqid": add["reqid"], "ordertype": "limit", "type": "buy", "pair": "XBT/USD", "volume": "0.0177", "price": "4000", "oflags": "post"})
+            );
+            // Replies are matched by reqid, not by order of arrival
+            let rejected = next_json(&mut ws).await;
+            send_json(
+                &mut ws,
+                json!({"errorMessage": "EOrder:Order minimum not met", "event": "addOrderStatus", "status": "error", "reqid": rejected["reqid"]}),
+            )
+            .await;
+            send_json(
+                &mut ws,
+                json!({"descr": "buy 0.01770000 XBTUSD @ limit 4000", "event": "addOrderStatus", "status": "ok", "txid": "ONPNXH-KMKMU-F4MR5V", "reqid": add["reqid"]}),
+            )
+            .await;
+
+            let edit = next_json(&mut ws).await;
+            assert_eq!(edit["event"], "editOrder");
+            assert_eq!(edit["orderid"], "ONPNXH-KMKMU-F4MR5V");
+            send_json(
+                &mut ws,
+                json!({"event": "editOrderStatus", "status": "ok", "txid": "OFMKJO-7IKKJ-6KAOSV", "originaltxid": "ONPNXH-KMKMU-F4MR5V", "reqid": edit["reqid"]}),
+            )
+            .await;
+
+            let cancel = next_json(&mut ws).await;
+            assert_eq!(
+                cancel["txid"],
+                json!(["OFMKJO-7IKKJ-6KAOSV", "OGTT3Y-C6I3P-XRI6HX"])
+            );
+            for _ in 0..2 {
+                send_json(
+                    &mut ws,
+                    json!({"event": "cancelOrderStatus", "status": "ok", "reqid": cancel["reqid"]}),
+                )
+                .await;
+            }
+
+            let cancel_all = next_json(&mut ws).await;
+            assert_eq!(cancel_all["token"], "secret-token");
+            send_json(
+                &mut ws,
+                json!({"event": "cancelAllStatus", "count": 2, "status": "ok", "reqid": cancel_all["reqid"]}),
+            )
+            .await;
+            ws
+        });
+
+        let client = Arc::new(
+            KrakenWsClient::connect_authenticated(&url, "secret-token", ClientConfig::default())
+                .await
+                .unwrap(),
+        );
+        let mut order = AddOrder::limit(
+            Side::Buy,
+            "XBT/USD",
+            "0.0177".parse().unwrap(),
+            "4000".parse().unwrap(),
+        );
+        order.oflags = vec![orders::OrderFlag::Post];
+        let placed = tokio::spawn({
+            let client = Arc::clone(&client);
+            async move { client.add_order(order).await }
+        });
+        // Make sure the first order is sent first
+        while client.next_reqid.load(Ordering::Relaxed) == 1 {
+            tokio::task::yield_now().await;
+        }
+        let tiny = AddOrder::market(Side::Sell, "XBT/USD", "0.00001".parse().unwrap());
+        match client.add_order(tiny).await {
+            Err(Error::Order(message)) => assert_eq!(message, "EOrder:Order minimum not met"),
+            other => panic!("expected a rejected order, got {:?}", other),
+        }
+        let placed = placed.await.unwrap().unwrap();
+        assert_eq!(placed.txid.as_deref(), Some("ONPNXH-KMKMU-F4MR5V"));
+        assert_eq!(
+            placed.description.as_deref(),
+            Some("buy 0.01770000 XBTUSD @ limit 4000")
+        );
+
+        let mut edit = EditOrder::new("ONPNXH-KMKMU-F4MR5V", "XBT/USD");
+        edit.price = Some("4100".parse().unwrap());
+        let edited = client.edit_order(edit).await.unwrap();
+        assert_eq!(edited.txid.as_deref(), Some("OFMKJO-7IKKJ-6KAOSV"));
+        assert_eq!(edited.original_txid.as_deref(), Some("ONPNXH-KMKMU-F4MR5V"));
+
+        // Refused without sending anything; the server sees the next cancel first
+        let empty = tokio::time::timeout(Duration::from_secs(1), client.cancel_order(&[]));
+        assert!(matches!(empty.await.unwrap(), Err(Error::Order(_))));
+        client
+            .cancel_order(&["OFMKJO-7IKKJ-6KAOSV", "OGTT3Y-C6I3P-XRI6HX"])
+            .await
+            .unwrap();
+        assert_eq!(client.cancel_all().await.unwrap(), 2);
+        server.await.unwrap();
+    }
+
+    // Polls until `condition` holds, failing the test after a second
+    async fn wait_for(mut condition: impl FnMut() -> bool) {
+        tokio::time::timeout(Duration::from_secs(1), async {
+            while !condition() {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_client_dead_mans_switch_follows_book_health() {
+        let (url, listener) = bind().await;
+        let refreshes = Arc::new(AtomicU64::new(0));
+        let server = tokio::spawn({
+            let refreshes = Arc::clone(&refreshes);
+            async move {
+                let mut ws = crate::test_support::accept(&listener).await;
+                loop {
+                    let refresh = next_json(&mut ws).await;
+                    assert_eq!(
+                        refresh,
+                        json!({"event": "cancelAllOrdersAfter", "token": "secret-token", "reqid": refresh["reqid"], "timeout": 60})
+                    );
+                    refreshes.fetch_add(1, Ordering::SeqCst);
+                    send_json(
+                        &mut ws,
+                        json!({"event": "cancelAllOrdersAfterStatus", "currentTime": "2020-12-21T09:37:09Z", "reqid": refresh["reqid"], "status": "ok", "triggerTime": "2020-12-21T09:38:09Z"}),
+                    )
+                    .await;
+                }
+            }
+        });
+
+        // The strategy trades from a book kept by another client
+        let watched = Arc::new(RwLock::new(BookManager::new()));
+        let snapshot: ChannelMessage = serde_json::from_value(snapshot_frame()).unwrap();
+        {
+            let mut books = watched.write().unwrap();
+            books.add_book("XBT/USD", OrderBook::new(10));
+            books.handle(&snapshot);
+        }
+        let mut switch = DeadMansSwitchConfig::new(Duration::from_secs(60));
+        switch.refresh_interval = Duration::from_millis(30);
+        switch.watched_books = Some(BookWatch::new(Arc::clone(&watched)));
+        let config = ClientConfig {
+            dead_mans_switch: Some(switch),
+            ..ClientConfig::default()
+        };
+        let client = KrakenWsClient::connect_authenticated(&url, "secret-token", config)
+            .await
+            .unwrap();
+
+        wait_for(|| client.dead_mans_switch().unwrap().armed).await;
+        let state = client.dead_mans_switch().unwrap();
+        assert!(state.refreshing);
+        assert_eq!(state.trigger_time.as_deref(), Some("2020-12-21T09:38:09Z"));
+
+        // A stale book holds refreshes back until it is synced again
+        watched.write().unwrap().reset_all();
+        wait_for(|| !client.dead_mans_switch().unwrap().refreshing).await;
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let sent = refreshes.load(Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(refreshes.load(Ordering::SeqCst), sent);
+
+        watched.write().unwrap().handle(&snapshot);
+        wait_for(|| refreshes.load(Ordering::SeqCst) > sent).await;
+        assert!(client.dead_mans_switch().unwrap().refreshing);
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn test_client_speaks_v2() {
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(b"45285210000045284015000000045283510000000");
+        let checksum = hasher.finalize();
+
+        let (url, listener) = bind().await;
+        let server = tokio::spawn(async move {
+            let mut ws = crate::test_support::accept(&listener).await;
+            let subscribe = next_json(&mut ws).await;
+            assert_eq!(
+                subscribe,
+                json!({"method": "subscribe", "params": {"channel": "book", "symbol": ["BTC/USD"], "depth": 10}, "req_id": subscribe["req_id"]})
+            );
+            send_json(
+                &mut ws,
+                json!({"method": "subscribe", "result": {"channel": "book", "depth": 10, "snapshot": true, "symbol": "BTC/USD"}, "success": true, "time_in": "2023-09-25T09:04:31.742599Z", "time_out": "2023-09-25T09:04:31.742648Z", "req_id": subscribe["req_id"]}),
+            )
+            .await;
+            send_json(
+                &mut ws,
+                json!({"channel": "book", "type": "snapshot", "data": [{"symbol": "BTC/USD", "bids": [{"price": 45283.5, "qty": 0.1}], "asks": [{"price": 45285.2, "qty": 0.001}]}]}),
+            )
+            .await;
+            send_json(
+                &mut ws,
+                json!({"channel": "book", "type": "update", "data": [{"symbol": "BTC/USD", "bids": [{"price": 45284.0, "qty": 1.5}], "asks": [], "checksum": checksum, "timestamp": "2023-10-06T17:35:55.440295Z"}]}),
+            )
+            .await;
+            send_json(
+                &mut ws,
+                json!({"channel": "book", "type": "update", "data": [{"symbol": "BTC/USD", "bids": [], "asks": [{"price": 45285.2, "qty": 0.0}], "checksum": checksum, "timestamp": "2023-10-06T17:35:56.440295Z"}]}),
+            )
+            .await;
+
+            // The stale book is resubscribed in v2 form
+            let unsubscribe = next_json(&mut ws).await;
+            assert_eq!(
+                unsubscribe,
+                json!({"method": "unsubscribe", "params": {"channel": "book", "symbol": ["BTC/USD"], "depth": 10}, "req_id": unsubscribe["req_id"]})
+            );
+            assert!(unsubscribe["req_id"].as_u64() > subscribe["req_id"].as_u64());
+            assert_eq!(next_json(&mut ws).await["method"], "subscribe");
+            ws
+        });
+
+        let config = ClientConfig {
+            protocol: Protocol::V2,
+            ..ClientConfig::default()
+        };
+        let mut client = KrakenWsClient::connect_with_config(&url, config)
+            .await
+            .unwrap();
+        // A book without the pair's precision would fail every v2 checksum
+        assert!(matches!(
+            client
+                .subscribe(&["BTC/USD"], Channel::Book { depth: 10 })
+                .await,
+            Err(Error::Config(_))
+        ));
+        assert!(client.books().get("BTC/USD").is_none());
+        // BTC/USD has 1 price and 8 quantity decimals
+        client
+            .subscribe_book("BTC/USD", OrderBook::with_precision(10, 1, 8))
+            .await
+            .unwrap();
+        for unsupported in [
+            client.subscribe_spread(&["BTC/USD"]).await,
+            client.subscribe_ticker(&["BTC/USD"]).await,
+            client.subscribe_ohlc(&["BTC/USD"], 1).await,
+            client.subscribe_own_trades().await,
+            client.cancel_order(&["OFMKJO-7IKKJ-6KAOSV"]).await,
+        ] {
+            assert!(matches!(unsupported, Err(Error::Unsupported(_))));
+        }
+
+        match next_event(&mut client).await {
+            ClientEvent::Event(EventMessage::SubscriptionStatus { status, .. }) => {
+                assert_eq!(status, "subscribed")
+            }
+            other => panic!("expected a subscription status, got {:?}", other),
+        }
+        assert_eq!(
+            next_event(&mut client).await,
+            ClientEvent::Book(BookEvent::Initialized {
+                pair: "BTC/USD".to_string()
+            })
+        );
+        assert_eq!(
+            next_event(&mut client).await,
+            ClientEvent::Book(BookEvent::Updated {
+                pair: "BTC/USD".to_string()
+            })
+        );
+        assert_eq!(
+            client
+                .books()
+                .get("BTC/USD")
+                .unwrap()
+                .bids()
+                .next()
+                .unwrap()
+                .volume,
+            "1.5".parse().unwrap()
+        );
+        assert!(matches!(
+            next_event(&mut client).await,
+            ClientEvent::Book(BookEvent::ChecksumMismatch { .. })
+        ));
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_client_level3_book() {
+        // BID-1 is partially filled, so ours is second in line
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(b"45285250000000452835500000045283530000000");
+        let checksum = hasher.finalize();
+
+        let (url, listener) = bind().await;
+        let server = tokio::spawn(async move {
+            let mut ws = crate::test_support::accept(&listener).await;
+            let subscribe = next_json(&mut ws).await;
+            assert_eq!(
+                subscribe,
+                json!({"method": "subscribe", "params": {"channel": "level3", "symbol": ["BTC/USD"], "depth": 10, "token": "secret-token"}, "req_id": subscribe["req_id"]})
+            );
+            send_json(
+                &mut ws,
+                json!({"method": "subscribe", "result": {"channel": "level3", "depth": 10, "snapshot": true, "symbol": "BTC/USD"}, "success": true, "req_id": subscribe["req_id"]}),
+            )
+            .await;
+            send_json(
+                &mut ws,
+                json!({"channel": "level3", "type": "snapshot", "data": [{"symbol": "BTC/USD",
+                    "bids": [
+                        {"order_id": "BID-1", "limit_price": 45283.5, "order_qty": 0.1, "timestamp": "2023-12-08T09:00:00.000000Z"},
+                        {"order_id": "MINE", "limit_price": 45283.5, "order_qty": 0.3, "timestamp": "2023-12-08T09:00:01.000000Z"}
+                    ],
+                    "asks": [{"order_id": "ASK-1", "limit_price": 45285.2, "order_qty": 0.5, "timestamp": "2023-12-08T09:00:00.000000Z"}]}]}),
+            )
+            .await;
+            send_json(
+                &mut ws,
+                json!({"channel": "level3", "type": "update", "data": [{"symbol": "BTC/USD", "checksum": checksum, "asks": [],
+                    "bids": [{"event": "modify", "order_id": "BID-1", "limit_price": 45283.5, "order_qty": 0.05, "timestamp": "2023-12-08T09:00:02.000000Z"}]}]}),
+            )
+            .await;
+            ws
+        });
+
+        let config = ClientConfig {
+            protocol: Protocol::V2,
+            ..ClientConfig::default()
+        };
+        let mut client = KrakenWsClient::connect_authenticated(&url, "secret-token", config)
+            .await
+            .unwrap();
+        client
+            .subscribe_level3("BTC/USD", Level3Book::new(10, 1, 8))
+            .await
+            .unwrap();
+        next_event(&mut client).await;
+        assert_eq!(
+            next_event(&mut client).await,
+            ClientEvent::Level3(BookEvent::Initialized {
+                pair: "BTC/USD".to_string()
+            })
+        );
+        assert_eq!(
+            next_event(&mut client).await,
+            ClientEvent::Level3(BookEvent::Updated {
+                pair: "BTC/USD".to_string()
+            })
+        );
+
+        {
+            let books = client.level3_books();
+            let position = books["BTC/USD"].queue_position("MINE").unwrap();
+            assert_eq!(position.orders_ahead, 1);
+            assert_eq!(position.volume_ahead, "0.05".parse().unwrap());
+            let aggregated = books["BTC/USD"].to_order_book();
+            assert_eq!(
+                aggregated.bids().next().unwrap().volume,
+                "0.35".parse().unwrap()
+            );
+        }
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_client_resubscribes_on_checksum_mismatch() {
+        let (url, listener) = bind().await;
+        let server = tokio::spawn(async move {
+            let mut ws = crate::test_support::accept(&listener).await;
+            let subscribe = next_json(&mut ws).await;
+            send_json(&mut ws, subscription_status(&subscribe, "subscribed", 42)).await;
+            send_json(&mut ws, snapshot_frame()).await;
+            send_json(&mut ws, update_frame("1")).await;
+
+            let unsubscribe = next_json(&mut ws).await;
+            assert_eq!(unsubscribe["event"], "unsubscribe");
+            assert_eq!(unsubscribe["pair"], json!(["XBT/USD"]));
+            let resubscribe = next_json(&mut ws).await;
+            assert_eq!(resubscribe["event"], "subscribe");
+            assert_eq!(
+                resubscribe["subscription"],
+                json!({"name": "book", "depth": 10})
+            );
+            // Both carry fresh reqids, answered like any other request
+            assert!(unsubscribe["reqid"].as_u64() > subscribe["reqid"].as_u64());
+            assert!(resubscribe["reqid"].as_u64() > unsubscribe["reqid"].as_u64());
+            send_json(
+                &mut ws,
+                subscription_status(&unsubscribe, "unsubscribed", 42),
+            )
+            .await;
+            send_json(&mut ws, subscription_status(&resubscribe, "subscribed", 43)).await;
+
+            let mut snapshot = snapshot_frame();
+            snapshot[0] = json!(43);
+            send_json(&mut ws, snapshot).await;
+            ws
+        });
+
+        let mut client = KrakenWsClient::connect(&url).await.unwrap();
+        client
+            .subscribe_book("XBT/USD", OrderBook::new(10))
+            .await
+            .unwrap();
+
+        // Subscription status, then the snapshot
+        next_event(&mut client).await;
+        next_event(&mut client).await;
+        assert!(matches!(
+            next_event(&mut client).await,
+            ClientEvent::Book(BookEvent::ChecksumMismatch { .. })
+        ));
+        assert_eq!(
+            client.books().get("XBT/USD").unwrap().status(),
+            BookStatus::Stale
+        );
+        // The unsubscribe and subscribe statuses
+        next_event(&mut client).await;
+        next_event(&mut client).await;
+        assert_eq!(
+            next_event(&mut client).await,
+            ClientEvent::Book(BookEvent::Initialized {
+                pair: "XBT/USD".to_string()
+            })
+        );
+        assert_eq!(client.books().get("XBT/USD").unwrap().stats().resyncs, 1);
+        {
+            let subscriptions = client.subscriptions();
+            assert_eq!(subscriptions.pending_requests(), 0);
+            let entry = subscriptions.by_channel(43).unwrap();
+            assert_eq!(entry.pair, "XBT/USD");
+            assert_eq!(entry.state, SubscriptionState::Subscribed);
+        }
+
+        server.await.unwrap();
+    }
+
+    fn fast_reconnect(max_attempts: Option<u32>) -> ClientConfig {
+        ClientConfig {
+            reconnect: ReconnectConfig {
+                initial_delay: Duration::from_millis(10),
+                max_delay: Duration::from_millis(50),
+                max_attempts,
+            },
+            ..ClientConfig::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_client_reports_closed_connection() {
+        let (url, listener) = bind().await;
+        let server = tokio::spawn(async move {
+            let mut ws = crate::test_support::accept(&listener).await;
+            ws.close(None).await.unwrap();
+        });
+
+        let mut client = KrakenWsClient::connect_with_config(&url, fast_reconnect(Some(0)))
+            .await
+            .unwrap();
+        server.await.unwrap();
+        assert!(matches!(
+            next_event(&mut client).await,
+            ClientEvent::Connection(ConnectionEvent::Disconnected { .. })
+        ));
+        assert!(matches!(client.next().await, Some(Err(_))));
+        assert!(client.next().await.is_none());
+        assert!(matches!(
+            client.subscribe(&["XBT/USD"], Channel::Trade).await,
+            Err(Error::ConnectionClosed)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_client_reconnects_and_replays_subscriptions() {
+        let (url, listener) = bind().await;
+        let (dropped_tx, dropped_rx) = tokio::sync::oneshot::channel();
+        let server = tokio::spawn(async move {
+            // First connection: serve a snapshot, then drop the socket without a close frame
+            let mut ws = crate::test_support::accept(&listener).await;
+            let mut book = next_json(&mut ws).await;
+            send_json(&mut ws, subscription_status(&book, "subscribed", 42)).await;
+            let mut trade = next_json(&mut ws).await;
+            send_json(&mut ws, subscription_status(&trade, "subscribed", 43)).await;
+            send_json(&mut ws, snapshot_frame()).await;
+            dropped_rx.await.unwrap();
+            drop(ws);
+
+            // Second connection: the client replays both subscriptions, without reqids
+            // since nobody is waiting for them any more
+            book.as_object_mut().unwrap().remove("reqid");
+            trade.as_object_mut().unwrap().remove("reqid");
+            let mut ws = crate::test_support::accept(&listener).await;
+            let mut replayed = vec![next_json(&mut ws).await, next_json(&mut ws).await];
+            replayed.sort_by_key(|request| request["subscription"]["name"].to_string());
+            assert_eq!(replayed, vec![book, trade]);
+            send_json(&mut ws, snapshot_frame()).await;
+            ws
+        });
+
+        let mut client = KrakenWsClient::connect_with_config(&url, fast_reconnect(None))
+            .await
+            .unwrap();
+        client
+            .subscribe(&["XBT/USD"], Channel::Book { depth: 10 })
+            .await
+            .unwrap();
+        client
+            .subscribe(&["XBT/USD"], Channel::Trade)
+            .await
+            .unwrap();
+        assert_eq!(
+            client.subscriptions().by_channel(43).unwrap().pair,
+            "XBT/USD"
+        );
+        next_event(&mut client).await;
+        next_event(&mut client).await;
+        assert_eq!(
+            next_event(&mut client).await,
+            ClientEvent::Book(BookEvent::Initialized {
+                pair: "XBT/USD".to_string()
+            })
+        );
+        dropped_tx.send(()).unwrap();
+
+        assert!(matches!(
+            next_event(&mut client).await,
+            ClientEvent::Connection(ConnectionEvent::Disconnected { .. })
+        ));
+        assert_eq!(
+            client.books().get("XBT/USD").unwrap().status(),
+            BookStatus::AwaitingSnapshot
+        );
+        assert!(client
+            .books()
+            .get("XBT/USD")
+            .unwrap()
+            .asks()
+            .next()
+            .is_none());
+        assert!(matches!(
+            next_event(&mut client).await,
+            ClientEvent::Connection(ConnectionEvent::Reconnecting { attempt: 1, .. })
+        ));
+        assert_eq!(
+            next_event(&mut client).await,
+            ClientEvent::Connection(ConnectionEvent::Reconnected)
+        );
+        assert_eq!(
+            next_event(&mut client).await,
+            ClientEvent::Book(BookEvent::Initialized {
+                pair: "XBT/USD".to_string()
+            })
+        );
+        assert_eq!(
+            client.books().get("XBT/USD").unwrap().status(),
+            BookStatus::Synced
+        );
+        // Channel IDs are per connection and only come back with new statuses
+        assert!(client.subscriptions().by_channel(43).is_none());
+
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_client_retries_when_replay_fails() {
+        let (url, listener) = bind().await;
+        let (dropped_tx, dropped_rx) = tokio::sync::oneshot::channel();
+        let server = tokio::spawn(async move {
+            let mut ws = crate::test_support::accept(&listener).await;
+            let mut subscribed = Vec::new();
+            for channel_id in 42..45 {
+                let mut subscribe = next_json(&mut ws).await;
+                send_json(
+                    &mut ws,
+                    subscription_status(&subscribe, "subscribed", channel_id),
+                )
+                .await;
+                subscribe.as_object_mut().unwrap().remove("reqid");
+                subscribed.push(subscribe);
+            }
+            dropped_rx.await.unwrap();
+            drop(ws);
+
+            // Accept the reconnect, then close the socket while the replay is being sent
+            drop(crate::test_support::accept(&listener).await);
+
+            // The next connection gets the whole replay again
+            let mut ws = crate::test_support::accept(&listener).await;
+            let mut replayed = Vec::new();
+            for _ in 0..3 {
+                replayed.push(next_json(&mut ws).await);
+            }
+            let key = |request: &serde_json::Value| request["subscription"]["name"].to_string();
+            replayed.sort_by_key(key);
+            subscribed.sort_by_key(key);
+            assert_eq!(replayed, subscribed);
+            send_json(&mut ws, snapshot_frame()).await;
+            ws
+        });
+
+        let mut client = KrakenWsClient::connect_with_config(&url, fast_reconnect(None))
+            .await
+            .unwrap();
+        client
+            .subscribe_book("XBT/USD", OrderBook::new(10))
+            .await
+            .unwrap();
+        client.subscribe_trades(&["XBT/USD"]).await.unwrap();
+        client.subscribe_ticker(&["XBT/USD"]).await.unwrap();
+        for _ in 0..3 {
+            next_event(&mut client).await;
+        }
+        dropped_tx.send(()).unwrap();
+
+        assert!(matches!(
+            next_event(&mut client).await,
+            ClientEvent::Connection(ConnectionEvent::Disconnected { .. })
+        ));
+        assert!(matches!(
+            next_event(&mut client).await,
+            ClientEvent::Connection(ConnectionEvent::Reconnecting { attempt: 1, .. })
+        ));
+        // The failed replay is reported and retried with the next backoff step
+        assert!(client.next().await.unwrap().is_err());
+        assert!(matches!(
+            next_event(&mut client).await,
+            ClientEvent::Connection(ConnectionEvent::Reconnecting { attempt: 2, .. })
+        ));
+        assert_eq!(
+            next_event(&mut client).await,
+            ClientEvent::Connection(ConnectionEvent::Reconnected)
+        );
+        assert_eq!(
+            next_event(&mut client).await,
+            ClientEvent::Book(BookEvent::Initialized {
+                pair: "XBT/USD".to_string()
+            })
+        );
+
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_client_subscribe_reports_kraken_error() {
+        let (url, listener) = bind().await;
+        let server = tokio::spawn(async move {
+            let mut ws = crate::test_support::accept(&listener).await;
+            let subscribe = next_json(&mut ws).await;
+            send_json(&mut ws, subscription_status(&subscribe, "subscribed", 7)).await;
+
+            let subscribe = next_json(&mut ws).await;
+            let mut error = subscription_status(&subscribe, "error", 0);
+            error.as_object_mut().unwrap().remove("channelID");
+            error["errorMessage"] = json!("Currency pair not supported FOO/BAR");
+            send_json(&mut ws, error).await;
+
+            let unsubscribe = next_json(&mut ws).await;
+            assert_eq!(unsubscribe["event"], "unsubscribe");
+            send_json(
+                &mut ws,
+                subscription_status(&unsubscribe, "unsubscribed", 7),
+            )
+            .await;
+            ws
+        });
+
+        let client = KrakenWsClient::connect(&url).await.unwrap();
+        client
+            .subscribe(&["XBT/USD"], Channel::Ticker)
+            .await
+            .unwrap();
+        {
+            let subscriptions = client.subscriptions();
+            let entry = subscriptions.by_channel(7).unwrap();
+            assert_eq!(entry.pair, "XBT/USD");
+            assert_eq!(entry.subscription, Channel::Ticker.subscription());
+            assert_eq!(entry.state, SubscriptionState::Subscribed);
+        }
+
+        match client.subscribe(&["FOO/BAR"], Channel::Ticker).await {
+            Err(Error::Subscription(message)) => {
+                assert_eq!(message, "Currency pair not supported FOO/BAR")
+            }
+            other => panic!("expected a subscription error, got {:?}", other),
+        }
+
+        client
+            .unsubscribe(&["XBT/USD"], Channel::Ticker)
+            .await
+            .unwrap();
+        assert!(client.subscriptions().by_channel(7).is_none());
+        assert_eq!(client.subscriptions().pending_requests(), 0);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_client_measures_ping_latency() {
+        let (url, listener) = bind().await;
+        let server = tokio::spawn(async move {
+            let mut ws = crate::test_support::accept(&listener).await;
+            let ping = next_json(&mut ws).await;
+            assert_eq!(ping["event"], "ping");
+            send_json(&mut ws, json!({"event": "pong", "reqid": ping["reqid"]})).await;
+            // A pong nobody asked for is passed through untouched
+            send_json(&mut ws, json!({"event": "pong", "reqid": 999})).await;
+            ws
+        });
+
+        let mut config = fast_reconnect(None);
+        config.heartbeat.ping_interval = Duration::from_millis(20);
+        let mut client = KrakenWsClient::connect_with_config(&url, config)
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            next_event(&mut client).await,
+            ClientEvent::Connection(ConnectionEvent::Latency { .. })
+        ));
+        assert_eq!(
+            next_event(&mut client).await,
+            ClientEvent::Event(EventMessage::Pong { reqid: Some(999) })
+        );
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_client_reconnects_after_silence() {
+        let (url, listener) = bind().await;
+        let server = tokio::spawn(async move {
+            // First connection never says anything, second one sends a heartbeat
+            let silent = crate::test_support::accept(&listener).await;
+            let mut ws = crate::test_support::accept(&listener).await;
+            send_json(&mut ws, json!({"event": "heartbeat"})).await;
+            (silent, ws)
+        });
+
+        let mut config = fast_reconnect(None);
+        // The server never answers the pings
+        config.heartbeat.ping_interval = Duration::from_millis(50);
+        config.heartbeat.silence_timeout = Duration::from_millis(100);
+        let mut client = KrakenWsClient::connect_with_config(&url, config)
+            .await
+            .unwrap();
+
+        match next_event(&mut client).await {
+            ClientEvent::Connection(ConnectionEvent::Disconnected { reason }) => {
+                assert_eq!(
+                    reason,
+                    Error::HeartbeatTimeout(Duration::from_millis(100)).to_string()
+                )
+            }
+            other => panic!("expected a disconnect, got {:?}", other),
+        }
+        assert!(matches!(
+            next_event(&mut client).await,
+            ClientEvent::Connection(ConnectionEvent::Reconnecting { .. })
+        ));
+        assert_eq!(
+            next_event(&mut client).await,
+            ClientEvent::Connection(ConnectionEvent::Reconnected)
+        );
+        assert_eq!(
+            next_event(&mut client).await,
+            ClientEvent::Event(EventMessage::Heartbeat)
+        );
+        server.await.unwrap();
+    }
+}
@@ -0,0 +1,246 @@
+use crate::book_manager::BookManager;
+use crate::error::Error;
+use crate::messages::{EventMessage, Request};
+use std::fmt;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// Keeps Kraken's `cancelAllOrdersAfter` countdown from expiring while the
+/// process is healthy.
+///
+/// Refreshes stop while the connection is down or any watched book is not
+/// synced, so Kraken cancels every open order once `timeout` runs out.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeadMansSwitchConfig {
+    // Countdown sent with every refresh; Kraken recommends at least 60 seconds
+    pub timeout: Duration,
+    // Should be well below `timeout` so a single lost refresh does not trigger it
+    pub refresh_interval: Duration,
+    // Books on another connection, e.g. the public client the strategy trades from
+    pub watched_books: Option<BookWatch>,
+}
+
+impl DeadMansSwitchConfig {
+    pub fn new(timeout: Duration) -> Self {
+        DeadMansSwitchConfig {
+            timeout,
+            refresh_interval: timeout / 4,
+            watched_books: None,
+        }
+    }
+
+    // Checked on connect. Kraken counts in whole seconds and reads a timeout of
+    // 0 as disabling the switch, and refreshes have to beat the countdown.
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.timeout < Duration::from_secs(1) || self.timeout.subsec_nanos() != 0 {
+            return Err(Error::Config(format!(
+                "dead man's switch timeout {:?} is not a whole number of seconds",
+                self.timeout
+            )));
+        }
+        if self.refresh_interval.is_zero() || self.refresh_interval >= self.timeout {
+            return Err(Error::Config(format!(
+                "dead man's switch refresh interval {:?} is not between zero and the timeout {:?}",
+                self.refresh_interval, self.timeout
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// A client's books, shared with whatever decides whether the process is healthy.
+#[derive(Clone)]
+pub struct BookWatch(pub(crate) Arc<RwLock<BookManager>>);
+
+impl BookWatch {
+    pub fn new(books: Arc<RwLock<BookManager>>) -> Self {
+        BookWatch(books)
+    }
+
+    pub fn all_synced(&self) -> bool {
+        self.0.read().unwrap().all_synced()
+    }
+}
+
+impl fmt::Debug for BookWatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("BookWatch").finish()
+    }
+}
+
+// Two watches are equal when they look at the same books
+impl PartialEq for BookWatch {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+/// What Kraken last confirmed about the countdown.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SwitchState {
+    pub armed: bool,
+    // When Kraken cancels all orders unless refreshed again, e.g. `2020-12-21T09:38:09Z`
+    pub trigger_time: Option<String>,
+    // False while refreshes are held back by stale books or a dropped connection
+    pub refreshing: bool,
+    pub last_error: Option<String>,
+}
+
+// Lives in the connection task, which sends the refreshes
+#[derive(Debug)]
+pub(crate) struct DeadMansSwitch {
+    config: DeadMansSwitchConfig,
+    state: Arc<RwLock<SwitchState>>,
+}
+
+impl DeadMansSwitch {
+    pub(crate) fn new(config: DeadMansSwitchConfig) -> Self {
+        DeadMansSwitch {
+            config,
+            state: Arc::new(RwLock::new(SwitchState::default())),
+        }
+    }
+
+    pub(crate) fn state(&self) -> Arc<RwLock<SwitchState>> {
+        Arc::clone(&self.state)
+    }
+
+    pub(crate) fn refresh_interval(&self) -> Duration {
+        self.config.refresh_interval
+    }
+
+    // The refresh to send, or `None` to let the countdown run
+    pub(crate) fn refresh(&self, own_books: &BookManager, reqid: u64) -> Option<Request> {
+        let healthy = own_books.all_synced()
+            && self
+                .config
+                .watched_books
+                .as_ref()
+                .is_none_or(BookWatch::all_synced);
+        self.state.write().unwrap().refreshing = healthy;
+        healthy.then_some(Request::CancelAllOrdersAfter {
+            token: None,
+            reqid: Some(reqid),
+            timeout: self.config.timeout.as_secs(),
+        })
+    }
+
+    pub(crate) fn disconnected(&self) {
+        self.state.write().unwrap().refreshing = false;
+    }
+
+    pub(crate) fn handle_event(&self, event: &EventMessage) {
+        if let EventMessage::CancelAllOrdersAfterStatus {
+            status,
+            trigger_time,
+            error_message,
+            ..
+        } = event
+        {
+            let mut state = self.state.write().unwrap();
+            if status == "ok" {
+                // A timeout of 0 disables the switch and reports a trigger time of "0"
+                state.armed = trigger_time.as_deref().is_some_and(|time| time != "0");
+                state.trigger_time = trigger_time.clone().filter(|_| state.armed);
+                state.last_error = None;
+            } else {
+                state.last_error = Some(error_message.clone().unwrap_or_else(|| status.clone()));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::order_book::OrderBook;
+
+    fn status(json: serde_json::Value) -> EventMessage {
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn test_refresh_only_while_books_are_synced() {
+        let watched = Arc::new(RwLock::new(BookManager::new()));
+        let mut config = DeadMansSwitchConfig::new(Duration::from_secs(60));
+        config.watched_books = Some(BookWatch::new(Arc::clone(&watched)));
+        assert_eq!(config.refresh_interval, Duration::from_secs(15));
+        let switch = DeadMansSwitch::new(config);
+
+        assert_eq!(
+            switch.refresh(&BookManager::new(), 4),
+            Some(Request::CancelAllOrdersAfter {
+                token: None,
+                reqid: Some(4),
+                timeout: 60
+            })
+        );
+        assert!(switch.state().read().unwrap().refreshing);
+
+        // A book still waiting for its snapshot holds the refresh back
+        watched
+            .write()
+            .unwrap()
+            .add_book("XBT/USD", OrderBook::new(10));
+        assert_eq!(switch.refresh(&BookManager::new(), 5), None);
+        assert!(!switch.state().read().unwrap().refreshing);
+    }
+
+    #[test]
+    fn test_config_rejects_timeouts_kraken_cannot_keep() {
+        assert!(DeadMansSwitchConfig::new(Duration::from_secs(60))
+            .validate()
+            .is_ok());
+        for timeout in [
+            Duration::ZERO,
+            Duration::from_nanos(3),
+            // Would be sent as 0, which disables the switch
+            Duration::from_millis(900),
+            Duration::from_millis(1500),
+        ] {
+            assert!(matches!(
+                DeadMansSwitchConfig::new(timeout).validate(),
+                Err(Error::Config(_))
+            ));
+        }
+
+        let mut config = DeadMansSwitchConfig::new(Duration::from_secs(60));
+        for refresh_interval in [
+            Duration::ZERO,
+            Duration::from_secs(60),
+            Duration::from_secs(90),
+        ] {
+            config.refresh_interval = refresh_interval;
+            assert!(matches!(config.validate(), Err(Error::Config(_))));
+        }
+    }
+
+    #[test]
+    fn test_state_follows_kraken_status() {
+        let switch = DeadMansSwitch::new(DeadMansSwitchConfig::new(Duration::from_secs(60)));
+        switch.handle_event(&status(serde_json::json!({
+            "event": "cancelAllOrdersAfterStatus", "currentTime": "2020-12-21T09:37:09Z",
+            "reqid": 1, "status": "ok", "triggerTime": "2020-12-21T09:38:09Z"
+        })));
+        let state = switch.state().read().unwrap().clone();
+        assert!(state.armed);
+        assert_eq!(state.trigger_time.as_deref(), Some("2020-12-21T09:38:09Z"));
+
+        switch.handle_event(&status(serde_json::json!({
+            "event": "cancelAllOrdersAfterStatus", "errorMessage": "EGeneral:Internal error",
+            "reqid": 2, "status": "error"
+        })));
+        let state = switch.state().read().unwrap().clone();
+        assert!(state.armed);
+        assert_eq!(state.last_error.as_deref(), Some("EGeneral:Internal error"));
+
+        switch.handle_event(&status(serde_json::json!({
+            "event": "cancelAllOrdersAfterStatus", "currentTime": "2020-12-21T09:39:00Z",
+            "reqid": 3, "status": "ok", "triggerTime": "0"
+        })));
+        let state = switch.state().read().unwrap().clone();
+        assert!(!state.armed);
+        assert_eq!(state.trigger_time, None);
+        assert_eq!(state.last_error, None);
+    }
+}
@@ -0,0 +1,369 @@
+use serde::de::{self, Deserializer, Visitor};
+use serde::{Deserialize, Serialize, Serializer};
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::iter::Sum;
+use std::ops::Add;
+use std::str::FromStr;
+
+// Largest scale we accept; 10^18 still fits an i64 and keeps comparisons in i128
+const MAX_SCALE: u32 = 18;
+
+/// Fixed-point decimal: an integer mantissa and the number of fractional digits.
+///
+/// Values parsed from Kraken's strings keep their scale, so `"5711.80000"`
+/// is stored as `571180000` with scale 5 and prints back unchanged. Equality,
+/// ordering and hashing compare the numeric value, so `1.0 == 1.00`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Decimal {
+    mantissa: i64,
+    scale: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseDecimalError {
+    input: String,
+}
+
+impl Decimal {
+    pub fn is_zero(&self) -> bool {
+        self.mantissa == 0
+    }
+
+    // Changes the number of fractional digits, rounding half away from zero when
+    // shortening; saturates at the largest value the scale can hold when widening overflows
+    pub fn rescale(&self, scale: u32) -> Decimal {
+        let scale = scale.min(MAX_SCALE);
+        self.checked_rescale(scale)
+            .unwrap_or_else(|| Decimal::saturated(self.mantissa.signum(), scale))
+    }
+
+    // `None` when the mantissa would not fit at the wider scale
+    pub fn checked_rescale(&self, scale: u32) -> Option<Decimal> {
+        let scale = scale.min(MAX_SCALE);
+        match scale.cmp(&self.scale) {
+            Ordering::Equal => Some(*self),
+            Ordering::Greater => Some(Decimal {
+                mantissa: self.mantissa.checked_mul(10i64.pow(scale - self.scale))?,
+                scale,
+            }),
+            Ordering::Less => {
+                let divisor = 10i64.pow(self.scale - scale);
+                let quotient = self.mantissa / divisor;
+                let remainder = self.mantissa % divisor;
+                let rounded = if remainder.abs() * 2 >= divisor {
+                    quotient + self.mantissa.signum()
+                } else {
+                    quotient
+                };
+                Some(Decimal {
+                    mantissa: rounded,
+                    scale,
+                })
+            }
+        }
+    }
+
+    // Exact at the larger of the two scales, `None` on overflow
+    pub fn checked_add(self, other: Decimal) -> Option<Decimal> {
+        let scale = self.scale.max(other.scale);
+        let sum = self.widened(scale) + other.widened(scale);
+        Some(Decimal {
+            mantissa: i64::try_from(sum).ok()?,
+            scale,
+        })
+    }
+
+    fn saturated(sign: i64, scale: u32) -> Decimal {
+        Decimal {
+            mantissa: if sign < 0 { i64::MIN } else { i64::MAX },
+            scale,
+        }
+    }
+
+    // Mantissa widened to the given (larger or equal) scale, used for exact comparisons
+    fn widened(&self, scale: u32) -> i128 {
+        self.mantissa as i128 * 10i128.pow(scale - self.scale)
+    }
+
+    // Same value with trailing fractional zeros removed
+    fn normalized(&self) -> Decimal {
+        let mut normalized = *self;
+        while normalized.scale > 0 && normalized.mantissa % 10 == 0 {
+            normalized.mantissa /= 10;
+            normalized.scale -= 1;
+        }
+        normalized
+    }
+}
+
+impl FromStr for Decimal {
+    type Err = ParseDecimalError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || ParseDecimalError {
+            input: s.to_string(),
+        };
+
+        let (negative, unsigned) = match s.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+        let (integer, fraction) = unsigned.split_once('.').unwrap_or((unsigned, ""));
+
+        if integer.is_empty() && fraction.is_empty() {
+            return Err(error());
+        }
+        if !integer
+            .bytes()
+            .chain(fraction.bytes())
+            .all(|b| b.is_ascii_digit())
+        {
+            return Err(error());
+        }
+        let scale = fraction.len() as u32;
+        if scale > MAX_SCALE {
+            return Err(error());
+        }
+
+        let mut mantissa: i64 = 0;
+        for digit in integer.bytes().chain(fraction.bytes()) {
+            mantissa = mantissa
+                .checked_mul(10)
+                .and_then(|m| m.checked_add((digit - b'0') as i64))
+                .ok_or_else(error)?;
+        }
+
+        Ok(Decimal {
+            mantissa: if negative { -mantissa } else { mantissa },
+            scale,
+        })
+    }
+}
+
+impl PartialEq for Decimal {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Decimal {}
+
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Decimal {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let scale = self.scale.max(other.scale);
+        self.widened(scale).cmp(&other.widened(scale))
+    }
+}
+
+impl Hash for Decimal {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let normalized = self.normalized();
+        normalized.mantissa.hash(state);
+        normalized.scale.hash(state);
+    }
+}
+
+impl From<i64> for Decimal {
+    fn from(mantissa: i64) -> Self {
+        Decimal { mantissa, scale: 0 }
+    }
+}
+
+// Exact at the larger of the two scales; saturates instead of overflowing
+impl Add for Decimal {
+    type Output = Decimal;
+
+    fn add(self, other: Decimal) -> Decimal {
+        self.checked_add(other).unwrap_or_else(|| {
+            let scale = self.scale.max(other.scale);
+            let sign = (self.widened(scale) + other.widened(scale)).signum() as i64;
+            Decimal::saturated(sign, scale)
+        })
+    }
+}
+
+impl Sum for Decimal {
+    fn sum<I: Iterator<Item = Decimal>>(iter: I) -> Decimal {
+        iter.fold(Decimal::default(), Add::add)
+    }
+}
+
+// Prints with the stored scale, or with the formatter's precision (`{:.5}`) when given
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Extra digits are padded rather than rescaled, which could overflow
+        let (value, padding) = match f.precision() {
+            Some(precision) if precision as u32 > self.scale => {
+                (*self, precision - self.scale as usize)
+            }
+            Some(precision) => (self.rescale(precision as u32), 0),
+            None => (*self, 0),
+        };
+
+        let digits = value.mantissa.unsigned_abs().to_string();
+        let scale = value.scale as usize;
+        let padded = format!("{:0>width$}", digits, width = scale + 1);
+        let (integer, fraction) = padded.split_at(padded.len() - scale);
+
+        if value.mantissa < 0 {
+            f.write_str("-")?;
+        }
+        f.write_str(integer)?;
+        if scale + padding > 0 {
+            write!(f, ".{}{}", fraction, "0".repeat(padding))?;
+        }
+        Ok(())
+    }
+}
+
+// Sent as a string, the way Kraken's v1 API expects prices and volumes
+impl Serialize for Decimal {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+// Kraken's v1 API sends prices and volumes as strings, v2 sends JSON numbers.
+// Floats are read back through their shortest representation, so `0.5666` stays `0.5666`.
+impl<'de> Deserialize<'de> for Decimal {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct DecimalVisitor;
+
+        impl<'de> Visitor<'de> for DecimalVisitor {
+            type Value = Decimal;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a decimal string or a number")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Decimal, E> {
+                v.parse().map_err(E::custom)
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<Decimal, E> {
+                Ok(Decimal::from(v))
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<Decimal, E> {
+                i64::try_from(v).map(Decimal::from).map_err(E::custom)
+            }
+
+            fn visit_f64<E: de::Error>(self, v: f64) -> Result<Decimal, E> {
+                v.to_string().parse().map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_any(DecimalVisitor)
+    }
+}
+
+impl fmt::Display for ParseDecimalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid decimal literal: {:?}", self.input)
+    }
+}
+
+impl std::error::Error for ParseDecimalError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dec(s: &str) -> Decimal {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_parse_keeps_scale() {
+        let price = dec("5711.80000");
+        assert_eq!(price.mantissa, 571180000);
+        assert_eq!(price.scale, 5);
+        assert_eq!(price.to_string(), "5711.80000");
+
+        assert_eq!(dec("0.00000500").to_string(), "0.00000500");
+        assert_eq!(dec("-1.5").to_string(), "-1.5");
+        assert_eq!(dec("42").to_string(), "42");
+        assert_eq!(dec(".5").to_string(), "0.5");
+    }
+
+    #[test]
+    fn test_parse_rejects_garbage() {
+        assert!("".parse::<Decimal>().is_err());
+        assert!(".".parse::<Decimal>().is_err());
+        assert!("NaN".parse::<Decimal>().is_err());
+        assert!("1.2.3".parse::<Decimal>().is_err());
+        assert!("1e-5".parse::<Decimal>().is_err());
+        assert!("99999999999999999999".parse::<Decimal>().is_err());
+    }
+
+    #[test]
+    fn test_deserialize_strings_and_numbers() {
+        let values: Vec<Decimal> =
+            serde_json::from_str(r#"["5711.80000", 42, 0.5666, 4831.75496356, 40.0]"#).unwrap();
+        assert_eq!(values[0].to_string(), "5711.80000");
+        assert_eq!(values[1], dec("42"));
+        assert_eq!(values[2].to_string(), "0.5666");
+        assert_eq!(values[3].to_string(), "4831.75496356");
+        assert_eq!(values[4], dec("40"));
+        assert!(serde_json::from_str::<Decimal>("1e300").is_err());
+    }
+
+    #[test]
+    fn test_equality_ignores_scale() {
+        assert_eq!(dec("1.0"), dec("1.00000"));
+        assert_ne!(dec("0.00000001"), dec("0.00000002"));
+        assert!(dec("5711.8") > dec("5711.75"));
+        assert!(dec("-0.1") < Decimal::default());
+        assert!(dec("0.00000000").is_zero());
+    }
+
+    #[test]
+    fn test_sum_keeps_larger_scale() {
+        assert_eq!((dec("1.5") + dec("0.25")).to_string(), "1.75");
+        assert_eq!((dec("2.10") + dec("-3")).to_string(), "-0.90");
+        let total: Decimal = [dec("0.1"), dec("0.2"), dec("0.30000000")]
+            .into_iter()
+            .sum();
+        assert_eq!(total.to_string(), "0.60000000");
+    }
+
+    #[test]
+    fn test_overflow_saturates() {
+        let large = Decimal::from(i64::MAX - 1);
+        assert_eq!(
+            large.checked_add(Decimal::from(1)),
+            Some(Decimal::from(i64::MAX))
+        );
+        assert_eq!(large.checked_add(Decimal::from(2)), None);
+        assert_eq!(large + Decimal::from(2), Decimal::from(i64::MAX));
+        assert_eq!(
+            Decimal::from(i64::MIN + 1) + Decimal::from(-3),
+            Decimal::from(i64::MIN)
+        );
+        let total: Decimal = [large, large].into_iter().sum();
+        assert_eq!(total, Decimal::from(i64::MAX));
+
+        // A volume that only fits at its own scale
+        let volume = dec("92233720368.54775807");
+        assert_eq!(volume.checked_rescale(18), None);
+        assert_eq!(volume.rescale(18).mantissa, i64::MAX);
+        assert_eq!(format!("{:.10}", volume), "92233720368.5477580700");
+        assert_eq!((volume + dec("0.000000001")).mantissa, i64::MAX);
+    }
+
+    #[test]
+    fn test_display_precision() {
+        assert_eq!(format!("{:.5}", dec("5711.8")), "5711.80000");
+        assert_eq!(format!("{:.2}", dec("0.005")), "0.01");
+        assert_eq!(format!("{:.2}", dec("-0.005")), "-0.01");
+        assert_eq!(format!("{:.0}", dec("12.4")), "12");
+    }
+}
@@ -0,0 +1,226 @@
+use crate::error::Error;
+use crate::heartbeat::{HeartbeatConfig, Liveness};
+use crate::reconnect::{Backoff, ReconnectConfig};
+use futures_util::{SinkExt, StreamExt};
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio_tungstenite::{
+    connect_async, tungstenite::protocol::Message, MaybeTlsStream, WebSocketStream,
+};
+use url::Url;
+
+pub(crate) type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// What a client does with its connection, driven by a `Driver`.
+///
+/// Sessions never touch the socket: whatever they want to send is queued on
+/// the `Link` and written by the driver once the call returns, so a failed
+/// write is handled the same way for every client. Errors returned from a
+/// session call drop the connection.
+pub(crate) trait Session: Send + 'static {
+    // What the client handle queues for the connection task
+    type Command: Send + 'static;
+
+    fn command(&mut self, command: Self::Command, link: &mut Link) -> Result<(), Error>;
+
+    // Frames a session cannot parse should be reported rather than returned
+    fn text(&mut self, text: &str, link: &mut Link) -> Result<(), Error>;
+
+    // Queues whatever keeps the connection alive and answered
+    fn ping(&mut self, link: &mut Link) -> Result<(), Error>;
+
+    // An extra periodic task, e.g. refreshing the dead man's switch
+    fn timer_interval(&self) -> Option<Duration> {
+        None
+    }
+
+    fn timer(&mut self, _link: &mut Link) -> Result<(), Error> {
+        Ok(())
+    }
+
+    // The connection dropped; everything tied to it has to be reset here
+    fn disconnected(&mut self, reason: &Error);
+
+    fn reconnecting(&mut self, _attempt: u32, _delay: Duration) {}
+
+    // Failed reconnect attempts, and the error the driver gives up with
+    fn report(&mut self, error: Error);
+
+    // Queues the requests that restore the session on a fresh socket; called
+    // again if that socket fails before the replay went out
+    fn replay(&mut self, link: &mut Link) -> Result<(), Error>;
+
+    fn reconnected(&mut self);
+
+    // Nobody listens to the session any more, so there is no point reconnecting
+    fn abandoned(&self) -> bool;
+
+    // The driver stopped for good
+    fn closed(&mut self) {}
+}
+
+// The driver's side of a session call: outgoing frames and the liveness state
+#[derive(Debug)]
+pub(crate) struct Link {
+    outbox: Vec<Message>,
+    liveness: Liveness,
+}
+
+impl Link {
+    pub(crate) fn send(&mut self, message: Message) {
+        self.outbox.push(message);
+    }
+
+    pub(crate) fn send_text(&mut self, text: String) {
+        self.send(Message::Text(text));
+    }
+
+    pub(crate) fn liveness(&mut self) -> &mut Liveness {
+        &mut self.liveness
+    }
+}
+
+/// The background task owning a client's socket.
+///
+/// Reads frames and commands, pings and watches for silence, and after a drop
+/// reconnects with backoff and has the session replay its subscriptions. A
+/// socket that fails during the replay is dropped and retried like a failed
+/// connect, so only running out of attempts stops the driver.
+pub(crate) struct Driver<S: Session> {
+    url: Url,
+    backoff: Backoff,
+    heartbeat: HeartbeatConfig,
+    socket: Socket,
+    link: Link,
+    commands: mpsc::UnboundedReceiver<S::Command>,
+    session: S,
+}
+
+// Connects the first time; only this attempt is reported to the caller as an error
+pub(crate) async fn connect(url: &str) -> Result<(Url, Socket), Error> {
+    let url = Url::parse(url)?;
+    let (socket, _response) = connect_async(url.clone()).await?;
+    Ok((url, socket))
+}
+
+impl<S: Session> Driver<S> {
+    pub(crate) fn new(
+        (url, socket): (Url, Socket),
+        reconnect: ReconnectConfig,
+        heartbeat: HeartbeatConfig,
+        commands: mpsc::UnboundedReceiver<S::Command>,
+        session: S,
+    ) -> Self {
+        Driver {
+            url,
+            backoff: Backoff::new(reconnect),
+            link: Link {
+                outbox: Vec::new(),
+                liveness: Liveness::new(heartbeat.silence_timeout),
+            },
+            heartbeat,
+            socket,
+            commands,
+            session,
+        }
+    }
+
+    pub(crate) async fn run(mut self) {
+        loop {
+            let error = match self.process().await {
+                Ok(()) => break,
+                Err(e) => e,
+            };
+            // Frames queued for the dead socket; the replay covers what matters
+            self.link.outbox.clear();
+            self.session.disconnected(&error);
+
+            if let Err(e) = self.reconnect().await {
+                self.session.report(e);
+                break;
+            }
+        }
+        self.session.closed();
+    }
+
+    async fn process(&mut self) -> Result<(), Error> {
+        let ping_interval = self.heartbeat.ping_interval;
+        let mut ping_timer =
+            tokio::time::interval_at(tokio::time::Instant::now() + ping_interval, ping_interval);
+        self.link.liveness.reset();
+        // Starts right away; without an interval the timer is never polled
+        let timer_interval = self.session.timer_interval();
+        let mut timer = tokio::time::interval(timer_interval.unwrap_or(ping_interval));
+
+        loop {
+            tokio::select! {
+                command = self.commands.recv() => match command {
+                    Some(command) => self.session.command(command, &mut self.link)?,
+                    // The client was dropped
+                    None => return Ok(self.socket.close(None).await?),
+                },
+                message = self.socket.next() => match message {
+                    Some(Ok(Message::Text(text))) => {
+                        self.link.liveness.record_message();
+                        self.session.text(&text, &mut self.link)?
+                    }
+                    Some(Ok(_)) => self.link.liveness.record_message(), // Other message types
+                    Some(Err(e)) => return Err(e.into()),
+                    None => return Err(Error::ConnectionClosed),
+                },
+                _ = ping_timer.tick() => self.session.ping(&mut self.link)?,
+                _ = timer.tick(), if timer_interval.is_some() => {
+                    self.session.timer(&mut self.link)?
+                }
+                _ = tokio::time::sleep_until(self.link.liveness.deadline()) => {
+                    return Err(Error::HeartbeatTimeout(self.heartbeat.silence_timeout));
+                }
+            }
+            self.flush().await?;
+        }
+    }
+
+    // Retries with backoff until connected and the session is replayed
+    async fn reconnect(&mut self) -> Result<(), Error> {
+        loop {
+            if self.session.abandoned() {
+                return Err(Error::ConnectionClosed);
+            }
+            let delay = self.backoff.next_delay().ok_or(Error::ConnectionClosed)?;
+            self.session.reconnecting(self.backoff.attempt(), delay);
+            tokio::time::sleep(delay).await;
+
+            match connect_async(self.url.clone()).await {
+                Ok((socket, _response)) => self.socket = socket,
+                Err(e) => {
+                    self.session.report(e.into());
+                    continue;
+                }
+            }
+            match self.replay().await {
+                Ok(()) => break,
+                Err(e) => {
+                    self.link.outbox.clear();
+                    self.session.report(e);
+                }
+            }
+        }
+
+        self.backoff.reset();
+        self.session.reconnected();
+        Ok(())
+    }
+
+    async fn replay(&mut self) -> Result<(), Error> {
+        self.session.replay(&mut self.link)?;
+        self.flush().await
+    }
+
+    async fn flush(&mut self) -> Result<(), Error> {
+        for message in self.link.outbox.drain(..) {
+            self.socket.send(message).await?;
+        }
+        Ok(())
+    }
+}
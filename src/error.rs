@@ -0,0 +1,221 @@
+use std::fmt;
+use std::time::Duration;
+use tokio_tungstenite::tungstenite;
+
+#[derive(Debug)]
+pub enum Error {
+    Url(url::ParseError),
+    // Boxed: tungstenite errors are large and would bloat every Result
+    WebSocket(Box<tungstenite::Error>),
+    Json(serde_json::Error),
+    // The connection task has stopped; no further requests can be sent
+    ConnectionClosed,
+    // Nothing was received within the heartbeat silence window
+    HeartbeatTimeout(Duration),
+    // Kraken rejected a subscribe or unsubscribe request; carries its `errorMessage`
+    Subscription(String),
+    // Kraken rejected an order request and this is its `errorMessage`, or the
+    // request was refused before sending
+    Order(String),
+    Http(reqwest::Error),
+    // Kraken's `error` array from a REST response
+    Api(Vec<ApiError>),
+    // The API secret is not valid base64
+    InvalidSecret(base64::DecodeError),
+    // The request has no equivalent in the protocol the connection speaks
+    Unsupported(String),
+    Io(std::io::Error),
+    // No nonce could be handed out, e.g. a nonce file holds something else
+    Nonce(String),
+    // The modelled API counter has no room for the call for this long;
+    // `Duration::MAX` when the call costs more than the counter holds
+    RateLimited(Duration),
+    // A configuration the client cannot work with, e.g. pings slower than the silence timeout
+    Config(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Url(e) => write!(f, "invalid url: {}", e),
+            Error::WebSocket(e) => write!(f, "websocket error: {}", e),
+            Error::Json(e) => write!(f, "invalid message: {}", e),
+            Error::ConnectionClosed => write!(f, "connection closed"),
+            Error::HeartbeatTimeout(silence) => {
+                write!(f, "no message received for {:?}", silence)
+            }
+            Error::Subscription(message) => write!(f, "subscription failed: {}", message),
+            Error::Order(message) => write!(f, "order rejected: {}", message),
+            Error::Http(e) => write!(f, "http error: {}", e),
+            Error::Api(errors) => {
+                let errors: Vec<String> = errors.iter().map(ApiError::to_string).collect();
+                write!(f, "api error: {}", errors.join(", "))
+            }
+            Error::InvalidSecret(e) => write!(f, "invalid api secret: {}", e),
+            Error::Unsupported(what) => write!(f, "not supported: {}", what),
+            Error::Io(e) => write!(f, "io error: {}", e),
+            Error::Nonce(message) => write!(f, "no nonce available: {}", message),
+            Error::RateLimited(wait) if *wait == Duration::MAX => {
+                write!(f, "rate limited: call exceeds the api counter")
+            }
+            Error::RateLimited(wait) => write!(f, "rate limited for another {:?}", wait),
+            Error::Config(message) => write!(f, "invalid configuration: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Url(e) => Some(e),
+            Error::WebSocket(e) => Some(e.as_ref()),
+            Error::Json(e) => Some(e),
+            Error::Http(e) => Some(e),
+            Error::InvalidSecret(e) => Some(e),
+            Error::Io(e) => Some(e),
+            Error::ConnectionClosed
+            | Error::HeartbeatTimeout(_)
+            | Error::Subscription(_)
+            | Error::Order(_)
+            | Error::Api(_)
+            | Error::Unsupported(_)
+            | Error::Nonce(_)
+            | Error::RateLimited(_)
+            | Error::Config(_) => None,
+        }
+    }
+}
+
+/// An error string from Kraken's REST API, e.g. `EGeneral:Invalid arguments`.
+///
+/// Kraken formats them as `<severity><category>:<message>[:<details>]`;
+/// the variant is picked from category and message, so details are dropped.
+/// Errors without a variant of their own are kept verbatim in `Other`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ApiError {
+    InvalidArguments,
+    UnknownMethod,
+    PermissionDenied,
+    TemporaryLockout,
+    ServiceUnavailable,
+    ServiceBusy,
+    MarketCancelOnly,
+    MarketPostOnly,
+    DeadlineElapsed,
+    InvalidKey,
+    InvalidSignature,
+    InvalidNonce,
+    // Too many REST calls; see `EOrder:Rate limit exceeded` for order placement
+    RateLimitExceeded,
+    UnknownAssetPair,
+    UnknownAsset,
+    OrderRateLimitExceeded,
+    OrdersLimitExceeded,
+    InsufficientFunds,
+    UnknownOrder,
+    InvalidPrice,
+    Other(String),
+}
+
+const API_ERRORS: &[(&str, ApiError)] = &[
+    ("EGeneral:Invalid arguments", ApiError::InvalidArguments),
+    ("EGeneral:Unknown method", ApiError::UnknownMethod),
+    ("EGeneral:Permission denied", ApiError::PermissionDenied),
+    ("EGeneral:Temporary lockout", ApiError::TemporaryLockout),
+    ("EService:Unavailable", ApiError::ServiceUnavailable),
+    ("EService:Busy", ApiError::ServiceBusy),
+    (
+        "EService:Market in cancel_only mode",
+        ApiError::MarketCancelOnly,
+    ),
+    (
+        "EService:Market in post_only mode",
+        ApiError::MarketPostOnly,
+    ),
+    ("EService:Deadline elapsed", ApiError::DeadlineElapsed),
+    ("EAPI:Invalid key", ApiError::InvalidKey),
+    ("EAPI:Invalid signature", ApiError::InvalidSignature),
+    ("EAPI:Invalid nonce", ApiError::InvalidNonce),
+    ("EAPI:Rate limit exceeded", ApiError::RateLimitExceeded),
+    ("EQuery:Unknown asset pair", ApiError::UnknownAssetPair),
+    ("EQuery:Unknown asset", ApiError::UnknownAsset),
+    (
+        "EOrder:Rate limit exceeded",
+        ApiError::OrderRateLimitExceeded,
+    ),
+    (
+        "EOrder:Orders limit exceeded",
+        ApiError::OrdersLimitExceeded,
+    ),
+    ("EOrder:Insufficient funds", ApiError::InsufficientFunds),
+    ("EOrder:Unknown order", ApiError::UnknownOrder),
+    ("EOrder:Invalid price", ApiError::InvalidPrice),
+];
+
+impl ApiError {
+    pub fn parse(error: &str) -> Self {
+        let mut parts = error.splitn(3, ':');
+        let known = match (parts.next(), parts.next()) {
+            (Some(category), Some(message)) => API_ERRORS
+                .iter()
+                .find(|(text, _)| text.split_once(':') == Some((category, message))),
+            _ => None,
+        };
+        match known {
+            Some((_, api_error)) => api_error.clone(),
+            None => ApiError::Other(error.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = match self {
+            ApiError::Other(error) => error,
+            api_error => {
+                API_ERRORS
+                    .iter()
+                    .find(|(_, known)| known == api_error)
+                    .expect("every variant but Other is listed")
+                    .0
+            }
+        };
+        f.write_str(text)
+    }
+}
+
+impl From<url::ParseError> for Error {
+    fn from(e: url::ParseError) -> Self {
+        Error::Url(e)
+    }
+}
+
+impl From<tungstenite::Error> for Error {
+    fn from(e: tungstenite::Error) -> Self {
+        Error::WebSocket(Box::new(e))
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Json(e)
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        Error::Http(e)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<base64::DecodeError> for Error {
+    fn from(e: base64::DecodeError) -> Self {
+        Error::InvalidSecret(e)
+    }
+}
@@ -0,0 +1,680 @@
+use crate::book_manager::{BookEvent, BookManager};
+use crate::decimal::Decimal;
+use crate::driver::{self, Driver, Link, Session};
+use crate::error::Error;
+use crate::heartbeat::HeartbeatConfig;
+use crate::messages::{BookEntry, BookSnapshot, BookUpdate, Side};
+use crate::order_book::{BookStatus, OrderBook};
+use crate::reconnect::ReconnectConfig;
+use futures_util::Stream;
+use serde::de::{self, Deserializer};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::{Arc, RwLock, RwLockReadGuard};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::protocol::Message;
+
+pub const FUTURES_URL: &str = "wss://futures.kraken.com/ws/v1";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FuturesConfig {
+    pub reconnect: ReconnectConfig,
+    // Pings are WebSocket ping frames; without a `heartbeat` subscription their
+    // pongs may be all an idle connection receives
+    pub heartbeat: HeartbeatConfig,
+}
+
+impl Default for FuturesConfig {
+    fn default() -> Self {
+        // The futures endpoint drops connections that stay silent for a minute
+        FuturesConfig {
+            reconnect: ReconnectConfig::default(),
+            heartbeat: HeartbeatConfig {
+                ping_interval: Duration::from_secs(30),
+                silence_timeout: Duration::from_secs(60),
+            },
+        }
+    }
+}
+
+/// Requests sent to the Kraken Futures WebSocket.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "lowercase")]
+pub enum FuturesRequest {
+    Subscribe {
+        feed: String,
+        product_ids: Vec<String>,
+    },
+    Unsubscribe {
+        feed: String,
+        product_ids: Vec<String>,
+    },
+}
+
+/// Any message received on the Kraken Futures WebSocket.
+///
+/// Events carry an `event` field; feed data carries a `feed` field instead.
+/// Book messages are numbered per product with `seq`, which increases by one
+/// with every message.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FuturesMessage {
+    Event(FuturesEvent),
+    BookSnapshot(FuturesBookSnapshot),
+    BookDelta(FuturesBookDelta),
+    Heartbeat,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "event", rename_all = "lowercase")]
+pub enum FuturesEvent {
+    Info {
+        version: Option<u32>,
+    },
+    Subscribed {
+        feed: String,
+        #[serde(default)]
+        product_ids: Vec<String>,
+    },
+    Unsubscribed {
+        feed: String,
+        #[serde(default)]
+        product_ids: Vec<String>,
+    },
+    Error {
+        message: String,
+    },
+    Alert {
+        message: String,
+    },
+}
+
+// Full depth of one product, sent after subscribing
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct FuturesBookSnapshot {
+    pub product_id: String,
+    pub seq: u64,
+    // Milliseconds since the epoch
+    pub timestamp: u64,
+    pub bids: Vec<FuturesLevel>,
+    pub asks: Vec<FuturesLevel>,
+}
+
+// One changed level; a quantity of 0 removes it
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct FuturesBookDelta {
+    pub product_id: String,
+    pub side: Side,
+    pub seq: u64,
+    pub price: Decimal,
+    pub qty: Decimal,
+    pub timestamp: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct FuturesLevel {
+    pub price: Decimal,
+    pub qty: Decimal,
+}
+
+impl<'de> Deserialize<'de> for FuturesMessage {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = Value::deserialize(deserializer)?;
+        if value.get("event").is_some() {
+            return FuturesEvent::deserialize(value)
+                .map(FuturesMessage::Event)
+                .map_err(de::Error::custom);
+        }
+        let feed = value
+            .get("feed")
+            .and_then(Value::as_str)
+            .ok_or_else(|| de::Error::custom("expected an event or a feed"))?
+            .to_string();
+        match feed.as_str() {
+            "book_snapshot" => FuturesBookSnapshot::deserialize(value)
+                .map(FuturesMessage::BookSnapshot)
+                .map_err(de::Error::custom),
+            "book" => FuturesBookDelta::deserialize(value)
+                .map(FuturesMessage::BookDelta)
+                .map_err(de::Error::custom),
+            "heartbeat" => Ok(FuturesMessage::Heartbeat),
+            _ => Err(de::Error::custom(format!("unknown feed: {}", feed))),
+        }
+    }
+}
+
+/// Something the futures client received or did on the connection.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FuturesClientEvent {
+    Event(FuturesEvent),
+    // What a book message did to the product's book in `books()`
+    Book(BookEvent),
+    // A book message went missing; the book waits for a new snapshot, which was requested
+    SequenceGap {
+        product_id: String,
+        expected: u64,
+        received: u64,
+    },
+    Disconnected {
+        reason: String,
+    },
+    Reconnected,
+}
+
+// Last applied `seq` per product; a delta that does not follow it means a missed message
+#[derive(Debug, Default)]
+struct Sequencer {
+    last_seq: HashMap<String, u64>,
+}
+
+impl Sequencer {
+    // Applies a book message to its product's book, if tracked
+    fn apply(
+        &mut self,
+        books: &mut BookManager,
+        message: &FuturesMessage,
+    ) -> Option<FuturesClientEvent> {
+        match message {
+            FuturesMessage::BookSnapshot(snapshot) => {
+                let book = books.get_mut(&snapshot.product_id)?;
+                book.initialize(&BookSnapshot {
+                    asks: entries(&snapshot.asks, snapshot.timestamp),
+                    bids: entries(&snapshot.bids, snapshot.timestamp),
+                    checksum: None,
+                });
+                self.last_seq
+                    .insert(snapshot.product_id.clone(), snapshot.seq);
+                Some(FuturesClientEvent::Book(BookEvent::Initialized {
+                    pair: snapshot.product_id.clone(),
+                }))
+            }
+            FuturesMessage::BookDelta(delta) => {
+                let book = books.get_mut(&delta.product_id)?;
+                let pair = delta.product_id.clone();
+                if book.status() != BookStatus::Synced {
+                    return Some(FuturesClientEvent::Book(BookEvent::Stale { pair }));
+                }
+                let expected = self.last_seq.get(&pair).map_or(0, |seq| seq + 1);
+                if delta.seq < expected {
+                    // Already applied, e.g. sent again around a snapshot
+                    return None;
+                }
+                if delta.seq > expected {
+                    book.reset();
+                    self.last_seq.remove(&pair);
+                    return Some(FuturesClientEvent::SequenceGap {
+                        product_id: pair,
+                        expected,
+                        received: delta.seq,
+                    });
+                }
+
+                let level = vec![BookEntry {
+                    price: delta.price,
+                    volume: delta.qty,
+                    timestamp: Some(seconds(delta.timestamp)),
+                    republish: false,
+                }];
+                let update = match delta.side {
+                    Side::Buy => BookUpdate {
+                        bids: level,
+                        ..BookUpdate::default()
+                    },
+                    Side::Sell => BookUpdate {
+                        asks: level,
+                        ..BookUpdate::default()
+                    },
+                };
+                // Futures books carry no checksum, so this cannot fail
+                let _ = book.apply_update(&update);
+                self.last_seq.insert(pair.clone(), delta.seq);
+                Some(FuturesClientEvent::Book(BookEvent::Updated { pair }))
+            }
+            FuturesMessage::Event(_) | FuturesMessage::Heartbeat => None,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.last_seq.clear();
+    }
+}
+
+fn entries(levels: &[FuturesLevel], timestamp: u64) -> Vec<BookEntry> {
+    levels
+        .iter()
+        .map(|level| BookEntry {
+            price: level.price,
+            volume: level.qty,
+            timestamp: Some(seconds(timestamp)),
+            republish: false,
+        })
+        .collect()
+}
+
+// Milliseconds to seconds since the epoch, the unit spot books use
+fn seconds(millis: u64) -> Decimal {
+    format!("{}.{:03}", millis / 1000, millis % 1000)
+        .parse()
+        .unwrap_or_default()
+}
+
+/// Async client for the Kraken Futures book feed.
+///
+/// Products are kept in a shared `BookManager` keyed by product ID, so the
+/// same book analytics work for spot and futures. Book messages are applied
+/// in `seq` order; on a gap the product is resubscribed for a fresh snapshot.
+/// Dropped or silent connections are retried like `KrakenWsClient` does.
+pub struct FuturesClient {
+    requests: mpsc::UnboundedSender<FuturesRequest>,
+    events: mpsc::UnboundedReceiver<Result<FuturesClientEvent, Error>>,
+    books: Arc<RwLock<BookManager>>,
+}
+
+impl FuturesClient {
+    pub async fn connect(url: &str) -> Result<Self, Error> {
+        FuturesClient::connect_with_config(url, FuturesConfig::default()).await
+    }
+
+    // Only the first connection attempt is reported as an error; later drops are retried
+    pub async fn connect_with_config(url: &str, config: FuturesConfig) -> Result<Self, Error> {
+        config.heartbeat.validate()?;
+        let socket = driver::connect(url).await?;
+
+        let (requests_tx, requests_rx) = mpsc::unbounded_channel();
+        let (events_tx, events_rx) = mpsc::unbounded_channel();
+        let books = Arc::new(RwLock::new(BookManager::new()));
+
+        let connection = Connection {
+            events: events_tx,
+            books: Arc::clone(&books),
+            sequencer: Sequencer::default(),
+        };
+        let driver = Driver::new(
+            socket,
+            config.reconnect,
+            config.heartbeat,
+            requests_rx,
+            connection,
+        );
+        tokio::spawn(driver.run());
+
+        Ok(FuturesClient {
+            requests: requests_tx,
+            events: events_rx,
+            books,
+        })
+    }
+
+    // Futures books are unbounded and levels are never re-sent, so the book keeps
+    // every level and `depth` only limits what it returns
+    pub fn subscribe_book(&self, product_id: &str, depth: usize) -> Result<(), Error> {
+        self.books
+            .write()
+            .unwrap()
+            .add_book(product_id, OrderBook::retaining_all(depth));
+        self.send(FuturesRequest::Subscribe {
+            feed: "book".to_string(),
+            product_ids: vec![product_id.to_string()],
+        })
+    }
+
+    pub fn books(&self) -> RwLockReadGuard<'_, BookManager> {
+        self.books.read().unwrap()
+    }
+
+    fn send(&self, request: FuturesRequest) -> Result<(), Error> {
+        self.requests
+            .send(request)
+            .map_err(|_| Error::ConnectionClosed)
+    }
+}
+
+impl Stream for FuturesClient {
+    type Item = Result<FuturesClientEvent, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.events.poll_recv(cx)
+    }
+}
+
+// The futures session, run by a `Driver` that owns the socket
+struct Connection {
+    events: mpsc::UnboundedSender<Result<FuturesClientEvent, Error>>,
+    books: Arc<RwLock<BookManager>>,
+    sequencer: Sequencer,
+}
+
+impl Session for Connection {
+    type Command = FuturesRequest;
+
+    fn command(&mut self, request: FuturesRequest, link: &mut Link) -> Result<(), Error> {
+        queue(link, &request)
+    }
+
+    fn text(&mut self, text: &str, link: &mut Link) -> Result<(), Error> {
+        let message = match serde_json::from_str::<FuturesMessage>(text) {
+            Ok(message) => message,
+            Err(e) => {
+                // A message we cannot parse is reported but does not end the connection
+                self.emit(Err(e.into()));
+                return Ok(());
+            }
+        };
+
+        let applied = self
+            .sequencer
+            .apply(&mut self.books.write().unwrap(), &message);
+        match (message, applied) {
+            (FuturesMessage::Event(event), _) => self.emit(Ok(FuturesClientEvent::Event(event))),
+            (
+                _,
+                Some(FuturesClientEvent::SequenceGap {
+                    product_id,
+                    expected,
+                    received,
+                }),
+            ) => {
+                resync(link, &product_id)?;
+                self.emit(Ok(FuturesClientEvent::SequenceGap {
+                    product_id,
+                    expected,
+                    received,
+                }));
+            }
+            (_, Some(event)) => self.emit(Ok(event)),
+            (_, None) => (),
+        }
+        Ok(())
+    }
+
+    // The endpoint answers WebSocket pings with pongs, which count as traffic
+    fn ping(&mut self, link: &mut Link) -> Result<(), Error> {
+        link.send(Message::Ping(Vec::new()));
+        Ok(())
+    }
+
+    fn disconnected(&mut self, reason: &Error) {
+        self.emit(Ok(FuturesClientEvent::Disconnected {
+            reason: reason.to_string(),
+        }));
+        self.books.write().unwrap().reset_all();
+        self.sequencer.reset();
+    }
+
+    fn report(&mut self, error: Error) {
+        self.emit(Err(error));
+    }
+
+    // Subscribes every tracked book again
+    fn replay(&mut self, link: &mut Link) -> Result<(), Error> {
+        let mut product_ids: Vec<String> = self
+            .books
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(product_id, _)| product_id.to_string())
+            .collect();
+        product_ids.sort();
+        if product_ids.is_empty() {
+            return Ok(());
+        }
+        queue(
+            link,
+            &FuturesRequest::Subscribe {
+                feed: "book".to_string(),
+                product_ids,
+            },
+        )
+    }
+
+    fn reconnected(&mut self) {
+        self.emit(Ok(FuturesClientEvent::Reconnected));
+    }
+
+    fn abandoned(&self) -> bool {
+        self.events.is_closed()
+    }
+}
+
+impl Connection {
+    // Events are dropped once the client stops listening
+    fn emit(&self, event: Result<FuturesClientEvent, Error>) {
+        let _ = self.events.send(event);
+    }
+}
+
+fn queue(link: &mut Link, request: &FuturesRequest) -> Result<(), Error> {
+    link.send_text(serde_json::to_string(request)?);
+    Ok(())
+}
+
+// Subscribing again makes Kraken send a fresh snapshot for the product
+fn resync(link: &mut Link, product_id: &str) -> Result<(), Error> {
+    let product_ids = vec![product_id.to_string()];
+    queue(
+        link,
+        &FuturesRequest::Unsubscribe {
+            feed: "book".to_string(),
+            product_ids: product_ids.clone(),
+        },
+    )?;
+    queue(
+        link,
+        &FuturesRequest::Subscribe {
+            feed: "book".to_string(),
+            product_ids,
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{bind, next_json, send_json};
+    use futures_util::StreamExt;
+    use serde_json::json;
+
+    fn dec(s: &str) -> Decimal {
+        s.parse().unwrap()
+    }
+
+    fn parse(value: Value) -> FuturesMessage {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn snapshot(seq: u64) -> Value {
+        json!({"feed": "book_snapshot", "product_id": "PI_XBTUSD", "timestamp": 1612269825817u64, "seq": seq, "tickSize": null,
+            "bids": [{"price": 34892.5, "qty": 6385}, {"price": 34892.0, "qty": 10924}],
+            "asks": [{"price": 34911.5, "qty": 20598}, {"price": 34912.0, "qty": 2300}]})
+    }
+
+    fn delta(seq: u64, side: &str, price: f64, qty: f64) -> Value {
+        json!({"feed": "book", "product_id": "PI_XBTUSD", "side": side, "seq": seq, "price": price, "qty": qty, "timestamp": 1612269953629u64})
+    }
+
+    #[test]
+    fn test_parse_messages() {
+        assert_eq!(
+            parse(json!({"event": "subscribed", "feed": "book", "product_ids": ["PI_XBTUSD"]})),
+            FuturesMessage::Event(FuturesEvent::Subscribed {
+                feed: "book".to_string(),
+                product_ids: vec!["PI_XBTUSD".to_string()],
+            })
+        );
+        assert_eq!(
+            parse(json!({"event": "error", "message": "Invalid product id"})),
+            FuturesMessage::Event(FuturesEvent::Error {
+                message: "Invalid product id".to_string()
+            })
+        );
+        match parse(delta(326094134, "sell", 34981.0, 0.0)) {
+            FuturesMessage::BookDelta(delta) => {
+                assert_eq!(delta.side, Side::Sell);
+                assert!(delta.qty.is_zero());
+                assert_eq!(seconds(delta.timestamp), dec("1612269953.629"));
+            }
+            other => panic!("expected a book delta, got {:?}", other),
+        }
+        assert!(serde_json::from_value::<FuturesMessage>(json!({"feed": "ticker"})).is_err());
+        assert_eq!(
+            serde_json::to_value(FuturesRequest::Subscribe {
+                feed: "book".to_string(),
+                product_ids: vec!["PI_XBTUSD".to_string()]
+            })
+            .unwrap(),
+            json!({"event": "subscribe", "feed": "book", "product_ids": ["PI_XBTUSD"]})
+        );
+    }
+
+    #[test]
+    fn test_sequence_gap_resets_book() {
+        let mut books = BookManager::new();
+        books.add_book("PI_XBTUSD", OrderBook::retaining_all(1));
+        let mut sequencer = Sequencer::default();
+
+        sequencer.apply(&mut books, &parse(snapshot(10)));
+        assert_eq!(books.get("PI_XBTUSD").unwrap().bids().count(), 1);
+        assert_eq!(
+            sequencer.apply(&mut books, &parse(delta(11, "buy", 34892.5, 0.0))),
+            Some(FuturesClientEvent::Book(BookEvent::Updated {
+                pair: "PI_XBTUSD".to_string()
+            }))
+        );
+        // The second level was kept beyond the depth and moves up
+        let book = books.get("PI_XBTUSD").unwrap();
+        assert_eq!(book.bids().next().unwrap().price, dec("34892"));
+
+        // Replayed deltas are ignored, skipped ones are not
+        assert_eq!(
+            sequencer.apply(&mut books, &parse(delta(11, "buy", 34892.5, 0.0))),
+            None
+        );
+        assert_eq!(
+            sequencer.apply(&mut books, &parse(delta(13, "sell", 34911.5, 1.0))),
+            Some(FuturesClientEvent::SequenceGap {
+                product_id: "PI_XBTUSD".to_string(),
+                expected: 12,
+                received: 13,
+            })
+        );
+        assert_eq!(
+            books.get("PI_XBTUSD").unwrap().status(),
+            BookStatus::AwaitingSnapshot
+        );
+        assert_eq!(
+            sequencer.apply(&mut books, &parse(delta(14, "sell", 34911.5, 1.0))),
+            Some(FuturesClientEvent::Book(BookEvent::Stale {
+                pair: "PI_XBTUSD".to_string()
+            }))
+        );
+    }
+
+    async fn next_event(client: &mut FuturesClient) -> FuturesClientEvent {
+        client.next().await.unwrap().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_client_resubscribes_after_gap() {
+        let (url, listener) = bind().await;
+        let server = tokio::spawn(async move {
+            let mut ws = crate::test_support::accept(&listener).await;
+            assert_eq!(
+                next_json(&mut ws).await,
+                json!({"event": "subscribe", "feed": "book", "product_ids": ["PI_XBTUSD"]})
+            );
+            send_json(
+                &mut ws,
+                json!({"event": "subscribed", "feed": "book", "product_ids": ["PI_XBTUSD"]}),
+            )
+            .await;
+            send_json(&mut ws, snapshot(100)).await;
+            send_json(&mut ws, delta(101, "sell", 34911.5, 15000.0)).await;
+            send_json(&mut ws, delta(103, "sell", 34911.5, 0.0)).await;
+
+            assert_eq!(next_json(&mut ws).await["event"], "unsubscribe");
+            assert_eq!(next_json(&mut ws).await["event"], "subscribe");
+            send_json(&mut ws, snapshot(200)).await;
+            ws
+        });
+
+        let mut client = FuturesClient::connect(&url).await.unwrap();
+        client.subscribe_book("PI_XBTUSD", 100).unwrap();
+        assert!(matches!(
+            next_event(&mut client).await,
+            FuturesClientEvent::Event(FuturesEvent::Subscribed { .. })
+        ));
+        assert!(matches!(
+            next_event(&mut client).await,
+            FuturesClientEvent::Book(BookEvent::Initialized { .. })
+        ));
+        assert!(matches!(
+            next_event(&mut client).await,
+            FuturesClientEvent::Book(BookEvent::Updated { .. })
+        ));
+        assert_eq!(
+            next_event(&mut client).await,
+            FuturesClientEvent::SequenceGap {
+                product_id: "PI_XBTUSD".to_string(),
+                expected: 102,
+                received: 103,
+            }
+        );
+        assert!(matches!(
+            next_event(&mut client).await,
+            FuturesClientEvent::Book(BookEvent::Initialized { .. })
+        ));
+
+        {
+            let books = client.books();
+            let book = books.get("PI_XBTUSD").unwrap();
+            assert_eq!(book.status(), BookStatus::Synced);
+            assert_eq!(book.stats().snapshots, 2);
+            assert_eq!(book.asks().next().unwrap().volume, dec("20598"));
+        }
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_client_reconnects_after_silence() {
+        let (url, listener) = bind().await;
+        let server = tokio::spawn(async move {
+            // First connection never answers the pings, second one gets the replay
+            let silent = crate::test_support::accept(&listener).await;
+            let mut ws = crate::test_support::accept(&listener).await;
+            assert_eq!(
+                next_json(&mut ws).await,
+                json!({"event": "subscribe", "feed": "book", "product_ids": ["PI_XBTUSD"]})
+            );
+            (silent, ws)
+        });
+
+        let config = FuturesConfig {
+            reconnect: ReconnectConfig {
+                initial_delay: Duration::from_millis(10),
+                max_delay: Duration::from_millis(50),
+                max_attempts: None,
+            },
+            heartbeat: HeartbeatConfig::new(Duration::from_millis(50), Duration::from_millis(100))
+                .unwrap(),
+        };
+        let mut client = FuturesClient::connect_with_config(&url, config)
+            .await
+            .unwrap();
+        client.subscribe_book("PI_XBTUSD", 10).unwrap();
+
+        assert_eq!(
+            next_event(&mut client).await,
+            FuturesClientEvent::Disconnected {
+                reason: Error::HeartbeatTimeout(Duration::from_millis(100)).to_string()
+            }
+        );
+        assert_eq!(
+            next_event(&mut client).await,
+            FuturesClientEvent::Reconnected
+        );
+        server.await.unwrap();
+    }
+}
@@ -0,0 +1,157 @@
+use crate::error::Error;
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Liveness checks for an open connection.
+///
+/// Pings have to go out well within the silence window: their pongs are what
+/// keeps a connection without any subscriptions alive.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeartbeatConfig {
+    // How often a `ping` request is sent
+    pub ping_interval: Duration,
+    // The connection is considered dead after this long without any message
+    pub silence_timeout: Duration,
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        // Kraken sends a heartbeat every second when a subscription is idle
+        HeartbeatConfig {
+            ping_interval: Duration::from_secs(5),
+            silence_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+impl HeartbeatConfig {
+    pub fn new(ping_interval: Duration, silence_timeout: Duration) -> Result<Self, Error> {
+        let config = HeartbeatConfig {
+            ping_interval,
+            silence_timeout,
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    // Also checked on connect, since the fields can be set directly
+    pub fn validate(&self) -> Result<(), Error> {
+        // A zero silence timeout fails the check below as well
+        if self.ping_interval.is_zero() {
+            return Err(Error::Config("ping interval is zero".to_string()));
+        }
+        if self.ping_interval >= self.silence_timeout {
+            return Err(Error::Config(format!(
+                "ping interval {:?} is not shorter than the silence timeout {:?}",
+                self.ping_interval, self.silence_timeout
+            )));
+        }
+        Ok(())
+    }
+}
+
+// Tracks the last received message and the pings waiting for a pong
+#[derive(Debug)]
+pub struct Liveness {
+    silence_timeout: Duration,
+    last_message: Instant,
+    pending_pings: HashMap<u64, Instant>,
+}
+
+impl Liveness {
+    pub fn new(silence_timeout: Duration) -> Self {
+        Liveness {
+            silence_timeout,
+            last_message: Instant::now(),
+            pending_pings: HashMap::new(),
+        }
+    }
+
+    // Any heartbeat, event or data message proves the connection is alive
+    pub fn record_message(&mut self) {
+        self.last_message = Instant::now();
+    }
+
+    // When the connection is declared dead unless another message arrives
+    pub fn deadline(&self) -> Instant {
+        self.last_message + self.silence_timeout
+    }
+
+    pub fn ping_sent(&mut self, reqid: u64) {
+        let now = Instant::now();
+        // Pings that never got an answer within the silence window are forgotten
+        let silence_timeout = self.silence_timeout;
+        self.pending_pings
+            .retain(|_, sent| now.duration_since(*sent) < silence_timeout);
+        self.pending_pings.insert(reqid, now);
+    }
+
+    // Round-trip time for one of our pings, or `None` for a pong we did not ask for
+    pub fn pong_received(&mut self, reqid: u64) -> Option<Duration> {
+        self.pending_pings.remove(&reqid).map(|sent| sent.elapsed())
+    }
+
+    // Starts over on a new connection
+    pub fn reset(&mut self) {
+        self.last_message = Instant::now();
+        self.pending_pings.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_rejects_pings_outside_silence_window() {
+        assert!(HeartbeatConfig::default().validate().is_ok());
+        let second = Duration::from_secs(1);
+        assert!(HeartbeatConfig::new(second, 2 * second).is_ok());
+        assert!(matches!(
+            HeartbeatConfig::new(second, second),
+            Err(Error::Config(_))
+        ));
+        assert!(matches!(
+            HeartbeatConfig::new(3 * second, second),
+            Err(Error::Config(_))
+        ));
+        // Zero intervals would panic in the connection task instead
+        assert!(matches!(
+            HeartbeatConfig::new(Duration::ZERO, second),
+            Err(Error::Config(_))
+        ));
+        assert!(matches!(
+            HeartbeatConfig::new(Duration::ZERO, Duration::ZERO),
+            Err(Error::Config(_))
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_deadline_moves_with_messages() {
+        let mut liveness = Liveness::new(Duration::from_secs(5));
+        let start = Instant::now();
+        assert_eq!(liveness.deadline(), start + Duration::from_secs(5));
+
+        tokio::time::advance(Duration::from_secs(3)).await;
+        liveness.record_message();
+        assert_eq!(liveness.deadline(), start + Duration::from_secs(8));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_pong_matches_ping() {
+        let mut liveness = Liveness::new(Duration::from_secs(5));
+        liveness.ping_sent(1);
+        tokio::time::advance(Duration::from_millis(40)).await;
+        assert_eq!(liveness.pong_received(1), Some(Duration::from_millis(40)));
+        assert_eq!(liveness.pong_received(1), None);
+        assert_eq!(liveness.pong_received(99), None);
+
+        // Unanswered pings expire once the silence window has passed
+        liveness.ping_sent(2);
+        tokio::time::advance(Duration::from_secs(6)).await;
+        liveness.ping_sent(3);
+        assert_eq!(liveness.pong_received(2), None);
+        assert!(liveness.pong_received(3).is_some());
+    }
+}
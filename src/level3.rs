@@ -0,0 +1,446 @@
+use crate::decimal::Decimal;
+use crate::messages::{
+    BookEntry, BookSnapshot, Level3Change, Level3Order, Level3Snapshot, Level3Update, OrderEvent,
+    Side,
+};
+use crate::order_book::{checksum_field, BookStatus, ChecksumMismatch, Level, OrderBook};
+use crc32fast::Hasher;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+
+/// A book of individual orders from the v2 `level3` channel.
+///
+/// Each price level keeps its orders in queue order, so the position of a
+/// resting order can be read off directly. The aggregated `Level` view and a
+/// plain `OrderBook`, with its level-2 checksum, are derived from the orders.
+#[derive(Debug, Clone)]
+pub struct Level3Book {
+    // Price levels kept, as for `OrderBook`
+    depth: usize,
+    price_decimals: u32,
+    lot_decimals: u32,
+    bids: BTreeMap<Reverse<Decimal>, Vec<Level3Order>>,
+    asks: BTreeMap<Decimal, Vec<Level3Order>>,
+    // Where each order rests, for modifies, deletes and queue lookups
+    index: HashMap<String, (Side, Decimal)>,
+    status: BookStatus,
+}
+
+/// Where an order sits in the queue of its price level.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueuePosition {
+    pub side: Side,
+    pub price: Decimal,
+    // Orders that fill before this one; 0 means it is first in line
+    pub orders_ahead: usize,
+    pub volume_ahead: Decimal,
+    // Everything resting at the price, including this order
+    pub level_volume: Decimal,
+}
+
+impl Level3Book {
+    pub fn new(depth: usize, price_decimals: u32, lot_decimals: u32) -> Self {
+        Level3Book {
+            depth,
+            price_decimals,
+            lot_decimals,
+            bids: BTreeMap::new(),
+            asks: BTreeMap::new(),
+            index: HashMap::new(),
+            status: BookStatus::AwaitingSnapshot,
+        }
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn status(&self) -> BookStatus {
+        self.status
+    }
+
+    pub fn reset(&mut self) {
+        self.bids.clear();
+        self.asks.clear();
+        self.index.clear();
+        self.status = BookStatus::AwaitingSnapshot;
+    }
+
+    pub fn initialize(&mut self, snapshot: &Level3Snapshot) {
+        self.reset();
+        for order in &snapshot.asks {
+            self.add(Side::Sell, order.clone());
+        }
+        for order in &snapshot.bids {
+            self.add(Side::Buy, order.clone());
+        }
+        self.truncate_to_depth();
+        self.status = BookStatus::Synced;
+    }
+
+    // Initializes the book, then validates the checksum the snapshot carries (if any)
+    pub fn apply_snapshot(&mut self, snapshot: &Level3Snapshot) -> Result<(), ChecksumMismatch> {
+        self.initialize(snapshot);
+        self.check(snapshot.checksum)
+    }
+
+    // Applies the changes, then validates the checksum they carry (if any).
+    // A mismatch marks the book stale; updates are dropped until the next snapshot.
+    pub fn apply_update(&mut self, update: &Level3Update) -> Result<(), ChecksumMismatch> {
+        if self.status != BookStatus::Synced {
+            return Ok(());
+        }
+        for change in &update.asks {
+            self.apply(Side::Sell, change);
+        }
+        for change in &update.bids {
+            self.apply(Side::Buy, change);
+        }
+        self.truncate_to_depth();
+        self.check(update.checksum)
+    }
+
+    fn check(&mut self, checksum: Option<u32>) -> Result<(), ChecksumMismatch> {
+        let Some(expected) = checksum else {
+            return Ok(());
+        };
+        let calculated = self.calculate_checksum();
+        if calculated == expected {
+            Ok(())
+        } else {
+            self.status = BookStatus::Stale;
+            Err(ChecksumMismatch {
+                expected,
+                calculated,
+            })
+        }
+    }
+
+    pub fn order(&self, order_id: &str) -> Option<&Level3Order> {
+        let (side, price) = self.index.get(order_id)?;
+        self.level(*side, *price)?
+            .iter()
+            .find(|order| order.order_id == order_id)
+    }
+
+    pub fn queue_position(&self, order_id: &str) -> Option<QueuePosition> {
+        let &(side, price) = self.index.get(order_id)?;
+        let queue = self.level(side, price)?;
+        let orders_ahead = queue.iter().position(|order| order.order_id == order_id)?;
+        Some(QueuePosition {
+            side,
+            price,
+            orders_ahead,
+            volume_ahead: queue[..orders_ahead].iter().map(|order| order.volume).sum(),
+            level_volume: queue.iter().map(|order| order.volume).sum(),
+        })
+    }
+
+    // Orders at a price, first in line first
+    pub fn orders_at(&self, side: Side, price: Decimal) -> &[Level3Order] {
+        self.level(side, price).map_or(&[], Vec::as_slice)
+    }
+
+    // Aggregated asks from best (lowest) to worst
+    pub fn asks(&self) -> impl Iterator<Item = Level> + '_ {
+        self.asks
+            .iter()
+            .map(|(&price, queue)| aggregate(price, queue))
+    }
+
+    // Aggregated bids from best (highest) to worst
+    pub fn bids(&self) -> impl Iterator<Item = Level> + '_ {
+        self.bids
+            .iter()
+            .map(|(&Reverse(price), queue)| aggregate(price, queue))
+    }
+
+    // The aggregated book, whose `calculate_checksum` is the level-2 checksum
+    pub fn to_order_book(&self) -> OrderBook {
+        let entry = |level: Level| BookEntry {
+            price: level.price,
+            volume: level.volume,
+            timestamp: None,
+            republish: false,
+        };
+        let mut book =
+            OrderBook::with_precision(self.depth, self.price_decimals, self.lot_decimals);
+        if self.status == BookStatus::Synced {
+            book.initialize(&BookSnapshot {
+                asks: self.asks().map(entry).collect(),
+                bids: self.bids().map(entry).collect(),
+                checksum: None,
+            });
+        }
+        book
+    }
+
+    // Like the level-2 checksum over the top 10 price levels of each side, asks
+    // first, but with the price and volume of every order in queue order
+    pub fn calculate_checksum(&self) -> u32 {
+        let mut input_string = String::new();
+        let asks = self.asks.values().take(10);
+        let bids = self.bids.values().take(10);
+        for order in asks.chain(bids).flatten() {
+            input_string.push_str(&checksum_field(order.price, Some(self.price_decimals)));
+            input_string.push_str(&checksum_field(order.volume, Some(self.lot_decimals)));
+        }
+
+        let mut hasher = Hasher::new();
+        hasher.update(input_string.as_bytes());
+        hasher.finalize()
+    }
+
+    fn apply(&mut self, side: Side, change: &Level3Change) {
+        let order = &change.order;
+        match change.event {
+            OrderEvent::Add => {
+                self.remove(&order.order_id);
+                self.add(side, order.clone());
+            }
+            // A modify at the same price keeps the order's place in the queue
+            OrderEvent::Modify => match self.index.get(&order.order_id) {
+                Some(&(_, price)) if price == order.price => {
+                    if let Some(resting) = self.level_mut(side, price).and_then(|queue| {
+                        queue
+                            .iter_mut()
+                            .find(|resting| resting.order_id == order.order_id)
+                    }) {
+                        resting.volume = order.volume;
+                        resting.timestamp = order.timestamp;
+                    }
+                }
+                _ => {
+                    self.remove(&order.order_id);
+                    self.add(side, order.clone());
+                }
+            },
+            OrderEvent::Delete => self.remove(&order.order_id),
+        }
+    }
+
+    // Joins the back of its price level
+    fn add(&mut self, side: Side, order: Level3Order) {
+        self.index
+            .insert(order.order_id.clone(), (side, order.price));
+        match side {
+            Side::Buy => self
+                .bids
+                .entry(Reverse(order.price))
+                .or_default()
+                .push(order),
+            Side::Sell => self.asks.entry(order.price).or_default().push(order),
+        }
+    }
+
+    fn remove(&mut self, order_id: &str) {
+        let Some((side, price)) = self.index.remove(order_id) else {
+            return;
+        };
+        let emptied = match self.level_mut(side, price) {
+            Some(queue) => {
+                queue.retain(|order| order.order_id != order_id);
+                queue.is_empty()
+            }
+            None => false,
+        };
+        if emptied {
+            match side {
+                Side::Buy => self.bids.remove(&Reverse(price)),
+                Side::Sell => self.asks.remove(&price),
+            };
+        }
+    }
+
+    fn level(&self, side: Side, price: Decimal) -> Option<&Vec<Level3Order>> {
+        match side {
+            Side::Buy => self.bids.get(&Reverse(price)),
+            Side::Sell => self.asks.get(&price),
+        }
+    }
+
+    fn level_mut(&mut self, side: Side, price: Decimal) -> Option<&mut Vec<Level3Order>> {
+        match side {
+            Side::Buy => self.bids.get_mut(&Reverse(price)),
+            Side::Sell => self.asks.get_mut(&price),
+        }
+    }
+
+    // Drops the worst price levels beyond the subscribed depth, with their orders
+    fn truncate_to_depth(&mut self) {
+        while self.asks.len() > self.depth {
+            if let Some((_, queue)) = self.asks.pop_last() {
+                self.forget(&queue);
+            }
+        }
+        while self.bids.len() > self.depth {
+            if let Some((_, queue)) = self.bids.pop_last() {
+                self.forget(&queue);
+            }
+        }
+    }
+
+    fn forget(&mut self, queue: &[Level3Order]) {
+        for order in queue {
+            self.index.remove(&order.order_id);
+        }
+    }
+}
+
+fn aggregate(price: Decimal, queue: &[Level3Order]) -> Level {
+    Level {
+        price,
+        volume: queue.iter().map(|order| order.volume).sum(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dec(s: &str) -> Decimal {
+        s.parse().unwrap()
+    }
+
+    fn order(order_id: &str, price: &str, volume: &str) -> Level3Order {
+        Level3Order {
+            order_id: order_id.to_string(),
+            price: dec(price),
+            volume: dec(volume),
+            timestamp: dec("1702026000.123456"),
+        }
+    }
+
+    fn change(event: OrderEvent, order_id: &str, price: &str, volume: &str) -> Level3Change {
+        Level3Change {
+            event,
+            order: order(order_id, price, volume),
+        }
+    }
+
+    fn crc(input: &str) -> u32 {
+        let mut hasher = Hasher::new();
+        hasher.update(input.as_bytes());
+        hasher.finalize()
+    }
+
+    fn synced_book() -> Level3Book {
+        let mut book = Level3Book::new(10, 1, 8);
+        book.initialize(&Level3Snapshot {
+            asks: vec![
+                order("OA1", "45285.2", "0.5"),
+                order("OA2", "45285.2", "1.25"),
+                order("OA3", "45286.0", "2"),
+            ],
+            bids: vec![
+                order("OB1", "45283.5", "0.1"),
+                order("OB2", "45283.5", "0.2"),
+                order("MINE", "45283.5", "0.3"),
+                order("OB3", "45280.0", "4"),
+            ],
+            checksum: None,
+        });
+        book
+    }
+
+    #[test]
+    fn test_queue_position() {
+        let mut book = synced_book();
+        assert_eq!(
+            book.queue_position("MINE"),
+            Some(QueuePosition {
+                side: Side::Buy,
+                price: dec("45283.5"),
+                orders_ahead: 2,
+                volume_ahead: dec("0.3"),
+                level_volume: dec("0.6"),
+            })
+        );
+
+        // A partial fill ahead keeps its place, a cancel ahead moves us up
+        book.apply_update(&Level3Update {
+            bids: vec![
+                change(OrderEvent::Modify, "OB1", "45283.5", "0.05"),
+                change(OrderEvent::Delete, "OB2", "45283.5", "0.2"),
+                change(OrderEvent::Add, "OB4", "45283.5", "1"),
+            ],
+            ..Level3Update::default()
+        })
+        .unwrap();
+        let position = book.queue_position("MINE").unwrap();
+        assert_eq!(position.orders_ahead, 1);
+        assert_eq!(position.volume_ahead, dec("0.05"));
+        assert_eq!(position.level_volume, dec("1.35"));
+        assert_eq!(book.orders_at(Side::Buy, dec("45283.5"))[2].order_id, "OB4");
+        assert_eq!(book.queue_position("OB2"), None);
+
+        // Moving to another price loses priority
+        book.apply_update(&Level3Update {
+            bids: vec![change(OrderEvent::Modify, "OB1", "45280.0", "0.05")],
+            ..Level3Update::default()
+        })
+        .unwrap();
+        assert_eq!(book.queue_position("MINE").unwrap().orders_ahead, 0);
+        assert_eq!(book.queue_position("OB1").unwrap().orders_ahead, 1);
+    }
+
+    #[test]
+    fn test_aggregated_view_and_checksums() {
+        let mut book = synced_book();
+        let asks: Vec<Level> = book.asks().collect();
+        assert_eq!(
+            asks,
+            vec![
+                Level {
+                    price: dec("45285.2"),
+                    volume: dec("1.75")
+                },
+                Level {
+                    price: dec("45286.0"),
+                    volume: dec("2")
+                },
+            ]
+        );
+
+        // Level 2 sums each price, level 3 lists every order
+        let aggregated = book.to_order_book();
+        assert_eq!(aggregated.status(), BookStatus::Synced);
+        assert_eq!(
+            aggregated.calculate_checksum(),
+            crc("45285217500000045286020000000045283560000000452800400000000")
+        );
+        let level3 = "45285250000000452852125000000452860200000000452835100000004528352000000045283530000000452800400000000";
+        assert_eq!(book.calculate_checksum(), crc(level3));
+
+        let mismatch = book
+            .apply_update(&Level3Update {
+                asks: vec![change(OrderEvent::Delete, "OA3", "45286.0", "2")],
+                checksum: Some(crc(level3)),
+                ..Level3Update::default()
+            })
+            .unwrap_err();
+        assert_eq!(mismatch.expected, crc(level3));
+        assert_eq!(book.status(), BookStatus::Stale);
+    }
+
+    #[test]
+    fn test_truncates_to_depth() {
+        let mut book = Level3Book::new(1, 1, 8);
+        book.initialize(&Level3Snapshot {
+            asks: vec![order("OA1", "10.0", "1"), order("OA2", "11.0", "1")],
+            bids: vec![],
+            checksum: None,
+        });
+        assert_eq!(book.asks().count(), 1);
+        assert!(book.order("OA2").is_none());
+
+        // A better ask pushes the worst level out
+        book.apply_update(&Level3Update {
+            asks: vec![change(OrderEvent::Add, "OA0", "9.5", "1")],
+            ..Level3Update::default()
+        })
+        .unwrap();
+        assert!(book.order("OA1").is_none());
+        assert_eq!(book.order("OA0").unwrap().price, dec("9.5"));
+    }
+}
@@ -0,0 +1,30 @@
+pub mod auth;
+pub mod book_manager;
+pub mod candles;
+pub mod client;
+pub mod dead_mans_switch;
+pub mod decimal;
+mod driver;
+pub mod error;
+pub mod futures;
+pub mod heartbeat;
+pub mod level3;
+pub mod messages;
+pub mod nonce;
+pub mod order_book;
+pub mod orders;
+pub mod private_messages;
+pub mod private_rest;
+pub mod rate_limit;
+pub mod reconnect;
+pub mod rest;
+pub mod spreads;
+pub mod subscriptions;
+pub mod ticker_cache;
+pub mod v2;
+
+#[cfg(test)]
+mod test_support;
+
+pub use client::{ClientEvent, KrakenWsClient};
+pub use error::Error;
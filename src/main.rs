@@ -1,98 +1,102 @@
-use futures_util::{SinkExt, StreamExt};
-use serde_json::Value;
-use tokio;
-use tokio_tungstenite::{connect_async, tungstenite::protocol::Message};
-use url::Url;
+use futures_util::StreamExt;
 
-mod order_book;
+use kraken_rust::book_manager::BookEvent;
+use kraken_rust::client::{ClientEvent, KrakenWsClient, PUBLIC_URL};
+use kraken_rust::messages::EventMessage;
+use kraken_rust::order_book::OrderBook;
+
+#[cfg(test)]
 mod test_test;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let url = Url::parse("wss://ws.kraken.com/")?;
-
-    // Connect to the WebSocket server
-    let (ws_stream, response) = connect_async(url).await?;
-
-    // Now, correctly split ws_stream into a writer and reader parts
-    let (mut write, read) = ws_stream.split();
-
-    // Initialize an empty order book
-    let mut order_book = order_book::OrderBook::new(10);
-
-    // Proceed to send messages and read responses
-    // For example, to send a subscription message:
-    // let subscribe_command = serde_json::json!({
-    //     "event": "subscribe",
-    //     "pair": ["XBT/USD"],
-    //     "subscription": {"name": "trade"}
-    // }).to_string();
-
-    let subscribe_command = serde_json::json!({
-        "event": "subscribe",
-        "pair": ["XBT/USD"],
-        "subscription": {
-            "name": "book",
-            "depth": 10
-    }
-    })
-    .to_string();
+    let mut client = KrakenWsClient::connect(PUBLIC_URL).await?;
 
-    // Send the subscription message
-    write.send(Message::Text(subscribe_command)).await?;
+    // One book per pair with its own depth; v1 checksums need no pair precision
+    client.subscribe_book("XBT/USD", OrderBook::new(10)).await?;
+    client.subscribe_book("ETH/USD", OrderBook::new(10)).await?;
+    client.subscribe_book("ETH/XBT", OrderBook::new(25)).await?;
+    client.subscribe_trades(&["XBT/USD", "ETH/USD"]).await?;
+    client.subscribe_ticker(&["XBT/USD", "ETH/USD"]).await?;
+    client.subscribe_ohlc(&["XBT/USD"], 1).await?;
+    client.subscribe_spread(&["XBT/USD"]).await?;
 
-    // Process incoming messages
-    let mut read = read;
-    while let Some(message) = read.next().await {
-        match message {
-            Ok(Message::Text(text)) => {
-                let json_msg: Value = serde_json::from_str(&text)?;
-                if let Some(update) = json_msg.get(1) {
-                    if update.get("b").is_some() || update.get("a").is_some() {
-                        process_order_book_update(&mut order_book, &update);
-                    } else if update.get("bs").is_some() && update.get("as").is_some() {
-                        // Initialize the order book if bs and as are in the keys
-                        order_book.initialize(&update);
-                    } else {
-                        println!("Unknown message: {}", update);
-                    }
+    // Process incoming events
+    while let Some(event) = client.next().await {
+        match event {
+            Ok(ClientEvent::Book(BookEvent::Initialized { pair })) => {
+                println!("Order book initialized for {}", pair)
+            }
+            Ok(ClientEvent::Book(BookEvent::Updated { pair })) => {
+                if let Some(book) = client.books().get(&pair) {
+                    println!("{}: {}", pair, book);
                 }
-                // Assuming heartbeat messages can be distinguished by a lack of "b" or "a" keys
-                else {
-                    // Handle the heartbeat
-                    process_heartbeat(&json_msg);
+            }
+            Ok(ClientEvent::Book(BookEvent::Stale { pair })) => {
+                println!(
+                    "Order book for {} is stale, waiting for a new snapshot",
+                    pair
+                )
+            }
+            Ok(ClientEvent::Book(BookEvent::ChecksumMismatch { pair, mismatch })) => {
+                println!("Checksum does not match for {}! ({})", pair, mismatch);
+                for (pair, book) in client.books().iter() {
+                    println!("{}: {:?} {:?}", pair, book.status(), book.stats());
                 }
             }
-            Ok(_) => (), // Other message types
-            Err(e) => return Err(e.into()),
-        }
-    }
-
-    Ok(())
-}
-
-// Handle extra messages
-fn process_heartbeat(message: &Value) {
-    println!("Heartbeat received: {}", message);
-}
-
-fn process_order_book_update(order_book: &mut order_book::OrderBook, message: &Value) {
-    order_book.update(message);
-
-    if let Some(checksum_str) = message.get("c").and_then(Value::as_str) {
-        match checksum_str.parse::<u32>() {
-            Ok(checksum) => {
-                if order_book.calculate_checksum().eq(&checksum) {
-                    println!("Checksum as u32: {}", checksum);
-                } else {
-                    println!("Checksum does not match!");
+            Ok(ClientEvent::Trades { pair, trades }) => {
+                for trade in trades {
+                    println!(
+                        "{} trade: {:?} {} @ {}",
+                        pair, trade.side, trade.volume, trade.price
+                    );
                 }
             }
-            Err(e) => {
-                eprintln!("Failed to parse checksum as u32: {}", e);
+            Ok(ClientEvent::CandleClosed {
+                pair,
+                interval,
+                candle,
+            }) => {
+                println!(
+                    "{} {}m candle closed: O {} H {} L {} C {} V {}",
+                    pair,
+                    interval,
+                    candle.open,
+                    candle.high,
+                    candle.low,
+                    candle.close,
+                    candle.volume
+                );
             }
+            Ok(ClientEvent::Candle { .. }) | Ok(ClientEvent::Spread { .. }) => (),
+            Ok(ClientEvent::SpreadDrift { pair, drift }) => {
+                println!("Spread and book disagree for {}: {}", pair, drift)
+            }
+            Ok(ClientEvent::Ticker { pair }) => {
+                if let Some(ticker) = client.tickers().get(&pair) {
+                    println!(
+                        "{} ticker: bid {} ask {} last {} (24h volume {})",
+                        pair,
+                        ticker.bid.price,
+                        ticker.ask.price,
+                        ticker.close.price,
+                        ticker.volume.last_24h
+                    );
+                }
+            }
+            // Only sent on authenticated connections
+            Ok(ClientEvent::OwnTrades { .. })
+            | Ok(ClientEvent::OpenOrders { .. })
+            | Ok(ClientEvent::Level3(_)) => (),
+            Ok(ClientEvent::Event(EventMessage::Heartbeat)) => println!("Heartbeat received"),
+            Ok(ClientEvent::Event(event)) => println!("Event received: {:?}", event),
+            Ok(ClientEvent::Connection(event)) => println!("Connection: {:?}", event),
+            Ok(ClientEvent::Channel(message)) => {
+                println!("Unhandled channel message: {:?}", message)
+            }
+            Err(e) => eprintln!("Error: {}", e),
         }
-    } else {
-        eprintln!("'c' key not found or not a string");
     }
+
+    Ok(())
 }
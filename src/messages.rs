@@ -0,0 +1,898 @@
+use crate::decimal::Decimal;
+use crate::orders::{AddOrder, EditOrder};
+use crate::private_messages::PrivateMessage;
+use serde::de::{self, Deserializer};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Any message received on the Kraken v1 WebSocket.
+///
+/// Kraken sends JSON objects for events and JSON arrays for channel data,
+/// so the variant is picked from the top-level shape. Private channel frames
+/// start with their payload array instead of a channel ID.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WsMessage {
+    Event(EventMessage),
+    Channel(ChannelMessage),
+    Private(PrivateMessage),
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "event", rename_all = "camelCase")]
+pub enum EventMessage {
+    Heartbeat,
+    #[serde(rename_all = "camelCase")]
+    SystemStatus {
+        #[serde(rename = "connectionID")]
+        connection_id: Option<u64>,
+        status: String,
+        version: Option<String>,
+    },
+    #[serde(rename_all = "camelCase")]
+    SubscriptionStatus {
+        #[serde(rename = "channelID")]
+        channel_id: Option<u64>,
+        channel_name: Option<String>,
+        pair: Option<String>,
+        reqid: Option<u64>,
+        status: String,
+        subscription: Option<Subscription>,
+        error_message: Option<String>,
+    },
+    Pong {
+        reqid: Option<u64>,
+    },
+    #[serde(rename_all = "camelCase")]
+    Error {
+        error_message: String,
+        reqid: Option<u64>,
+    },
+    #[serde(rename_all = "camelCase")]
+    AddOrderStatus {
+        reqid: Option<u64>,
+        status: String,
+        txid: Option<String>,
+        descr: Option<String>,
+        error_message: Option<String>,
+    },
+    #[serde(rename_all = "camelCase")]
+    EditOrderStatus {
+        reqid: Option<u64>,
+        status: String,
+        txid: Option<String>,
+        #[serde(rename = "originaltxid")]
+        original_txid: Option<String>,
+        descr: Option<String>,
+        error_message: Option<String>,
+    },
+    #[serde(rename_all = "camelCase")]
+    CancelOrderStatus {
+        reqid: Option<u64>,
+        status: String,
+        error_message: Option<String>,
+    },
+    #[serde(rename_all = "camelCase")]
+    CancelAllStatus {
+        reqid: Option<u64>,
+        status: String,
+        count: Option<u32>,
+        error_message: Option<String>,
+    },
+    #[serde(rename_all = "camelCase")]
+    CancelAllOrdersAfterStatus {
+        reqid: Option<u64>,
+        status: String,
+        current_time: Option<String>,
+        trigger_time: Option<String>,
+        error_message: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Subscription {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub depth: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interval: Option<u32>,
+    // Only sent for private channels, and never echoed back by Kraken
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+}
+
+impl Subscription {
+    // Private channels are only available on the authenticated endpoint
+    pub fn is_private(&self) -> bool {
+        matches!(self.name.as_str(), "ownTrades" | "openOrders")
+    }
+
+    // Private channels and the v2 `level3` book are sent with the WebSockets token
+    pub fn needs_token(&self) -> bool {
+        self.is_private() || self.name == "level3"
+    }
+}
+
+/// Requests sent to Kraken.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "camelCase")]
+pub enum Request {
+    Subscribe {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        reqid: Option<u64>,
+        // Empty for private channels
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        pair: Vec<String>,
+        subscription: Subscription,
+    },
+    Unsubscribe {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        reqid: Option<u64>,
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        pair: Vec<String>,
+        subscription: Subscription,
+    },
+    Ping {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        reqid: Option<u64>,
+    },
+    // Order requests need the WebSockets token, which the connection fills in
+    AddOrder {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        token: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        reqid: Option<u64>,
+        #[serde(flatten)]
+        order: AddOrder,
+    },
+    EditOrder {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        token: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        reqid: Option<u64>,
+        #[serde(flatten)]
+        edit: EditOrder,
+    },
+    CancelOrder {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        token: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        reqid: Option<u64>,
+        txid: Vec<String>,
+    },
+    CancelAll {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        token: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        reqid: Option<u64>,
+    },
+    // Dead man's switch: cancel everything after `timeout` seconds, 0 disables it
+    CancelAllOrdersAfter {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        token: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        reqid: Option<u64>,
+        timeout: u64,
+    },
+}
+
+// Candle lengths, in minutes, that the `ohlc` channel accepts
+pub const OHLC_INTERVALS: [u32; 9] = [1, 5, 15, 30, 60, 240, 1440, 10080, 21600];
+
+/// Channels a client can subscribe to. `OwnTrades` and `OpenOrders` are
+/// private and take no pairs; `Level3` is only available over v2.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Channel {
+    Book { depth: usize },
+    Trade,
+    Ticker,
+    Spread,
+    Ohlc { interval: u32 },
+    OwnTrades,
+    OpenOrders,
+    Level3 { depth: usize },
+}
+
+impl Channel {
+    pub fn subscription(&self) -> Subscription {
+        let (name, depth, interval) = match *self {
+            Channel::Book { depth } => ("book", Some(depth), None),
+            Channel::Trade => ("trade", None, None),
+            Channel::Ticker => ("ticker", None, None),
+            Channel::Spread => ("spread", None, None),
+            Channel::Ohlc { interval } => ("ohlc", None, Some(interval)),
+            Channel::OwnTrades => ("ownTrades", None, None),
+            Channel::OpenOrders => ("openOrders", None, None),
+            Channel::Level3 { depth } => ("level3", Some(depth), None),
+        };
+        Subscription {
+            name: name.to_string(),
+            depth,
+            interval,
+            token: None,
+        }
+    }
+}
+
+/// A channel data frame: `[channelID, payload, channelName, pair]`.
+///
+/// Book updates touching both sides arrive with two payload objects,
+/// `[channelID, {"a": [...]}, {"b": [...], "c": "..."}, channelName, pair]`,
+/// and are merged into a single `BookUpdate`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChannelMessage {
+    pub channel_id: u64,
+    pub channel_name: String,
+    pub pair: String,
+    pub data: ChannelData,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChannelData {
+    BookSnapshot(BookSnapshot),
+    BookUpdate(BookUpdate),
+    Trade(Vec<Trade>),
+    Ticker(Box<Ticker>),
+    Spread(Spread),
+    Ohlc(Ohlc),
+    Level3Snapshot(Level3Snapshot),
+    Level3Update(Level3Update),
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct BookSnapshot {
+    // REST `Depth` spells the sides out
+    #[serde(rename = "as", alias = "asks")]
+    pub asks: Vec<BookEntry>,
+    #[serde(rename = "bs", alias = "bids")]
+    pub bids: Vec<BookEntry>,
+    // Only v2 snapshots carry one; v1 checksums come with the first update
+    #[serde(rename = "c", default, deserialize_with = "deserialize_checksum")]
+    pub checksum: Option<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Deserialize)]
+pub struct BookUpdate {
+    #[serde(rename = "a", default)]
+    pub asks: Vec<BookEntry>,
+    #[serde(rename = "b", default)]
+    pub bids: Vec<BookEntry>,
+    #[serde(rename = "c", default, deserialize_with = "deserialize_checksum")]
+    pub checksum: Option<u32>,
+}
+
+/// `[price, volume, timestamp]`, with a trailing `"r"` when Kraken republishes a level.
+/// Levels from sources without a timestamp (e.g. hand-built snapshots) leave it out.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(from = "RawBookEntry")]
+pub struct BookEntry {
+    pub price: Decimal,
+    pub volume: Decimal,
+    pub timestamp: Option<Decimal>,
+    pub republish: bool,
+}
+
+/// One resting order from the v2 `level3` channel.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Level3Order {
+    pub order_id: String,
+    pub price: Decimal,
+    pub volume: Decimal,
+    // Seconds since the epoch, like v1 book entries
+    pub timestamp: Decimal,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OrderEvent {
+    Add,
+    // The order kept its place in the queue with a new volume, e.g. after a partial fill
+    Modify,
+    Delete,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Level3Change {
+    pub event: OrderEvent,
+    pub order: Level3Order,
+}
+
+// Orders on each side, best price first and in queue order within a price
+#[derive(Debug, Clone, PartialEq)]
+pub struct Level3Snapshot {
+    pub asks: Vec<Level3Order>,
+    pub bids: Vec<Level3Order>,
+    pub checksum: Option<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Level3Update {
+    pub asks: Vec<Level3Change>,
+    pub bids: Vec<Level3Change>,
+    pub checksum: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct RawBookEntry(
+    Decimal,
+    Decimal,
+    #[serde(default)] Option<Decimal>,
+    #[serde(default)] Option<String>,
+);
+
+/// `[price, volume, time, side, orderType, misc]`, followed by the trade ID over REST
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(from = "RawTrade")]
+pub struct Trade {
+    pub price: Decimal,
+    pub volume: Decimal,
+    pub time: Decimal,
+    pub side: Side,
+    pub order_type: OrderType,
+    pub misc: String,
+    // v1 WebSocket trades are not numbered
+    pub trade_id: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct RawTrade(
+    Decimal,
+    Decimal,
+    Decimal,
+    Side,
+    OrderType,
+    String,
+    #[serde(default)] Option<u64>,
+);
+
+// Public trades abbreviate the side, private feeds and order requests spell it out
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Side {
+    #[serde(rename(deserialize = "b", serialize = "buy"), alias = "buy")]
+    Buy,
+    #[serde(rename(deserialize = "s", serialize = "sell"), alias = "sell")]
+    Sell,
+}
+
+// v1 abbreviates the order type of public trades, v2 spells it out
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum OrderType {
+    #[serde(rename = "m", alias = "market")]
+    Market,
+    #[serde(rename = "l", alias = "limit")]
+    Limit,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Ticker {
+    #[serde(rename = "a")]
+    pub ask: TickerQuote,
+    #[serde(rename = "b")]
+    pub bid: TickerQuote,
+    #[serde(rename = "c")]
+    pub close: TickerClose,
+    #[serde(rename = "v")]
+    pub volume: TodayAnd24h<Decimal>,
+    #[serde(rename = "p")]
+    pub vwap: TodayAnd24h<Decimal>,
+    #[serde(rename = "t")]
+    pub trades: TodayAnd24h<u64>,
+    #[serde(rename = "l")]
+    pub low: TodayAnd24h<Decimal>,
+    #[serde(rename = "h")]
+    pub high: TodayAnd24h<Decimal>,
+    #[serde(rename = "o")]
+    pub open: TodayAnd24h<Decimal>,
+}
+
+/// `[price, wholeLotVolume, lotVolume]`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(from = "RawTickerQuote")]
+pub struct TickerQuote {
+    pub price: Decimal,
+    pub whole_lot_volume: u64,
+    pub lot_volume: Decimal,
+}
+
+// The WebSocket sends the whole lot volume as a number, REST `Ticker` as a string
+#[derive(Deserialize)]
+struct RawTickerQuote(
+    Decimal,
+    #[serde(deserialize_with = "deserialize_count")] u64,
+    Decimal,
+);
+
+/// `[price, lotVolume]`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(from = "(Decimal, Decimal)")]
+pub struct TickerClose {
+    pub price: Decimal,
+    pub lot_volume: Decimal,
+}
+
+/// `[today, last24Hours]`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(from = "(T, T)")]
+pub struct TodayAnd24h<T> {
+    pub today: T,
+    pub last_24h: T,
+}
+
+/// `[bid, ask, timestamp, bidVolume, askVolume]`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(from = "RawSpread")]
+pub struct Spread {
+    pub bid: Decimal,
+    pub ask: Decimal,
+    pub timestamp: Decimal,
+    pub bid_volume: Option<Decimal>,
+    pub ask_volume: Option<Decimal>,
+}
+
+#[derive(Deserialize)]
+struct RawSpread(
+    Decimal,
+    Decimal,
+    Decimal,
+    #[serde(default)] Option<Decimal>,
+    #[serde(default)] Option<Decimal>,
+);
+
+/// `[time, etime, open, high, low, close, vwap, volume, count]`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(from = "RawOhlc")]
+pub struct Ohlc {
+    pub time: Decimal,
+    pub end_time: Decimal,
+    pub open: Decimal,
+    pub high: Decimal,
+    pub low: Decimal,
+    pub close: Decimal,
+    pub vwap: Decimal,
+    pub volume: Decimal,
+    pub count: u64,
+}
+
+#[derive(Deserialize)]
+struct RawOhlc(
+    Decimal,
+    Decimal,
+    Decimal,
+    Decimal,
+    Decimal,
+    Decimal,
+    Decimal,
+    Decimal,
+    u64,
+);
+
+impl<'de> Deserialize<'de> for WsMessage {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = Value::deserialize(deserializer)?;
+        if value.is_object() {
+            EventMessage::deserialize(value)
+                .map(WsMessage::Event)
+                .map_err(de::Error::custom)
+        } else if value.get(0).is_some_and(Value::is_array) {
+            PrivateMessage::deserialize(value)
+                .map(WsMessage::Private)
+                .map_err(de::Error::custom)
+        } else {
+            ChannelMessage::deserialize(value)
+                .map(WsMessage::Channel)
+                .map_err(de::Error::custom)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for ChannelMessage {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut elements = Vec::<Value>::deserialize(deserializer)?;
+        if elements.len() < 4 {
+            return Err(de::Error::invalid_length(
+                elements.len(),
+                &"[channelID, payload, channelName, pair]",
+            ));
+        }
+
+        let pair = take_string(elements.pop())?;
+        let channel_name = take_string(elements.pop())?;
+        let channel_id = elements[0]
+            .as_u64()
+            .ok_or_else(|| de::Error::custom("channelID is not an integer"))?;
+        let payloads: Vec<Value> = elements.drain(1..).collect();
+
+        let data = parse_channel_data(&channel_name, payloads).map_err(de::Error::custom)?;
+
+        Ok(ChannelMessage {
+            channel_id,
+            channel_name,
+            pair,
+            data,
+        })
+    }
+}
+
+fn take_string<E: de::Error>(value: Option<Value>) -> Result<String, E> {
+    match value {
+        Some(Value::String(s)) => Ok(s),
+        other => Err(E::custom(format!("expected a string, found {:?}", other))),
+    }
+}
+
+// Channel names carry the subscription options as a suffix, e.g. `book-10` or `ohlc-5`
+fn parse_channel_data(channel_name: &str, mut payloads: Vec<Value>) -> Result<ChannelData, String> {
+    let name = channel_name.split('-').next().unwrap_or(channel_name);
+    if name == "book" {
+        return parse_book_data(payloads)
+            .map_err(|e| format!("invalid {} payload: {}", channel_name, e));
+    }
+    if payloads.len() != 1 {
+        return Err(format!(
+            "expected one {} payload, found {}",
+            channel_name,
+            payloads.len()
+        ));
+    }
+
+    let payload = payloads.remove(0);
+    let data = match name {
+        "trade" => serde_json::from_value(payload).map(ChannelData::Trade),
+        "ticker" => serde_json::from_value(payload).map(ChannelData::Ticker),
+        "spread" => serde_json::from_value(payload).map(ChannelData::Spread),
+        "ohlc" => serde_json::from_value(payload).map(ChannelData::Ohlc),
+        _ => return Err(format!("unknown channel: {}", channel_name)),
+    };
+    data.map_err(|e| format!("invalid {} payload: {}", channel_name, e))
+}
+
+// A snapshot is a single object; updates may be split into an asks and a bids object
+fn parse_book_data(payloads: Vec<Value>) -> Result<ChannelData, serde_json::Error> {
+    if let [payload] = payloads.as_slice() {
+        if payload.get("as").is_some() || payload.get("bs").is_some() {
+            return serde_json::from_value(payload.clone()).map(ChannelData::BookSnapshot);
+        }
+    }
+
+    let mut merged = BookUpdate::default();
+    for payload in payloads {
+        let update: BookUpdate = serde_json::from_value(payload)?;
+        merged.asks.extend(update.asks);
+        merged.bids.extend(update.bids);
+        merged.checksum = update.checksum.or(merged.checksum);
+    }
+    Ok(ChannelData::BookUpdate(merged))
+}
+
+// Kraken sends the book checksum as a decimal string
+fn deserialize_checksum<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<u32>, D::Error> {
+    let checksum = String::deserialize(deserializer)?;
+    checksum.parse().map(Some).map_err(de::Error::custom)
+}
+
+fn deserialize_count<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    match Value::deserialize(deserializer)? {
+        Value::String(count) => count.parse().map_err(de::Error::custom),
+        count => count
+            .as_u64()
+            .ok_or_else(|| de::Error::custom(format!("expected a count, got {}", count))),
+    }
+}
+
+impl From<RawBookEntry> for BookEntry {
+    fn from(RawBookEntry(price, volume, timestamp, flag): RawBookEntry) -> Self {
+        BookEntry {
+            price,
+            volume,
+            timestamp,
+            republish: flag.as_deref() == Some("r"),
+        }
+    }
+}
+
+impl From<RawTrade> for Trade {
+    fn from(RawTrade(price, volume, time, side, order_type, misc, trade_id): RawTrade) -> Self {
+        Trade {
+            price,
+            volume,
+            time,
+            side,
+            order_type,
+            misc,
+            trade_id,
+        }
+    }
+}
+
+impl From<RawTickerQuote> for TickerQuote {
+    fn from(RawTickerQuote(price, whole_lot_volume, lot_volume): RawTickerQuote) -> Self {
+        TickerQuote {
+            price,
+            whole_lot_volume,
+            lot_volume,
+        }
+    }
+}
+
+impl From<(Decimal, Decimal)> for TickerClose {
+    fn from((price, lot_volume): (Decimal, Decimal)) -> Self {
+        TickerClose { price, lot_volume }
+    }
+}
+
+impl<T> From<(T, T)> for TodayAnd24h<T> {
+    fn from((today, last_24h): (T, T)) -> Self {
+        TodayAnd24h { today, last_24h }
+    }
+}
+
+impl From<RawSpread> for Spread {
+    fn from(RawSpread(bid, ask, timestamp, bid_volume, ask_volume): RawSpread) -> Self {
+        Spread {
+            bid,
+            ask,
+            timestamp,
+            bid_volume,
+            ask_volume,
+        }
+    }
+}
+
+impl From<RawOhlc> for Ohlc {
+    fn from(RawOhlc(time, end_time, open, high, low, close, vwap, volume, count): RawOhlc) -> Self {
+        Ohlc {
+            time,
+            end_time,
+            open,
+            high,
+            low,
+            close,
+            vwap,
+            volume,
+            count,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dec(s: &str) -> Decimal {
+        s.parse().unwrap()
+    }
+
+    fn parse(text: &str) -> WsMessage {
+        serde_json::from_str(text).unwrap()
+    }
+
+    fn parse_channel(text: &str) -> ChannelMessage {
+        match parse(text) {
+            WsMessage::Channel(message) => message,
+            other => panic!("expected a channel message, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_events() {
+        assert_eq!(
+            parse(r#"{"event":"heartbeat"}"#),
+            WsMessage::Event(EventMessage::Heartbeat)
+        );
+        assert_eq!(
+            parse(
+                r#"{"connectionID":8628615390848610000,"event":"systemStatus","status":"online","version":"1.0.0"}"#
+            ),
+            WsMessage::Event(EventMessage::SystemStatus {
+                connection_id: Some(8628615390848610000),
+                status: "online".to_string(),
+                version: Some("1.0.0".to_string()),
+            })
+        );
+        assert_eq!(
+            parse(r#"{"event":"pong","reqid":42}"#),
+            WsMessage::Event(EventMessage::Pong { reqid: Some(42) })
+        );
+        assert_eq!(
+            parse(r#"{"errorMessage":"Malformed request","event":"error"}"#),
+            WsMessage::Event(EventMessage::Error {
+                error_message: "Malformed request".to_string(),
+                reqid: None,
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_subscription_status() {
+        let message = parse(
+            r#"{"channelID":10001,"channelName":"book-10","event":"subscriptionStatus","pair":"XBT/USD","status":"subscribed","subscription":{"depth":10,"name":"book"}}"#,
+        );
+        assert_eq!(
+            message,
+            WsMessage::Event(EventMessage::SubscriptionStatus {
+                channel_id: Some(10001),
+                channel_name: Some("book-10".to_string()),
+                pair: Some("XBT/USD".to_string()),
+                reqid: None,
+                status: "subscribed".to_string(),
+                subscription: Some(Subscription {
+                    name: "book".to_string(),
+                    depth: Some(10),
+                    interval: None,
+                    token: None,
+                }),
+                error_message: None,
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_book_snapshot_and_update() {
+        let snapshot = parse_channel(
+            r#"[0,{"as":[["5541.30000","2.50700000","1534614248.123678"]],"bs":[["5541.20000","1.52900000","1534614248.765567"]]},"book-100","XBT/USD"]"#,
+        );
+        assert_eq!(snapshot.channel_name, "book-100");
+        assert_eq!(snapshot.pair, "XBT/USD");
+        match snapshot.data {
+            ChannelData::BookSnapshot(book) => {
+                assert_eq!(book.asks[0].price, dec("5541.3"));
+                assert_eq!(book.bids[0].volume, dec("1.529"));
+                assert_eq!(book.asks[0].timestamp, Some(dec("1534614248.123678")));
+            }
+            other => panic!("expected a book snapshot, got {:?}", other),
+        }
+
+        // Entries without a timestamp, as in hand-built books
+        let entries: Vec<BookEntry> =
+            serde_json::from_str(r#"[["5541.30000","2.50700000"]]"#).unwrap();
+        assert_eq!(entries[0].timestamp, None);
+        assert!(!entries[0].republish);
+
+        let update = parse_channel(
+            r#"[1234,{"a":[["5541.30000","2.50700000","1534614248.456738","r"]],"c":"974942666"},"book-10","XBT/USD"]"#,
+        );
+        assert_eq!(update.channel_id, 1234);
+        match update.data {
+            ChannelData::BookUpdate(book) => {
+                assert!(book.asks[0].republish);
+                assert!(book.bids.is_empty());
+                assert_eq!(book.checksum, Some(974942666));
+            }
+            other => panic!("expected a book update, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_trade_ticker_spread_ohlc() {
+        let trade = parse_channel(
+            r#"[0,[["5541.20000","0.15850568","1534614057.321597","s","l",""],["6060.00000","0.02455000","1534614057.324998","b","m",""]],"trade","XBT/USD"]"#,
+        );
+        match trade.data {
+            ChannelData::Trade(trades) => {
+                assert_eq!(trades.len(), 2);
+                assert_eq!(trades[0].side, Side::Sell);
+                assert_eq!(trades[0].order_type, OrderType::Limit);
+                assert_eq!(trades[1].side, Side::Buy);
+                assert_eq!(trades[1].volume, dec("0.02455"));
+            }
+            other => panic!("expected trades, got {:?}", other),
+        }
+
+        let ticker = parse_channel(
+            r#"[0,{"a":["5525.40000",1,"1.000"],"b":["5525.10000",1,"1.000"],"c":["5525.10000","0.00398963"],"h":["5783.00000","5783.00000"],"l":["5505.00000","5505.00000"],"o":["5760.70000","5763.40000"],"p":["5631.44067","5653.78939"],"t":[11493,16267],"v":["2634.11501494","3591.17907851"]},"ticker","XBT/USD"]"#,
+        );
+        match ticker.data {
+            ChannelData::Ticker(ticker) => {
+                assert_eq!(ticker.ask.price, dec("5525.4"));
+                assert_eq!(ticker.ask.whole_lot_volume, 1);
+                assert_eq!(ticker.close.lot_volume, dec("0.00398963"));
+                assert_eq!(ticker.trades.last_24h, 16267);
+                assert_eq!(ticker.open.today, dec("5760.7"));
+            }
+            other => panic!("expected a ticker, got {:?}", other),
+        }
+
+        let spread = parse_channel(
+            r#"[0,["5698.40000","5700.00000","1542057299.545897","1.01234567","0.98765432"],"spread","XBT/USD"]"#,
+        );
+        match spread.data {
+            ChannelData::Spread(spread) => {
+                assert_eq!(spread.bid, dec("5698.4"));
+                assert_eq!(spread.ask_volume, Some(dec("0.98765432")));
+            }
+            other => panic!("expected a spread, got {:?}", other),
+        }
+
+        let ohlc = parse_channel(
+            r#"[42,["1542057314.748456","1542057360.435743","3586.70000","3586.70000","3586.60000","3586.60000","3586.68894","0.03373000",5],"ohlc-5","XBT/USD"]"#,
+        );
+        assert_eq!(ohlc.channel_name, "ohlc-5");
+        match ohlc.data {
+            ChannelData::Ohlc(candle) => {
+                assert_eq!(candle.close, dec("3586.6"));
+                assert_eq!(candle.count, 5);
+            }
+            other => panic!("expected a candle, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_rejects_unknown_channel() {
+        assert!(serde_json::from_str::<WsMessage>(r#"[0,{},"mystery","XBT/USD"]"#).is_err());
+        assert!(serde_json::from_str::<WsMessage>(r#"[0,{}]"#).is_err());
+    }
+
+    #[test]
+    fn test_serialize_requests() {
+        let subscribe = Request::Subscribe {
+            reqid: Some(7),
+            pair: vec!["XBT/USD".to_string(), "ETH/USD".to_string()],
+            subscription: Channel::Book { depth: 25 }.subscription(),
+        };
+        assert_eq!(
+            serde_json::to_value(&subscribe).unwrap(),
+            serde_json::json!({
+                "event": "subscribe",
+                "reqid": 7,
+                "pair": ["XBT/USD", "ETH/USD"],
+                "subscription": {"name": "book", "depth": 25}
+            })
+        );
+
+        let unsubscribe = Request::Unsubscribe {
+            reqid: None,
+            pair: vec!["XBT/USD".to_string()],
+            subscription: Channel::Ohlc { interval: 5 }.subscription(),
+        };
+        assert_eq!(
+            serde_json::to_value(&unsubscribe).unwrap(),
+            serde_json::json!({
+                "event": "unsubscribe",
+                "pair": ["XBT/USD"],
+                "subscription": {"name": "ohlc", "interval": 5}
+            })
+        );
+
+        assert_eq!(
+            serde_json::to_value(Request::Ping { reqid: Some(3) }).unwrap(),
+            serde_json::json!({"event": "ping", "reqid": 3})
+        );
+
+        let mut subscription = Channel::OwnTrades.subscription();
+        assert!(subscription.is_private());
+        subscription.token = Some("WW91ciBhdXRoZW50aWNhdGlvbiB0b2tlbiBnb2VzIGhlcmUu".to_string());
+        assert_eq!(
+            serde_json::to_value(Request::Subscribe {
+                reqid: None,
+                pair: Vec::new(),
+                subscription,
+            })
+            .unwrap(),
+            serde_json::json!({
+                "event": "subscribe",
+                "subscription": {"name": "ownTrades", "token": "WW91ciBhdXRoZW50aWNhdGlvbiB0b2tlbiBnb2VzIGhlcmUu"}
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_combined_book_update() {
+        let update = parse_channel(
+            r#"[1234,{"a":[["5541.30000","2.50700000","1534614248.456738"],["5542.50000","0.40100000","1534614248.456738"]]},{"b":[["5541.30000","0.00000000","1534614335.345903"]],"c":"974942666"},"book-10","XBT/USD"]"#,
+        );
+        assert_eq!(update.channel_id, 1234);
+        assert_eq!(update.channel_name, "book-10");
+        assert_eq!(update.pair, "XBT/USD");
+        match update.data {
+            ChannelData::BookUpdate(book) => {
+                assert_eq!(book.asks.len(), 2);
+                assert_eq!(book.asks[1].price, dec("5542.5"));
+                assert_eq!(book.bids.len(), 1);
+                assert!(book.bids[0].volume.is_zero());
+                assert_eq!(book.checksum, Some(974942666));
+            }
+            other => panic!("expected a book update, got {:?}", other),
+        }
+    }
+}
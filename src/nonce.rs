@@ -0,0 +1,258 @@
+use crate::error::Error;
+use fs2::FileExt;
+use std::fmt::Debug;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Hands out the nonces private REST calls are signed with.
+///
+/// Kraken rejects a call with `EAPI:Invalid nonce` unless its nonce is larger
+/// than every nonce the key has used before, or within the key's nonce window
+/// of the largest one. All providers count milliseconds since the epoch, so
+/// they can replace each other on the same key.
+pub trait NonceProvider: Debug + Send + Sync {
+    fn next_nonce(&self) -> Result<u64, Error>;
+
+    // Whether `next_nonce` waits on locks or I/O, so async callers have to run
+    // it on the blocking thread pool
+    fn blocks(&self) -> bool {
+        false
+    }
+}
+
+/// Strictly increasing nonces within one process.
+///
+/// Enough when a single process uses the key. Clients sharing a key have to
+/// share the provider; by default every `PrivateRestClient` shares one.
+#[derive(Debug)]
+pub struct AtomicNonce {
+    last: AtomicU64,
+    clock: fn() -> u64,
+}
+
+impl Default for AtomicNonce {
+    fn default() -> Self {
+        AtomicNonce::new()
+    }
+}
+
+impl AtomicNonce {
+    pub const fn new() -> Self {
+        AtomicNonce::with_clock(unix_millis)
+    }
+
+    const fn with_clock(clock: fn() -> u64) -> Self {
+        AtomicNonce {
+            last: AtomicU64::new(0),
+            clock,
+        }
+    }
+
+    // The clock, bumped by one when it has not moved since the last nonce;
+    // `None` instead of running more than `max_lead` ahead of the clock
+    fn issue(&self, max_lead: u64) -> Option<u64> {
+        let now = (self.clock)();
+        let next = |last: u64| now.max(last + 1);
+        self.last
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |last| {
+                Some(next(last)).filter(|nonce| nonce - now <= max_lead)
+            })
+            .ok()
+            .map(next)
+    }
+}
+
+impl NonceProvider for AtomicNonce {
+    fn next_nonce(&self) -> Result<u64, Error> {
+        Ok(self.issue(u64::MAX).expect("no lead limit"))
+    }
+}
+
+/// Strictly increasing nonces across processes, through a shared file.
+///
+/// The last nonce is kept in the file and updated under an exclusive lock,
+/// so every process pointing at the same path can use the key. Each call
+/// blocks on the lock and a small file write; `PrivateRestClient` makes it
+/// on the blocking thread pool.
+#[derive(Debug, Clone)]
+pub struct FileNonce {
+    path: PathBuf,
+}
+
+impl FileNonce {
+    // The file is created on first use
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        FileNonce {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn update(&self, file: &mut File) -> Result<u64, Error> {
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)?;
+        let last: u64 = match contents.trim() {
+            "" => 0,
+            last => last.parse().map_err(|_| {
+                Error::Nonce(format!("{} does not hold a nonce", self.path.display()))
+            })?,
+        };
+
+        let nonce = unix_millis().max(last + 1);
+        file.seek(SeekFrom::Start(0))?;
+        file.set_len(0)?;
+        write!(file, "{}", nonce)?;
+        file.sync_data()?;
+        Ok(nonce)
+    }
+}
+
+impl NonceProvider for FileNonce {
+    fn next_nonce(&self) -> Result<u64, Error> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&self.path)?;
+        file.lock_exclusive()?;
+        let nonce = self.update(&mut file);
+        // Closing the file would release the lock as well
+        let _ = file.unlock();
+        nonce
+    }
+
+    fn blocks(&self) -> bool {
+        true
+    }
+}
+
+/// Nonces for keys with a nonce window configured on Kraken.
+///
+/// Kraken then also accepts nonces up to `window` below the largest one seen,
+/// so processes can share the key without coordinating as long as their
+/// clocks agree. This provider counts like `AtomicNonce` but never runs more
+/// than `window` ahead of its clock, which a burst of calls within the same
+/// milliseconds would otherwise do; such calls fail with `Error::Nonce`
+/// instead of pushing other processes' nonces out of the window.
+#[derive(Debug)]
+pub struct WindowNonce {
+    counter: AtomicNonce,
+    window: Duration,
+}
+
+impl WindowNonce {
+    // `window` should be the setting of the API key
+    pub fn new(window: Duration) -> Self {
+        WindowNonce {
+            counter: AtomicNonce::new(),
+            window,
+        }
+    }
+
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+}
+
+impl NonceProvider for WindowNonce {
+    fn next_nonce(&self) -> Result<u64, Error> {
+        let max_lead = self.window.as_millis().saturating_sub(1) as u64;
+        self.counter.issue(max_lead).ok_or_else(|| {
+            Error::Nonce(format!(
+                "next nonce would run past the {:?} nonce window",
+                self.window
+            ))
+        })
+    }
+}
+
+static PROCESS_NONCE: OnceLock<Arc<AtomicNonce>> = OnceLock::new();
+
+// The provider clients fall back to, shared by the whole process
+pub(crate) fn process_nonce() -> Arc<AtomicNonce> {
+    Arc::clone(PROCESS_NONCE.get_or_init(|| Arc::new(AtomicNonce::new())))
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    // A clock that stands still, as if every call landed in the same millisecond
+    fn frozen_clock() -> u64 {
+        1_700_000_000_000
+    }
+
+    #[test]
+    fn test_atomic_nonce_increases_within_a_millisecond() {
+        let nonces = AtomicNonce::with_clock(frozen_clock);
+        let first = nonces.next_nonce().unwrap();
+        assert_eq!(first, frozen_clock());
+        assert_eq!(nonces.next_nonce().unwrap(), first + 1);
+        assert_eq!(nonces.next_nonce().unwrap(), first + 2);
+
+        let window = WindowNonce {
+            counter: AtomicNonce::with_clock(frozen_clock),
+            window: Duration::from_millis(3),
+        };
+        let issued: Vec<u64> = (0..3).map(|_| window.next_nonce().unwrap()).collect();
+        assert_eq!(issued, vec![first, first + 1, first + 2]);
+        // A fourth nonce would be 3ms ahead, on the edge of the window
+        assert!(matches!(window.next_nonce(), Err(Error::Nonce(_))));
+        assert!(matches!(window.next_nonce(), Err(Error::Nonce(_))));
+
+        let real = AtomicNonce::new().next_nonce().unwrap();
+        assert!(real >= unix_millis() - 1000);
+    }
+
+    #[test]
+    fn test_file_nonce_is_shared_between_providers() {
+        let path = std::env::temp_dir().join(format!("kraken-nonce-{}-test", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        // A counter far ahead of the clock, e.g. left by a process using another scheme
+        std::fs::write(&path, "99999999999999").unwrap();
+
+        // Separate providers stand in for separate processes
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let nonces = FileNonce::new(&path);
+                thread::spawn(move || {
+                    (0..25)
+                        .map(|_| nonces.next_nonce().unwrap())
+                        .collect::<Vec<u64>>()
+                })
+            })
+            .collect();
+        let mut issued: Vec<u64> = threads
+            .into_iter()
+            .flat_map(|thread| thread.join().unwrap())
+            .collect();
+        issued.sort_unstable();
+        assert_eq!(issued.first(), Some(&100000000000000));
+        assert_eq!(issued.last(), Some(&100000000000099));
+        issued.dedup();
+        assert_eq!(issued.len(), 100);
+
+        std::fs::write(&path, "garbage").unwrap();
+        assert!(matches!(
+            FileNonce::new(&path).next_nonce(),
+            Err(Error::Nonce(_))
+        ));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
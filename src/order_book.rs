@@ -1,179 +1,245 @@
+use crate::decimal::Decimal;
+use crate::messages::{BookSnapshot, BookUpdate};
 use crc32fast::Hasher;
-use serde_json::Value;
-use std::cmp::Ordering;
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
 use std::fmt;
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Level {
-    price: f64,
-    volume: f64,
+    pub price: Decimal,
+    pub volume: Decimal,
 }
 
+// Both sides are kept in price-keyed maps ordered best-first, so updates and
+// truncation are O(log n) instead of re-sorting the whole side
 #[derive(Debug, Clone)]
 pub struct OrderBook {
     depth: usize,
-    bids: Vec<Level>,
-    asks: Vec<Level>,
+    // Price and volume decimals the checksum input is formatted at. Only set for
+    // v2 books, whose numbers arrive without formatting; v1 checksums cover the
+    // strings exactly as sent, so `None` uses each value's received scale.
+    precision: Option<(u32, u32)>,
+    // Keeps levels beyond `depth` instead of dropping them; reads still stop at `depth`
+    retain_all: bool,
+    bids: BTreeMap<Reverse<Decimal>, Decimal>,
+    asks: BTreeMap<Decimal, Decimal>,
+    status: BookStatus,
+    stats: BookStats,
+}
+
+// Whether the book can be trusted; only a fresh snapshot moves it back to `Synced`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BookStatus {
+    AwaitingSnapshot,
+    Synced,
+    Stale,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BookStats {
+    pub snapshots: u64,
+    pub updates: u64,
+    pub checksum_mismatches: u64,
+    // Snapshots that replaced a stale book
+    pub resyncs: u64,
+    // Updates received while waiting for a snapshot
+    pub dropped_updates: u64,
+}
+
+// The book no longer matches the checksum Kraken sent with an update
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChecksumMismatch {
+    pub expected: u32,
+    pub calculated: u32,
 }
 
 impl OrderBook {
+    // Creates a book for the v1 feed, checksummed over the strings as received
     pub fn new(depth: usize) -> Self {
+        OrderBook::create(depth, None)
+    }
+
+    // Creates a book checksummed at the pair's price and volume decimals, for
+    // v2 feeds where the original formatting is lost
+    pub fn with_precision(depth: usize, price_decimals: u32, lot_decimals: u32) -> Self {
+        OrderBook::create(depth, Some((price_decimals, lot_decimals)))
+    }
+
+    // Creates a book for feeds that send each level once and never re-send it,
+    // like futures: a level below the top `depth` can move up when better ones
+    // are deleted, so every level is kept and only reads stop at `depth`
+    pub fn retaining_all(depth: usize) -> Self {
         OrderBook {
-            depth,
-            bids: Vec::with_capacity(depth),
-            asks: Vec::with_capacity(depth),
+            retain_all: true,
+            ..OrderBook::create(depth, None)
         }
     }
 
-    // Initializes the order book with a snapshot
-    pub fn initialize(&mut self, snapshot: &Value) {
-        // Parsing asks
-        if let Some(asks) = snapshot.get("as").and_then(Value::as_array) {
-            self.asks = asks
-                .iter()
-                .take(self.depth)
-                .filter_map(|ask| {
-                    let price = ask.get(0)?.as_str()?.parse::<f64>().ok()?;
-                    let volume = ask.get(1)?.as_str()?.parse::<f64>().ok()?;
-                    Some(Level { price, volume })
-                })
-                .collect();
+    fn create(depth: usize, precision: Option<(u32, u32)>) -> Self {
+        OrderBook {
+            depth,
+            precision,
+            retain_all: false,
+            bids: BTreeMap::new(),
+            asks: BTreeMap::new(),
+            status: BookStatus::AwaitingSnapshot,
+            stats: BookStats::default(),
         }
+    }
 
-        // Parsing bids
-        if let Some(bids) = snapshot.get("bs").and_then(Value::as_array) {
-            self.bids = bids
-                .iter()
-                .take(self.depth)
-                .filter_map(|bid| {
-                    let price = bid.get(0)?.as_str()?.parse::<f64>().ok()?;
-                    let volume = bid.get(1)?.as_str()?.parse::<f64>().ok()?;
-                    Some(Level { price, volume })
-                })
-                .collect();
-        }
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
 
-        // Sort bids and asks
-        self.bids
-            .sort_by(|a, b| b.price.partial_cmp(&a.price).unwrap());
+    pub fn status(&self) -> BookStatus {
+        self.status
+    }
+
+    pub fn stats(&self) -> BookStats {
+        self.stats
+    }
+
+    // Drops all levels and waits for a new snapshot, e.g. after the connection was lost
+    pub fn reset(&mut self) {
+        self.asks.clear();
+        self.bids.clear();
+        self.status = BookStatus::AwaitingSnapshot;
+    }
+
+    // Asks from best (lowest) to worst, at most `depth` of them
+    pub fn asks(&self) -> impl Iterator<Item = Level> + '_ {
         self.asks
-            .sort_by(|a, b| a.price.partial_cmp(&b.price).unwrap());
+            .iter()
+            .take(self.depth)
+            .map(|(&price, &volume)| Level { price, volume })
+    }
+
+    // Bids from best (highest) to worst, at most `depth` of them
+    pub fn bids(&self) -> impl Iterator<Item = Level> + '_ {
+        self.bids
+            .iter()
+            .take(self.depth)
+            .map(|(&Reverse(price), &volume)| Level { price, volume })
+    }
+
+    // How many levels per side are stored
+    fn capacity(&self) -> usize {
+        if self.retain_all {
+            usize::MAX
+        } else {
+            self.depth
+        }
+    }
+
+    // Initializes the order book with a snapshot
+    pub fn initialize(&mut self, snapshot: &BookSnapshot) {
+        self.asks = snapshot
+            .asks
+            .iter()
+            .take(self.capacity())
+            .map(|entry| (entry.price, entry.volume))
+            .collect();
+        self.bids = snapshot
+            .bids
+            .iter()
+            .take(self.capacity())
+            .map(|entry| (Reverse(entry.price), entry.volume))
+            .collect();
+
+        if self.status == BookStatus::Stale {
+            self.stats.resyncs += 1;
+        }
+        self.stats.snapshots += 1;
+        self.status = BookStatus::Synced;
     }
 
     // Updates the order book with changes
-    pub fn update(&mut self, update: &serde_json::Value) {
-        if let Some(update_data) = update.get(1) {
-            // Handle asks update
-            if let Some(asks_update) = update_data.get("a").and_then(|a| a.as_array()) {
-                for ask in asks_update {
-                    if let Some((price_str, volume_str, _timestamp)) =
-                        ask.as_array().and_then(|ask| {
-                            Some((ask[0].as_str()?, ask[1].as_str()?, ask[2].as_str()?))
-                        })
-                    {
-                        let price: f64 = price_str.parse().unwrap_or(0.0);
-                        let volume: f64 = volume_str.parse().unwrap_or(0.0);
-
-                        if volume == 0.0 {
-                            // Delete the price level with 0 volume
-                            self.asks.retain(|a| a.price != price);
-                        } else {
-                            // Check if the price level exists and update or insert accordingly
-                            match self.asks.iter_mut().find(|a| a.price == price) {
-                                Some(existing_ask) => existing_ask.volume = volume, // Update existing
-                                None => {
-                                    // Insert new price level in sorted order
-                                    let new_ask = Level { price, volume };
-                                    self.asks.push(new_ask);
-                                    self.asks
-                                        .sort_by(|a, b| a.price.partial_cmp(&b.price).unwrap());
-                                }
-                            }
-                        }
-                    }
-                }
+    pub fn update(&mut self, update: &BookUpdate) {
+        for ask in &update.asks {
+            if ask.volume.is_zero() {
+                // Delete the price level with 0 volume
+                self.asks.remove(&ask.price);
+            } else {
+                // Update the existing level or insert a new one
+                self.asks.insert(ask.price, ask.volume);
             }
+        }
 
-            if let Some(bids_update) = update_data.get("b").and_then(|b| b.as_array()) {
-                for bid in bids_update {
-                    if let Some((price_str, volume_str, _timestamp)) =
-                        bid.as_array().and_then(|bid| {
-                            Some((bid[0].as_str()?, bid[1].as_str()?, bid[2].as_str()?))
-                        })
-                    {
-                        let price: f64 = price_str.parse().unwrap_or(0.0);
-                        let volume: f64 = volume_str.parse().unwrap_or(0.0);
-
-                        if volume == 0.0 {
-                            // Delete the price level with 0 volume
-                            self.bids.retain(|b| b.price != price);
-                        } else {
-                            // Check if the price level exists and update or insert accordingly
-                            match self.bids.iter_mut().find(|b| b.price == price) {
-                                Some(existing_bid) => existing_bid.volume = volume, // Update existing
-                                None => {
-                                    // Insert new price level in sorted order
-                                    let new_bid = Level { price, volume };
-                                    self.bids.push(new_bid);
-                                    self.bids
-                                        .sort_by(|a, b| b.price.partial_cmp(&a.price).unwrap());
-                                }
-                            }
-                        }
-                    }
-                }
+        for bid in &update.bids {
+            if bid.volume.is_zero() {
+                // Delete the price level with 0 volume
+                self.bids.remove(&Reverse(bid.price));
+            } else {
+                // Update the existing level or insert a new one
+                self.bids.insert(Reverse(bid.price), bid.volume);
             }
         }
         self.truncate_to_depth();
     }
 
-    fn truncate_to_depth(&mut self) {
-        // Truncate asks to the specified depth
-        if self.asks.len() > self.depth {
-            self.asks.truncate(self.depth);
+    // Applies both sides of an update, then validates the checksum it carries (if any).
+    // A mismatch marks the book stale; updates are then dropped until the next snapshot.
+    pub fn apply_update(&mut self, update: &BookUpdate) -> Result<(), ChecksumMismatch> {
+        if self.status != BookStatus::Synced {
+            self.stats.dropped_updates += 1;
+            return Ok(());
         }
-        // Truncate bids to the specified depth
-        if self.bids.len() > self.depth {
-            self.bids.truncate(self.depth);
+
+        self.update(update);
+        self.stats.updates += 1;
+        self.check(update.checksum)
+    }
+
+    // Initializes the book, then validates the checksum the snapshot carries (if any)
+    pub fn apply_snapshot(&mut self, snapshot: &BookSnapshot) -> Result<(), ChecksumMismatch> {
+        self.initialize(snapshot);
+        self.check(snapshot.checksum)
+    }
+
+    // Marks the book stale when it does not match the checksum Kraken sent
+    fn check(&mut self, checksum: Option<u32>) -> Result<(), ChecksumMismatch> {
+        let result = match checksum {
+            Some(expected) => self.verify_checksum(expected),
+            None => Ok(()),
+        };
+        if result.is_err() {
+            self.stats.checksum_mismatches += 1;
+            self.status = BookStatus::Stale;
         }
+        result
+    }
 
-        // Since we may have inserted a new price level, ensure the order book is sorted
-        self.asks
-            .sort_by(|a, b| a.price.partial_cmp(&b.price).unwrap());
-        self.bids
-            .sort_by(|a, b| b.price.partial_cmp(&a.price).unwrap());
+    pub fn verify_checksum(&self, expected: u32) -> Result<(), ChecksumMismatch> {
+        let calculated = self.calculate_checksum();
+        if calculated == expected {
+            Ok(())
+        } else {
+            Err(ChecksumMismatch {
+                expected,
+                calculated,
+            })
+        }
+    }
+
+    // Drops the worst levels beyond the subscribed depth; both maps end with the worst price
+    fn truncate_to_depth(&mut self) {
+        while self.asks.len() > self.capacity() {
+            self.asks.pop_last();
+        }
+        while self.bids.len() > self.capacity() {
+            self.bids.pop_last();
+        }
     }
 
     pub fn calculate_checksum(&self) -> u32 {
         let mut input_string = String::new();
 
-        // Process asks
-        for ask in self.asks.iter().take(10) {
-            let price = format!("{:.5}", ask.price)
-                .replace(".", "")
-                .trim_start_matches('0')
-                .to_string();
-            let volume = format!("{:.8}", ask.volume)
-                .replace(".", "")
-                .trim_start_matches('0')
-                .to_string();
-            input_string.push_str(&price);
-            input_string.push_str(&volume);
-        }
-
-        // Process bids
-        for bid in self.bids.iter().take(10) {
-            // Ensure high to low order for bids
-            let price = format!("{:.5}", bid.price)
-                .replace(".", "")
-                .trim_start_matches('0')
-                .to_string();
-            let volume = format!("{:.8}", bid.volume)
-                .replace(".", "")
-                .trim_start_matches('0')
-                .to_string();
-            input_string.push_str(&price);
-            input_string.push_str(&volume);
+        // Top 10 asks (low to high) followed by top 10 bids (high to low)
+        let (price_decimals, lot_decimals) = self.precision.unzip();
+        for level in self.asks().take(10).chain(self.bids().take(10)) {
+            input_string.push_str(&checksum_field(level.price, price_decimals));
+            input_string.push_str(&checksum_field(level.volume, lot_decimals));
         }
 
         let mut hasher = Hasher::new();
@@ -182,28 +248,51 @@ impl OrderBook {
     }
 }
 
+// Formats a value at the given precision, or at the scale it was received
+// with, then drops the decimal point and leading zeros
+pub(crate) fn checksum_field(value: Decimal, decimals: Option<u32>) -> String {
+    match decimals {
+        Some(decimals) => format!("{:.*}", decimals as usize, value),
+        None => value.to_string(),
+    }
+    .replace('.', "")
+    .trim_start_matches('0')
+    .to_string()
+}
+
+impl fmt::Display for ChecksumMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "checksum mismatch: expected {}, calculated {}",
+            self.expected, self.calculated
+        )
+    }
+}
+
+impl std::error::Error for ChecksumMismatch {}
+
 impl fmt::Display for Level {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:.5} ({:.8})", self.price, self.volume)
+        write!(f, "{} ({})", self.price, self.volume)
     }
 }
 
 impl fmt::Display for OrderBook {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        writeln!(f, "Order Book:")?;
-        writeln!(
-            f,
-            "{:<10} {:<20} | {:<10} {}",
-            "Depth", "Bid", "Ask", "Depth"
-        )?;
+        match self.status {
+            BookStatus::Synced => writeln!(f, "Order Book:")?,
+            _ => writeln!(f, "Order Book (stale):")?,
+        }
+        writeln!(f, "{:<10} {:<20} | {:<10} Depth", "Depth", "Bid", "Ask")?;
+        let mut bids = self.bids();
+        let mut asks = self.asks();
         for i in 0..self.depth {
-            let bid_level = self
-                .bids
-                .get(i)
+            let bid_level = bids
+                .next()
                 .map_or("".to_string(), |level| format!("{}", level));
-            let ask_level = self
-                .asks
-                .get(i)
+            let ask_level = asks
+                .next()
                 .map_or("".to_string(), |level| format!("{}", level));
             writeln!(
                 f,
@@ -221,10 +310,19 @@ impl fmt::Display for OrderBook {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::messages::{ChannelData, ChannelMessage};
     use serde_json::Value;
 
+    fn snapshot_payload(frame: &Value) -> BookSnapshot {
+        serde_json::from_value(frame[1].clone()).unwrap()
+    }
+
+    fn update_payload(frame: &Value) -> BookUpdate {
+        serde_json::from_value(frame[1].clone()).unwrap()
+    }
+
     fn get_snapshot() -> Value {
-        return serde_json::json!(
+        serde_json::json!(
         [0,
         {"as":[
             ["5711.80000","8.13439401","1557070784.848047"],
@@ -251,11 +349,11 @@ mod tests {
         },
         "book-10",
         "XBT/USD"]
-        );
+        )
     }
 
     fn get_update1() -> Value {
-        return serde_json::json!(
+        serde_json::json!(
         [0,
         {"b":[
             ["5709.20000","3.00000000","1557070785.898642"],
@@ -265,31 +363,31 @@ mod tests {
         },
         "book-10",
         "XBT/USD"]
-        );
+        )
     }
 
     fn get_update2() -> Value {
-        return serde_json::json!(
+        serde_json::json!(
             [0,
             {"b":[
                 ["5709.20000","8.00000000","1557070786.250425"],
                 ["5709.40000","0.30000000","1557070786.259115"]],
                  "c":"4148072505"},"book-10","XBT/USD"]
-        );
+        )
     }
 
     fn get_update3() -> Value {
-        return serde_json::json!(
+        serde_json::json!(
             [0,
             {"b":[
                 ["5708.30000","0.00000000","1557070786.389495"],
                 ["5705.90000","7.62400000","1557070783.582385","r"]],
                  "c":"3093569863"},"book-10","XBT/USD"]
-        );
+        )
     }
 
     fn get_expected_order_book1() -> Value {
-        return serde_json::json!(
+        serde_json::json!(
             [
                 0,
                 {
@@ -321,11 +419,11 @@ mod tests {
                 "book-10",
                 "XBT/USD"
             ]
-        );
+        )
     }
 
     fn get_expected_order_book2() -> Value {
-        return serde_json::json!(
+        serde_json::json!(
             [
                 0,
                 {
@@ -357,11 +455,11 @@ mod tests {
                 "book-10",
                 "XBT/USD"
             ]
-        );
+        )
     }
 
     fn get_expected_order_book3() -> Value {
-        return serde_json::json! {
+        serde_json::json! {
             [0,
             {
                 "as": [
@@ -392,7 +490,7 @@ mod tests {
             "book-10",
             "XBT/USD"
         ]
-        };
+        }
     }
 
     #[test]
@@ -400,12 +498,16 @@ mod tests {
         let mut order_book = OrderBook::new(10);
         let snapshot = get_snapshot();
 
-        order_book.initialize(&snapshot);
+        order_book.initialize(&snapshot_payload(&snapshot));
 
         assert_eq!(order_book.asks.len(), 10);
         assert_eq!(order_book.bids.len(), 10);
-        assert!(order_book.asks.iter().all(|level| level.price > 5711.75));
-        assert!(order_book.bids.iter().all(|level| level.price < 5711.75));
+        assert!(order_book
+            .asks()
+            .all(|level| level.price > "5711.75".parse().unwrap()));
+        assert!(order_book
+            .bids()
+            .all(|level| level.price < "5711.75".parse().unwrap()))
     }
 
     #[test]
@@ -414,35 +516,35 @@ mod tests {
         let mut order_book = OrderBook::new(10);
         let initial_snapshot = get_snapshot();
 
-        order_book.initialize(&initial_snapshot);
+        order_book.initialize(&snapshot_payload(&initial_snapshot));
 
         // Apply updates to the OrderBook.
         let updates1 = get_update1();
-        order_book.update(&updates1);
+        order_book.update(&update_payload(&updates1));
 
         // Verify that the OrderBook now matches the expected output.
         let mut expected_order_book = OrderBook::new(10);
-        expected_order_book.initialize(&get_expected_order_book1());
+        expected_order_book.initialize(&snapshot_payload(&get_expected_order_book1()));
 
         assert_eq!(order_book.asks, expected_order_book.asks);
         assert_eq!(order_book.bids, expected_order_book.bids);
 
         // Apply another update to the OrderBook
         let updates2 = get_update2();
-        order_book.update(&updates2);
+        order_book.update(&update_payload(&updates2));
 
         expected_order_book = OrderBook::new(10);
-        expected_order_book.initialize(&get_expected_order_book2());
+        expected_order_book.initialize(&snapshot_payload(&get_expected_order_book2()));
 
         assert_eq!(order_book.asks, expected_order_book.asks);
         assert_eq!(order_book.bids, expected_order_book.bids);
 
         // Apply another update to the OrderBook
         let updates3 = get_update3();
-        order_book.update(&updates3);
+        order_book.update(&update_payload(&updates3));
 
         expected_order_book = OrderBook::new(10);
-        expected_order_book.initialize(&get_expected_order_book3());
+        expected_order_book.initialize(&snapshot_payload(&get_expected_order_book3()));
 
         assert_eq!(order_book.asks, expected_order_book.asks);
         assert_eq!(order_book.bids, expected_order_book.bids);
@@ -451,7 +553,7 @@ mod tests {
     #[test]
     fn test_order_book_checksum() {
         let mut order_book = OrderBook::new(10);
-        order_book.initialize(&serde_json::json!(
+        order_book.initialize(&snapshot_payload(&serde_json::json!(
             [0,
             {
                 "as": [
@@ -478,7 +580,148 @@ mod tests {
                     [ "0.04950", "0.00000500", "1582905488.509872" ] ]
                 }
             ]
-        ));
+        )));
         assert_eq!(order_book.calculate_checksum(), 974947235);
     }
+
+    fn build_snapshot(asks: &[(&str, &str)], bids: &[(&str, &str)]) -> Value {
+        let to_levels = |levels: &[(&str, &str)]| -> Vec<Value> {
+            levels
+                .iter()
+                .map(|(price, volume)| serde_json::json!([price, volume, "1700000000.000000"]))
+                .collect()
+        };
+        serde_json::json!([0, {"as": to_levels(asks), "bs": to_levels(bids)}, "book-10", "PAIR"])
+    }
+
+    #[test]
+    fn test_order_book_checksum_as_received() {
+        // XBT/USD frames as Kraken sent them, prices with 5 decimals although the
+        // pair's `pair_decimals` is 1; the checksums cover the strings unchanged
+        let mut order_book = OrderBook::new(10);
+        order_book.initialize(&snapshot_payload(&get_snapshot()));
+        for update in [get_update1(), get_update2(), get_update3()] {
+            assert_eq!(order_book.apply_update(&update_payload(&update)), Ok(()));
+        }
+        assert_eq!(order_book.status(), BookStatus::Synced);
+
+        // Reformatting at the pair precision breaks them
+        let mut reformatted = OrderBook::with_precision(10, 1, 8);
+        reformatted.initialize(&snapshot_payload(&get_snapshot()));
+        let mismatch = reformatted
+            .apply_update(&update_payload(&get_update1()))
+            .unwrap_err();
+        assert_eq!(mismatch.expected, 2470128591);
+    }
+
+    #[test]
+    fn test_deep_book_stays_sorted_and_truncated() {
+        let mut order_book = OrderBook::new(1000);
+        order_book.initialize(&snapshot_payload(&build_snapshot(&[], &[])));
+
+        // Insert 2000 levels per side in scrambled order, then delete every third one
+        for i in 0..2000u32 {
+            let tick = (i * 7919) % 2000;
+            let ask = format!("{}.{:05}", 10000 + tick, i % 7);
+            let bid = format!("{}.{:05}", 9999 - tick, i % 7);
+            order_book.update(&update_payload(&serde_json::json!([0, {
+                "a": [[ask, "1.00000000", "1700000000.000000"]],
+                "b": [[bid, "1.00000000", "1700000000.000000"]]
+            }, "book-1000", "XBT/USD"])));
+        }
+        let deleted: Vec<Level> = order_book.asks().step_by(3).collect();
+        for level in &deleted {
+            order_book.update(&update_payload(&serde_json::json!([0, {
+                "a": [[level.price.to_string(), "0.00000000", "1700000000.000000"]]
+            }, "book-1000", "XBT/USD"])));
+        }
+
+        let asks: Vec<Level> = order_book.asks().collect();
+        let bids: Vec<Level> = order_book.bids().collect();
+        assert_eq!(bids.len(), 1000);
+        assert_eq!(asks.len(), 1000 - deleted.len());
+        assert!(asks.windows(2).all(|pair| pair[0].price < pair[1].price));
+        assert!(bids.windows(2).all(|pair| pair[0].price > pair[1].price));
+        assert!(asks.iter().all(|level| !deleted.contains(level)));
+    }
+
+    // Combined ask+bid frame as Kraken sends it: one checksum covers both halves
+    fn get_combined_update() -> &'static str {
+        r#"[0,{"a":[["5711.80000","0.00000000","1557070786.512345"],["5712.50000","1.25000000","1557070786.512345"]]},{"b":[["5711.70000","0.50000000","1557070786.512399"],["5711.75000","0.10000000","1557070786.512399"]],"c":"3470003306"},"book-10","XBT/USD"]"#
+    }
+
+    #[test]
+    fn test_order_book_combined_update() {
+        let mut order_book = OrderBook::new(10);
+        order_book.initialize(&snapshot_payload(&get_snapshot()));
+
+        let frame: ChannelMessage = serde_json::from_str(get_combined_update()).unwrap();
+        let update = match frame.data {
+            ChannelData::BookUpdate(update) => update,
+            other => panic!("expected a book update, got {:?}", other),
+        };
+        assert_eq!(order_book.apply_update(&update), Ok(()));
+
+        let best_ask = order_book.asks().next().unwrap();
+        let best_bid = order_book.bids().next().unwrap();
+        assert_eq!(best_ask.price, "5712.2".parse().unwrap());
+        assert_eq!(best_bid.price, "5711.75".parse().unwrap());
+        assert_eq!(
+            order_book.bids().nth(1).unwrap().volume,
+            "0.5".parse().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_order_book_checksum_mismatch() {
+        let mut order_book = OrderBook::new(10);
+        order_book.initialize(&snapshot_payload(&get_snapshot()));
+
+        // Applying only the asks half leaves the book out of line with the checksum
+        let frame: Value = serde_json::from_str(get_combined_update()).unwrap();
+        let mut asks_only = update_payload(&frame);
+        asks_only.checksum = Some(3470003306);
+        let mismatch = order_book.apply_update(&asks_only).unwrap_err();
+        assert_eq!(mismatch.expected, 3470003306);
+        assert_eq!(mismatch.calculated, order_book.calculate_checksum());
+    }
+
+    #[test]
+    fn test_order_book_resync_after_mismatch() {
+        let mut order_book = OrderBook::new(10);
+        assert_eq!(order_book.status(), BookStatus::AwaitingSnapshot);
+        order_book.initialize(&snapshot_payload(&get_snapshot()));
+        assert_eq!(order_book.status(), BookStatus::Synced);
+
+        let mut corrupt = update_payload(&get_update1());
+        corrupt.checksum = Some(1);
+        assert!(order_book.apply_update(&corrupt).is_err());
+        assert_eq!(order_book.status(), BookStatus::Stale);
+
+        // Updates are ignored until a new snapshot arrives
+        let before: Vec<Level> = order_book.bids().collect();
+        assert_eq!(
+            order_book.apply_update(&update_payload(&get_update2())),
+            Ok(())
+        );
+        assert_eq!(order_book.bids().collect::<Vec<_>>(), before);
+
+        order_book.initialize(&snapshot_payload(&get_snapshot()));
+        assert_eq!(order_book.status(), BookStatus::Synced);
+        assert_eq!(
+            order_book.apply_update(&update_payload(&get_update1())),
+            Ok(())
+        );
+
+        assert_eq!(
+            order_book.stats(),
+            BookStats {
+                snapshots: 2,
+                updates: 2,
+                checksum_mismatches: 1,
+                resyncs: 1,
+                dropped_updates: 1,
+            }
+        );
+    }
 }
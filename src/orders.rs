@@ -0,0 +1,411 @@
+use crate::decimal::Decimal;
+use crate::error::Error;
+use crate::messages::{EventMessage, Side};
+use serde::{Serialize, Serializer};
+use std::collections::HashMap;
+use std::fmt::Display;
+use tokio::sync::oneshot;
+
+// Order types accepted by `addOrder`; the trade channel only reports market or limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OrderType {
+    Market,
+    Limit,
+    StopLoss,
+    TakeProfit,
+    StopLossLimit,
+    TakeProfitLimit,
+    SettlePosition,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum TimeInForce {
+    #[serde(rename = "GTC")]
+    GoodTillCancelled,
+    #[serde(rename = "IOC")]
+    ImmediateOrCancel,
+    // Needs an `expire_time`
+    #[serde(rename = "GTD")]
+    GoodTillDate,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrderFlag {
+    // Post-only limit order
+    Post,
+    FeeInBase,
+    FeeInQuote,
+    NoMarketPriceProtection,
+    // Volume is given in the quote currency
+    VolumeInQuote,
+}
+
+impl OrderFlag {
+    fn code(self) -> &'static str {
+        match self {
+            OrderFlag::Post => "post",
+            OrderFlag::FeeInBase => "fcib",
+            OrderFlag::FeeInQuote => "fciq",
+            OrderFlag::NoMarketPriceProtection => "nompp",
+            OrderFlag::VolumeInQuote => "viqc",
+        }
+    }
+}
+
+/// Parameters of an `addOrder` request.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AddOrder {
+    #[serde(rename = "ordertype")]
+    pub order_type: OrderType,
+    #[serde(rename = "type")]
+    pub side: Side,
+    pub pair: String,
+    pub volume: Decimal,
+    // Limit price, or trigger price for stop and take-profit orders
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub price: Option<Decimal>,
+    // Limit price of stop-loss-limit and take-profit-limit orders
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub price2: Option<Decimal>,
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "optional_string"
+    )]
+    pub leverage: Option<u32>,
+    #[serde(skip_serializing_if = "Vec::is_empty", serialize_with = "flags")]
+    pub oflags: Vec<OrderFlag>,
+    #[serde(rename = "timeinforce", skip_serializing_if = "Option::is_none")]
+    pub time_in_force: Option<TimeInForce>,
+    // Unix time, or `+<seconds>` from now
+    #[serde(rename = "expiretm", skip_serializing_if = "Option::is_none")]
+    pub expire_time: Option<String>,
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "optional_string"
+    )]
+    pub userref: Option<i32>,
+    // Only validate the order, do not place it
+    #[serde(skip_serializing_if = "is_false", serialize_with = "flag_string")]
+    pub validate: bool,
+}
+
+impl AddOrder {
+    pub fn market(side: Side, pair: &str, volume: Decimal) -> Self {
+        AddOrder {
+            order_type: OrderType::Market,
+            side,
+            pair: pair.to_string(),
+            volume,
+            price: None,
+            price2: None,
+            leverage: None,
+            oflags: Vec::new(),
+            time_in_force: None,
+            expire_time: None,
+            userref: None,
+            validate: false,
+        }
+    }
+
+    pub fn limit(side: Side, pair: &str, volume: Decimal, price: Decimal) -> Self {
+        AddOrder {
+            order_type: OrderType::Limit,
+            price: Some(price),
+            ..AddOrder::market(side, pair, volume)
+        }
+    }
+}
+
+/// Parameters of an `editOrder` request; unset fields keep their current value.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EditOrder {
+    #[serde(rename = "orderid")]
+    pub order_id: String,
+    pub pair: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub volume: Option<Decimal>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub price: Option<Decimal>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub price2: Option<Decimal>,
+    #[serde(skip_serializing_if = "Vec::is_empty", serialize_with = "flags")]
+    pub oflags: Vec<OrderFlag>,
+    #[serde(
+        rename = "newuserref",
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "optional_string"
+    )]
+    pub new_userref: Option<i32>,
+    #[serde(skip_serializing_if = "is_false", serialize_with = "flag_string")]
+    pub validate: bool,
+}
+
+impl EditOrder {
+    pub fn new(order_id: &str, pair: &str) -> Self {
+        EditOrder {
+            order_id: order_id.to_string(),
+            pair: pair.to_string(),
+            volume: None,
+            price: None,
+            price2: None,
+            oflags: Vec::new(),
+            new_userref: None,
+            validate: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AddOrderResult {
+    pub txid: Option<String>,
+    // e.g. `buy 0.01770000 XBTUSD @ limit 4000`
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EditOrderResult {
+    // Kraken replaces an edited order with a new one
+    pub txid: Option<String>,
+    pub original_txid: Option<String>,
+    pub description: Option<String>,
+}
+
+// Kraken's v1 WebSocket takes these numbers as strings
+fn optional_string<T: Display, S: Serializer>(
+    value: &Option<T>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match value {
+        Some(value) => serializer.serialize_str(&value.to_string()),
+        None => serializer.serialize_none(),
+    }
+}
+
+fn flags<S: Serializer>(flags: &[OrderFlag], serializer: S) -> Result<S::Ok, S::Error> {
+    let codes: Vec<&str> = flags.iter().map(|flag| flag.code()).collect();
+    serializer.serialize_str(&codes.join(","))
+}
+
+fn flag_string<S: Serializer>(flag: &bool, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(if *flag { "true" } else { "false" })
+}
+
+fn is_false(flag: &bool) -> bool {
+    !flag
+}
+
+pub(crate) type Responder = oneshot::Sender<Result<EventMessage, Error>>;
+
+// Order requests waiting for their status events, keyed by reqid
+#[derive(Debug, Default)]
+pub(crate) struct PendingReplies {
+    pending: HashMap<u64, PendingReply>,
+}
+
+#[derive(Debug)]
+struct PendingReply {
+    // `cancelOrder` gets one status per order ID
+    remaining: usize,
+    responder: Responder,
+}
+
+impl PendingReplies {
+    pub(crate) fn insert(&mut self, reqid: u64, expected: usize, responder: Responder) {
+        self.pending.insert(
+            reqid,
+            PendingReply {
+                remaining: expected.max(1),
+                responder,
+            },
+        );
+    }
+
+    // Resolves the request an order status belongs to with the last status, or
+    // with the first error
+    pub(crate) fn resolve(&mut self, event: &EventMessage) {
+        let (reqid, error_message) = match event {
+            EventMessage::AddOrderStatus {
+                reqid: Some(reqid),
+                error_message,
+                ..
+            }
+            | EventMessage::EditOrderStatus {
+                reqid: Some(reqid),
+                error_message,
+                ..
+            }
+            | EventMessage::CancelOrderStatus {
+                reqid: Some(reqid),
+                error_message,
+                ..
+            }
+            | EventMessage::CancelAllStatus {
+                reqid: Some(reqid),
+                error_message,
+                ..
+            } => (
+                *reqid,
+                // Success is decided by `status`; the message is only the explanation
+                is_error(event).then(|| {
+                    error_message
+                        .clone()
+                        .unwrap_or_else(|| "error status without an errorMessage".to_string())
+                }),
+            ),
+            EventMessage::Error {
+                reqid: Some(reqid),
+                error_message,
+            } => (*reqid, Some(error_message.clone())),
+            _ => return,
+        };
+        let Some(reply) = self.pending.get_mut(&reqid) else {
+            return;
+        };
+
+        reply.remaining -= 1;
+        if let Some(message) = error_message {
+            let reply = self.pending.remove(&reqid).unwrap();
+            let _ = reply.responder.send(Err(Error::Order(message)));
+        } else if reply.remaining == 0 {
+            let reply = self.pending.remove(&reqid).unwrap();
+            let _ = reply.responder.send(Ok(event.clone()));
+        }
+    }
+
+    // The connection dropped; Kraken will never answer these
+    pub(crate) fn fail_all(&mut self) {
+        for (_, reply) in self.pending.drain() {
+            let _ = reply.responder.send(Err(Error::ConnectionClosed));
+        }
+    }
+}
+
+fn is_error(event: &EventMessage) -> bool {
+    match event {
+        EventMessage::AddOrderStatus { status, .. }
+        | EventMessage::EditOrderStatus { status, .. }
+        | EventMessage::CancelOrderStatus { status, .. }
+        | EventMessage::CancelAllStatus { status, .. } => status == "error",
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(json: serde_json::Value) -> EventMessage {
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn test_serialize_orders() {
+        let mut order = AddOrder::limit(
+            Side::Buy,
+            "XBT/USD",
+            "0.0177".parse().unwrap(),
+            "4000.0".parse().unwrap(),
+        );
+        order.leverage = Some(2);
+        order.oflags = vec![OrderFlag::Post, OrderFlag::FeeInQuote];
+        order.time_in_force = Some(TimeInForce::GoodTillDate);
+        order.expire_time = Some("+60".to_string());
+        order.userref = Some(-12);
+        order.validate = true;
+        assert_eq!(
+            serde_json::to_value(&order).unwrap(),
+            serde_json::json!({
+                "ordertype": "limit",
+                "type": "buy",
+                "pair": "XBT/USD",
+                "volume": "0.0177",
+                "price": "4000.0",
+                "leverage": "2",
+                "oflags": "post,fciq",
+                "timeinforce": "GTD",
+                "expiretm": "+60",
+                "userref": "-12",
+                "validate": "true"
+            })
+        );
+
+        let mut stop = AddOrder::market(Side::Sell, "ETH/USD", "1".parse().unwrap());
+        stop.order_type = OrderType::StopLossLimit;
+        stop.price = Some("1800".parse().unwrap());
+        stop.price2 = Some("1795".parse().unwrap());
+        assert_eq!(
+            serde_json::to_value(&stop).unwrap(),
+            serde_json::json!({
+                "ordertype": "stop-loss-limit",
+                "type": "sell",
+                "pair": "ETH/USD",
+                "volume": "1",
+                "price": "1800",
+                "price2": "1795"
+            })
+        );
+
+        let mut edit = EditOrder::new("OGTT3Y-C6I3P-XRI6HX", "XBT/USD");
+        edit.price = Some("4100".parse().unwrap());
+        edit.new_userref = Some(7);
+        assert_eq!(
+            serde_json::to_value(&edit).unwrap(),
+            serde_json::json!({
+                "orderid": "OGTT3Y-C6I3P-XRI6HX",
+                "pair": "XBT/USD",
+                "price": "4100",
+                "newuserref": "7"
+            })
+        );
+    }
+
+    #[test]
+    fn test_replies_resolve_by_reqid() {
+        let mut replies = PendingReplies::default();
+        let (tx, mut rx) = oneshot::channel();
+        replies.insert(3, 2, tx);
+
+        // Not ours
+        replies.resolve(&event(
+            serde_json::json!({"event": "cancelOrderStatus", "status": "ok", "reqid": 4}),
+        ));
+        replies.resolve(&event(
+            serde_json::json!({"event": "cancelOrderStatus", "status": "ok", "reqid": 3}),
+        ));
+        assert!(rx.try_recv().is_err());
+        replies.resolve(&event(
+            serde_json::json!({"event": "cancelOrderStatus", "status": "ok", "reqid": 3}),
+        ));
+        assert!(matches!(
+            rx.try_recv(),
+            Ok(Ok(EventMessage::CancelOrderStatus { .. }))
+        ));
+
+        let (tx, mut rx) = oneshot::channel();
+        replies.insert(5, 1, tx);
+        replies.resolve(&event(serde_json::json!({
+            "errorMessage": "EOrder:Order minimum not met",
+            "event": "addOrderStatus",
+            "status": "error",
+            "reqid": 5
+        })));
+        match rx.try_recv() {
+            Ok(Err(Error::Order(message))) => assert_eq!(message, "EOrder:Order minimum not met"),
+            other => panic!("expected an order error, got {:?}", other),
+        }
+
+        // An error without its message is still an error
+        let (tx, mut rx) = oneshot::channel();
+        replies.insert(7, 1, tx);
+        replies.resolve(&event(
+            serde_json::json!({"event": "cancelOrderStatus", "status": "error", "reqid": 7}),
+        ));
+        assert!(matches!(rx.try_recv(), Ok(Err(Error::Order(_)))));
+
+        let (tx, mut rx) = oneshot::channel();
+        replies.insert(6, 1, tx);
+        replies.fail_all();
+        assert!(matches!(rx.try_recv(), Ok(Err(Error::ConnectionClosed))));
+    }
+}
@@ -0,0 +1,246 @@
+use crate::decimal::Decimal;
+use crate::messages::Side;
+use serde::de::{self, Deserializer};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// A frame from the authenticated WebSocket: `[payload, channelName, {"sequence": n}]`.
+///
+/// Unlike public channels there is no channel ID or pair; the payload is a list
+/// of single-entry objects keyed by trade or order ID.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PrivateMessage {
+    pub channel_name: String,
+    pub sequence: Option<u64>,
+    pub data: PrivateData,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PrivateData {
+    OwnTrades(Vec<OwnTrade>),
+    OpenOrders(Vec<OrderUpdate>),
+}
+
+/// One fill from the `ownTrades` channel.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct OwnTrade {
+    // Filled in from the key the trade is listed under
+    #[serde(skip)]
+    pub trade_id: String,
+    #[serde(rename = "ordertxid")]
+    pub order_id: String,
+    #[serde(rename = "postxid")]
+    pub position_id: Option<String>,
+    pub pair: String,
+    pub time: Decimal,
+    #[serde(rename = "type")]
+    pub side: Side,
+    #[serde(rename = "ordertype")]
+    pub order_type: String,
+    pub price: Decimal,
+    pub cost: Decimal,
+    pub fee: Decimal,
+    #[serde(rename = "vol")]
+    pub volume: Decimal,
+    pub margin: Option<Decimal>,
+    pub userref: Option<i64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OrderStatus {
+    Pending,
+    Open,
+    Closed,
+    Canceled,
+    Expired,
+}
+
+/// An `openOrders` entry. The snapshot carries every field; later updates only
+/// carry what changed, e.g. the status or the executed volume. REST order
+/// queries return the same shape.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct OrderUpdate {
+    // Filled in from the key the order is listed under
+    #[serde(skip)]
+    pub order_id: String,
+    pub status: Option<OrderStatus>,
+    #[serde(rename = "descr")]
+    pub description: Option<OrderDescription>,
+    #[serde(rename = "vol")]
+    pub volume: Option<Decimal>,
+    #[serde(rename = "vol_exec")]
+    pub volume_executed: Option<Decimal>,
+    pub cost: Option<Decimal>,
+    pub fee: Option<Decimal>,
+    // `price` over REST
+    #[serde(alias = "price")]
+    pub avg_price: Option<Decimal>,
+    #[serde(rename = "limitprice")]
+    pub limit_price: Option<Decimal>,
+    #[serde(rename = "stopprice")]
+    pub stop_price: Option<Decimal>,
+    #[serde(rename = "opentm")]
+    pub open_time: Option<Decimal>,
+    // Only REST reports when an order was closed
+    #[serde(rename = "closetm")]
+    pub close_time: Option<Decimal>,
+    pub userref: Option<i64>,
+    pub oflags: Option<String>,
+    // Why an order was closed or canceled
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct OrderDescription {
+    pub pair: String,
+    #[serde(rename = "type")]
+    pub side: Side,
+    #[serde(rename = "ordertype")]
+    pub order_type: String,
+    pub price: Decimal,
+    pub price2: Decimal,
+    pub leverage: Option<String>,
+    // Human readable summary, e.g. `buy 10.00 XBT/USD @ limit 34.50000`
+    pub order: String,
+}
+
+#[derive(Deserialize)]
+struct Sequence {
+    sequence: u64,
+}
+
+impl<'de> Deserialize<'de> for PrivateMessage {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let elements = Vec::<Value>::deserialize(deserializer)?;
+        let (payload, channel_name, sequence) = match elements.as_slice() {
+            [payload, Value::String(channel_name), rest @ ..] if rest.len() <= 1 => {
+                (payload, channel_name.clone(), rest.first())
+            }
+            _ => {
+                return Err(de::Error::custom(
+                    "expected [payload, channelName, {\"sequence\": n}]",
+                ))
+            }
+        };
+        let sequence = match sequence {
+            Some(value) => Some(
+                Sequence::deserialize(value)
+                    .map_err(de::Error::custom)?
+                    .sequence,
+            ),
+            None => None,
+        };
+
+        let data = match channel_name.as_str() {
+            "ownTrades" => keyed_entries(payload)
+                .map(|trades| {
+                    trades
+                        .into_iter()
+                        .map(|(trade_id, trade)| OwnTrade { trade_id, ..trade })
+                        .collect()
+                })
+                .map(PrivateData::OwnTrades),
+            "openOrders" => keyed_entries(payload)
+                .map(|orders| {
+                    orders
+                        .into_iter()
+                        .map(|(order_id, order)| OrderUpdate { order_id, ..order })
+                        .collect()
+                })
+                .map(PrivateData::OpenOrders),
+            _ => {
+                return Err(de::Error::custom(format!(
+                    "unknown channel: {}",
+                    channel_name
+                )))
+            }
+        }
+        .map_err(|e| de::Error::custom(format!("invalid {} payload: {}", channel_name, e)))?;
+
+        Ok(PrivateMessage {
+            channel_name,
+            sequence,
+            data,
+        })
+    }
+}
+
+// `[{"ID-1": {...}}, {"ID-2": {...}}]` in the order Kraken listed them
+fn keyed_entries<T: for<'de> Deserialize<'de>>(
+    payload: &Value,
+) -> Result<Vec<(String, T)>, serde_json::Error> {
+    let maps: Vec<HashMap<String, T>> = serde_json::from_value(payload.clone())?;
+    Ok(maps.into_iter().flatten().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Recorded from ws-auth.kraken.com, as in Kraken's documentation
+    const OWN_TRADES: &str = r#"[[{"TDLH43-DVQXD-2KHVYY":{"cost":"1000000.00000","fee":"1600.00000","margin":"0.00000","ordertxid":"TDLH43-DVQXD-2KHVYY","ordertype":"limit","pair":"XBT/EUR","postxid":"OGTT3Y-C6I3P-XRI6HX","price":"100000.00000","time":"1560516023.070651","type":"sell","vol":"1000000000.00000000"}},{"TDLH43-DVQXD-2KHVYY":{"cost":"1000000.00000","fee":"600.00000","margin":"0.00000","ordertxid":"TDLH43-DVQXD-2KHVYY","ordertype":"limit","pair":"XBT/EUR","postxid":"OGTT3Y-C6I3P-XRI6HX","price":"100000.00000","time":"1560516023.070658","type":"buy","vol":"1000000000.00000000"}}],"ownTrades",{"sequence":2}]"#;
+
+    const OPEN_ORDERS: &str = r#"[[{"OGTT3Y-C6I3P-XRI6HX":{"avg_price":"34.50000","cost":"0.00000","descr":{"close":"","leverage":"0:1","order":"sell 10.00345345 XBT/EUR @ limit 34.50000 with 0:1 leverage","ordertype":"limit","pair":"XBT/EUR","price":"34.50000","price2":"0.00000","type":"sell"},"expiretm":"0.000000","fee":"0.00000","limitprice":"34.50000","misc":"","oflags":"fcib","opentm":"0.000000","refid":"OKIVMP-5GVZN-Z2D2UA","starttm":"0.000000","status":"open","stopprice":"0.000000","userref":0,"vol":"10.00345345","vol_exec":"0.00000000"}}],"openOrders",{"sequence":234}]"#;
+
+    #[test]
+    fn test_parse_own_trades() {
+        let message: PrivateMessage = serde_json::from_str(OWN_TRADES).unwrap();
+        assert_eq!(message.channel_name, "ownTrades");
+        assert_eq!(message.sequence, Some(2));
+        let trades = match message.data {
+            PrivateData::OwnTrades(trades) => trades,
+            other => panic!("expected trades, got {:?}", other),
+        };
+        assert_eq!(trades.len(), 2);
+        assert_eq!(trades[0].trade_id, "TDLH43-DVQXD-2KHVYY");
+        assert_eq!(trades[0].side, Side::Sell);
+        assert_eq!(trades[0].fee, "1600".parse().unwrap());
+        assert_eq!(trades[1].side, Side::Buy);
+        assert_eq!(
+            trades[1].position_id.as_deref(),
+            Some("OGTT3Y-C6I3P-XRI6HX")
+        );
+        assert_eq!(trades[1].userref, None);
+    }
+
+    #[test]
+    fn test_parse_open_orders_snapshot_and_update() {
+        let message: PrivateMessage = serde_json::from_str(OPEN_ORDERS).unwrap();
+        let orders = match message.data {
+            PrivateData::OpenOrders(orders) => orders,
+            other => panic!("expected orders, got {:?}", other),
+        };
+        let order = &orders[0];
+        assert_eq!(order.order_id, "OGTT3Y-C6I3P-XRI6HX");
+        assert_eq!(order.status, Some(OrderStatus::Open));
+        assert_eq!(order.volume, Some("10.00345345".parse().unwrap()));
+        let description = order.description.as_ref().unwrap();
+        assert_eq!(description.side, Side::Sell);
+        assert_eq!(description.price, "34.5".parse().unwrap());
+
+        let message: PrivateMessage = serde_json::from_str(
+            r#"[[{"OGTT3Y-C6I3P-XRI6HX":{"status":"canceled","cost":"0.00000","vol_exec":"0.00000000","fee":"0.00000","avg_price":"0.00000","reason":"User requested"}}],"openOrders",{"sequence":235}]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            message.data,
+            PrivateData::OpenOrders(vec![OrderUpdate {
+                order_id: "OGTT3Y-C6I3P-XRI6HX".to_string(),
+                status: Some(OrderStatus::Canceled),
+                volume_executed: Some("0".parse().unwrap()),
+                cost: Some("0".parse().unwrap()),
+                fee: Some("0".parse().unwrap()),
+                avg_price: Some("0".parse().unwrap()),
+                reason: Some("User requested".to_string()),
+                ..OrderUpdate::default()
+            }])
+        );
+
+        assert!(
+            serde_json::from_str::<PrivateMessage>(r#"[[], "ownTrades", {"sequence": "x"}]"#)
+                .is_err()
+        );
+    }
+}
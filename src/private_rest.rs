@@ -0,0 +1,614 @@
+use crate::auth::Credentials;
+use crate::decimal::Decimal;
+use crate::error::{ApiError, Error};
+use crate::nonce::{process_nonce, NonceProvider};
+use crate::orders::{AddOrder, AddOrderResult};
+use crate::private_messages::{OrderUpdate, OwnTrade};
+use crate::rate_limit::{call_cost, RateLimitState, RateLimiter};
+use crate::rest::{Envelope, RestClient, REST_URL};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+use url::form_urlencoded;
+
+// Calls rejected for their nonce are sent again this many times by default
+const DEFAULT_NONCE_RETRIES: u32 = 2;
+
+/// Async client for Kraken's private REST endpoints.
+///
+/// Every call is a POST signed with `API-Key` and `API-Sign`. Keys protected
+/// by a second factor also need the current one-time password, see `set_otp`.
+/// Public endpoints stay available through `public()`.
+///
+/// Nonces come from a process-wide `AtomicNonce` unless another provider is
+/// set; a call rejected with `EAPI:Invalid nonce` is retried with a new one.
+/// With a `RateLimiter` set, calls are held back or rejected before Kraken's
+/// API counter would overflow.
+#[derive(Debug, Clone)]
+pub struct PrivateRestClient {
+    rest: RestClient,
+    credentials: Credentials,
+    otp: Option<String>,
+    nonces: Arc<dyn NonceProvider>,
+    nonce_retries: u32,
+    rate_limiter: Option<RateLimiter>,
+}
+
+impl PrivateRestClient {
+    pub fn new(credentials: Credentials) -> Self {
+        PrivateRestClient::with_base_url(REST_URL, credentials)
+    }
+
+    pub fn with_base_url(base_url: &str, credentials: Credentials) -> Self {
+        PrivateRestClient {
+            rest: RestClient::with_base_url(base_url),
+            credentials,
+            otp: None,
+            nonces: process_nonce(),
+            nonce_retries: DEFAULT_NONCE_RETRIES,
+            rate_limiter: None,
+        }
+    }
+
+    pub fn public(&self) -> &RestClient {
+        &self.rest
+    }
+
+    // Sent with every call until replaced; one-time passwords expire, so keep it current
+    pub fn set_otp(&mut self, otp: Option<&str>) {
+        self.otp = otp.map(str::to_string);
+    }
+
+    // Share one provider between every client, thread or process using the key
+    pub fn set_nonce_provider(&mut self, nonces: Arc<dyn NonceProvider>) {
+        self.nonces = nonces;
+    }
+
+    // 0 returns `EAPI:Invalid nonce` to the caller straight away
+    pub fn set_nonce_retries(&mut self, retries: u32) {
+        self.nonce_retries = retries;
+    }
+
+    // Clients using the same key should share one limiter, see `RateLimiter`
+    pub fn set_rate_limiter(&mut self, rate_limiter: Option<RateLimiter>) {
+        self.rate_limiter = rate_limiter;
+    }
+
+    pub fn rate_limit(&self) -> Option<RateLimitState> {
+        self.rate_limiter.as_ref().map(RateLimiter::state)
+    }
+
+    // Non-zero balances keyed by Kraken's asset names, e.g. `XXBT` or `ZUSD`
+    pub async fn balance(&self) -> Result<HashMap<String, Decimal>, Error> {
+        self.private("Balance", &()).await
+    }
+
+    // Margin figures valued in `asset`, USD unless given
+    pub async fn trade_balance(&self, asset: Option<&str>) -> Result<TradeBalance, Error> {
+        #[derive(Serialize)]
+        struct Params<'a> {
+            asset: Option<&'a str>,
+        }
+        self.private("TradeBalance", &Params { asset }).await
+    }
+
+    pub async fn open_orders(&self) -> Result<Vec<OrderUpdate>, Error> {
+        let result: OpenOrders = self.private("OpenOrders", &()).await?;
+        Ok(keyed_orders(result.open))
+    }
+
+    // At most 50 orders per call; page with `range.offset` up to `count`
+    pub async fn closed_orders(&self, range: &HistoryRange) -> Result<Page<OrderUpdate>, Error> {
+        let result: ClosedOrders = self.private("ClosedOrders", range).await?;
+        Ok(Page {
+            entries: keyed_orders(result.closed),
+            count: result.count,
+        })
+    }
+
+    // Up to 50 orders by ID, in the order they were asked for
+    pub async fn query_orders(&self, txids: &[&str]) -> Result<Vec<OrderUpdate>, Error> {
+        #[derive(Serialize)]
+        struct Params {
+            txid: String,
+        }
+        let mut result: HashMap<String, OrderUpdate> = self
+            .private(
+                "QueryOrders",
+                &Params {
+                    txid: txids.join(","),
+                },
+            )
+            .await?;
+        Ok(txids
+            .iter()
+            .filter_map(|txid| {
+                let order = result.remove(*txid)?;
+                Some(OrderUpdate {
+                    order_id: txid.to_string(),
+                    ..order
+                })
+            })
+            .collect())
+    }
+
+    // At most 50 trades per call, newest first
+    pub async fn trades_history(&self, range: &HistoryRange) -> Result<Page<OwnTrade>, Error> {
+        let result: TradesHistory = self.private("TradesHistory", range).await?;
+        let mut trades: Vec<OwnTrade> = result
+            .trades
+            .into_iter()
+            .map(|(trade_id, trade)| OwnTrade { trade_id, ..trade })
+            .collect();
+        trades.sort_by_key(|trade| std::cmp::Reverse(trade.time));
+        Ok(Page {
+            entries: trades,
+            count: result.count,
+        })
+    }
+
+    pub async fn add_order(&self, order: &AddOrder) -> Result<AddOrderResult, Error> {
+        let result: RestAddOrder = self.private("AddOrder", order).await?;
+        Ok(AddOrderResult {
+            // Several IDs only come back for orders Kraken splits, which this API does not place
+            txid: result.txid.into_iter().next(),
+            description: result.descr.order,
+        })
+    }
+
+    // `txid` may also be a `userref`, cancelling every order tagged with it;
+    // returns how many orders were cancelled
+    pub async fn cancel_order(&self, txid: &str) -> Result<u32, Error> {
+        #[derive(Serialize)]
+        struct Params<'a> {
+            txid: &'a str,
+        }
+        let result: CancelOrder = self.private("CancelOrder", &Params { txid }).await?;
+        Ok(result.count)
+    }
+
+    // Token for the authenticated WebSocket; it has to be used within 15 minutes
+    // and stays valid as long as a private subscription keeps using it
+    pub async fn websockets_token(&self) -> Result<String, Error> {
+        let result: WebSocketsToken = self.private("GetWebSocketsToken", &()).await?;
+        Ok(result.token)
+    }
+
+    async fn private<T: DeserializeOwned, P: Serialize + ?Sized>(
+        &self,
+        method: &str,
+        params: &P,
+    ) -> Result<T, Error> {
+        let path = format!("/0/private/{}", method);
+        let mut attempt = 0;
+        loop {
+            // Retries count against the counter like any other call
+            if let Some(rate_limiter) = &self.rate_limiter {
+                rate_limiter.acquire(call_cost(method)).await?;
+            }
+            let result = self.signed_post(&path, params).await;
+            match result {
+                Err(Error::Api(ref errors)) if errors.contains(&ApiError::RateLimitExceeded) => {
+                    // Our model was behind, e.g. another process used the key
+                    if let Some(rate_limiter) = &self.rate_limiter {
+                        rate_limiter.exceeded();
+                    }
+                    return result;
+                }
+                // Kraken checks the nonce before anything else, so nothing was done
+                Err(Error::Api(ref errors))
+                    if errors.contains(&ApiError::InvalidNonce) && attempt < self.nonce_retries =>
+                {
+                    attempt += 1
+                }
+                result => return result,
+            }
+        }
+    }
+
+    // Providers that block, like `FileNonce`, must not stall the runtime's worker
+    async fn next_nonce(&self) -> Result<u64, Error> {
+        if !self.nonces.blocks() {
+            return self.nonces.next_nonce();
+        }
+        let nonces = Arc::clone(&self.nonces);
+        tokio::task::spawn_blocking(move || nonces.next_nonce())
+            .await
+            .map_err(|e| Error::Nonce(format!("nonce provider failed: {}", e)))?
+    }
+
+    async fn signed_post<T: DeserializeOwned, P: Serialize + ?Sized>(
+        &self,
+        path: &str,
+        params: &P,
+    ) -> Result<T, Error> {
+        let nonce = self.next_nonce().await?;
+        let post_data = post_data(nonce, self.otp.as_deref(), params)?;
+
+        let response = self
+            .rest
+            .http()
+            .post(format!("{}{}", self.rest.base_url(), path))
+            .header("API-Key", self.credentials.api_key())
+            .header("API-Sign", self.credentials.sign(path, nonce, &post_data))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(post_data)
+            .send()
+            .await?;
+        let envelope: Envelope<T> = serde_json::from_slice(&response.bytes().await?)?;
+        envelope.into_result()
+    }
+}
+
+// The form body that gets signed: `nonce`, then `otp`, then the parameters in
+// alphabetical order; unset parameters are left out
+fn post_data<P: Serialize + ?Sized>(
+    nonce: u64,
+    otp: Option<&str>,
+    params: &P,
+) -> Result<String, Error> {
+    let mut form = form_urlencoded::Serializer::new(String::new());
+    form.append_pair("nonce", &nonce.to_string());
+    if let Some(otp) = otp {
+        form.append_pair("otp", otp);
+    }
+    if let Value::Object(params) = serde_json::to_value(params)? {
+        for (name, value) in params {
+            match value {
+                Value::Null => (),
+                Value::String(value) => {
+                    form.append_pair(&name, &value);
+                }
+                value => {
+                    form.append_pair(&name, &value.to_string());
+                }
+            }
+        }
+    }
+    Ok(form.finish())
+}
+
+fn keyed_orders(orders: HashMap<String, OrderUpdate>) -> Vec<OrderUpdate> {
+    let mut orders: Vec<OrderUpdate> = orders
+        .into_iter()
+        .map(|(order_id, order)| OrderUpdate { order_id, ..order })
+        .collect();
+    // Oldest first, as the WebSocket snapshot lists them
+    orders.sort_by_key(|order| order.open_time);
+    orders
+}
+
+/// Bounds for `ClosedOrders` and `TradesHistory`.
+///
+/// `start` and `end` are Unix timestamps or transaction IDs, exclusive.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct HistoryRange {
+    pub start: Option<String>,
+    pub end: Option<String>,
+    #[serde(rename = "ofs")]
+    pub offset: Option<u32>,
+}
+
+/// Up to 50 entries and how many match in total.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Page<T> {
+    pub entries: Vec<T>,
+    pub count: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct TradeBalance {
+    #[serde(rename = "eb")]
+    pub equivalent_balance: Decimal,
+    #[serde(rename = "tb")]
+    pub trade_balance: Decimal,
+    #[serde(rename = "m")]
+    pub margin: Decimal,
+    #[serde(rename = "n")]
+    pub unrealized_pnl: Decimal,
+    #[serde(rename = "c")]
+    pub cost_basis: Decimal,
+    #[serde(rename = "v")]
+    pub valuation: Decimal,
+    #[serde(rename = "e")]
+    pub equity: Decimal,
+    #[serde(rename = "mf")]
+    pub free_margin: Decimal,
+    // Only reported while positions are open
+    #[serde(rename = "ml")]
+    pub margin_level: Option<Decimal>,
+    #[serde(rename = "uv")]
+    pub unexecuted_value: Option<Decimal>,
+}
+
+#[derive(Deserialize)]
+struct OpenOrders {
+    open: HashMap<String, OrderUpdate>,
+}
+
+#[derive(Deserialize)]
+struct ClosedOrders {
+    closed: HashMap<String, OrderUpdate>,
+    count: u32,
+}
+
+#[derive(Deserialize)]
+struct TradesHistory {
+    trades: HashMap<String, OwnTrade>,
+    count: u32,
+}
+
+#[derive(Deserialize)]
+struct RestAddOrder {
+    descr: RestOrderDescription,
+    #[serde(default)]
+    txid: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct RestOrderDescription {
+    order: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CancelOrder {
+    count: u32,
+}
+
+#[derive(Deserialize)]
+struct WebSocketsToken {
+    token: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::Side;
+    use crate::nonce::{AtomicNonce, FileNonce};
+    use crate::private_messages::OrderStatus;
+    use crate::rate_limit::{RateLimitConfig, WhenLimited};
+    use crate::test_support::{serve_http_once, HttpRequest};
+    use tokio::net::TcpListener;
+
+    // Example from Kraken's REST authentication guide
+    const SECRET: &str =
+        "kQH5HW/8p1uGOVjbgWA7FunAmGO8lsSUXNsu3eow76sz84Q18fWxnyRzBHCd3pd5nE9qa99HAZtuZuj6F1huXg==";
+
+    fn dec(s: &str) -> Decimal {
+        s.parse().unwrap()
+    }
+
+    // The nonce is taken from the body, which the signature covers as sent
+    fn assert_signed(request: &HttpRequest, credentials: &Credentials) {
+        let nonce: u64 = form_urlencoded::parse(request.body.as_bytes())
+            .find(|(name, _)| name == "nonce")
+            .unwrap()
+            .1
+            .parse()
+            .unwrap();
+        assert_eq!(request.header("api-key"), Some(credentials.api_key()));
+        assert_eq!(
+            request.header("api-sign"),
+            Some(
+                credentials
+                    .sign(&request.path, nonce, &request.body)
+                    .as_str()
+            )
+        );
+    }
+
+    #[test]
+    fn test_add_order_matches_kraken_example() {
+        let order = AddOrder::limit(Side::Buy, "XBTUSD", dec("1.25"), dec("37500"));
+        let body = post_data(1616492376594, None, &order).unwrap();
+        assert_eq!(
+            body,
+            "nonce=1616492376594&ordertype=limit&pair=XBTUSD&price=37500&type=buy&volume=1.25"
+        );
+        let credentials = Credentials::new("key", SECRET).unwrap();
+        assert_eq!(
+            credentials.sign("/0/private/AddOrder", 1616492376594, &body),
+            "4/dpxb3iT4tp/ZCVEwSnEsLxx0bqyhLpdfOpc6fn7OR8+UClSV5n9E6aSS8MPtnRfp32bAb0nmbRn6H8ndwLUQ=="
+        );
+
+        let range = HistoryRange {
+            start: Some("1688667796".to_string()),
+            offset: Some(50),
+            ..HistoryRange::default()
+        };
+        assert_eq!(
+            post_data(7, Some("123 456"), &range).unwrap(),
+            "nonce=7&otp=123+456&ofs=50&start=1688667796"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_retries_invalid_nonce() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let credentials = Credentials::new("my-key", SECRET).unwrap();
+        let mut client = PrivateRestClient::with_base_url(
+            &format!("http://{}", listener.local_addr().unwrap()),
+            credentials.clone(),
+        );
+        client.set_nonce_provider(Arc::new(AtomicNonce::new()));
+        let server = tokio::spawn(async move {
+            let invalid_nonce = r#"{"error":["EAPI:Invalid nonce"]}"#;
+            let mut requests = Vec::new();
+            for response in [
+                invalid_nonce,
+                r#"{"error":[],"result":{"count":1}}"#,
+                invalid_nonce,
+            ] {
+                requests.push(serve_http_once(&listener, response).await);
+            }
+            requests
+        });
+
+        assert_eq!(client.cancel_order("OU22CG-KLAF2-FWUDD7").await.unwrap(), 1);
+        client.set_nonce_retries(0);
+        match client.cancel_order("OU22CG-KLAF2-FWUDD7").await {
+            Err(Error::Api(errors)) => assert_eq!(errors, vec![ApiError::InvalidNonce]),
+            other => panic!("expected an API error, got {:?}", other),
+        }
+
+        let requests = server.await.unwrap();
+        let nonces: Vec<String> = requests
+            .iter()
+            .map(|request| {
+                assert_signed(request, &credentials);
+                form_urlencoded::parse(request.body.as_bytes())
+                    .find(|(name, _)| name == "nonce")
+                    .unwrap()
+                    .1
+                    .into_owned()
+            })
+            .collect();
+        // The retry is signed again with a fresh nonce
+        assert!(nonces[1].parse::<u64>().unwrap() > nonces[0].parse::<u64>().unwrap());
+        assert_eq!(requests[0].path, requests[1].path);
+    }
+
+    #[tokio::test]
+    async fn test_file_nonce_signs_calls() {
+        let path = std::env::temp_dir().join(format!("kraken-rest-nonce-{}", std::process::id()));
+        std::fs::write(&path, "99999999999999").unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let credentials = Credentials::new("my-key", SECRET).unwrap();
+        let mut client = PrivateRestClient::with_base_url(
+            &format!("http://{}", listener.local_addr().unwrap()),
+            credentials.clone(),
+        );
+        client.set_nonce_provider(Arc::new(FileNonce::new(&path)));
+        let server = tokio::spawn(async move {
+            serve_http_once(&listener, r#"{"error":[],"result":{"count":1}}"#).await
+        });
+
+        assert_eq!(client.cancel_order("OU22CG-KLAF2-FWUDD7").await.unwrap(), 1);
+        let request = server.await.unwrap();
+        assert_signed(&request, &credentials);
+        assert!(request.body.starts_with("nonce=100000000000000&"));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "100000000000000");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_rate_limiter_rejects_before_kraken() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = PrivateRestClient::with_base_url(
+            &format!("http://{}", listener.local_addr().unwrap()),
+            Credentials::new("my-key", SECRET).unwrap(),
+        );
+        client.set_rate_limiter(Some(RateLimiter::new(RateLimitConfig {
+            max_counter: 3.0,
+            decay_per_second: 0.01,
+            when_limited: WhenLimited::Reject,
+        })));
+        let server = tokio::spawn(async move {
+            for response in [
+                r#"{"error":[],"result":{"trades":{},"count":0}}"#,
+                r#"{"error":["EAPI:Rate limit exceeded"]}"#,
+            ] {
+                serve_http_once(&listener, response).await;
+            }
+        });
+
+        let history = client
+            .trades_history(&HistoryRange::default())
+            .await
+            .unwrap();
+        assert_eq!(history.count, 0);
+        assert!(client.rate_limit().unwrap().counter >= 1.99);
+        // Costs two more, which the counter has no room for; nothing is sent
+        assert!(matches!(
+            client.trades_history(&HistoryRange::default()).await,
+            Err(Error::RateLimited(_))
+        ));
+
+        // Kraken disagreeing with the model fills it up
+        match client.balance().await {
+            Err(Error::Api(errors)) => assert_eq!(errors, vec![ApiError::RateLimitExceeded]),
+            other => panic!("expected an API error, got {:?}", other),
+        }
+        assert!(client.rate_limit().unwrap().counter >= 2.99);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_private_endpoints() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let credentials = Credentials::new("my-key", SECRET).unwrap();
+        let mut client = PrivateRestClient::with_base_url(
+            &format!("http://{}", listener.local_addr().unwrap()),
+            credentials.clone(),
+        );
+        let server = tokio::spawn(async move {
+            let mut requests = Vec::new();
+            for response in [
+                r#"{"error":[],"result":{"ZUSD":"171288.6158","XXBT":"0.0011000000"}}"#,
+                r#"{"error":[],"result":{"eb":"3224744.0162","tb":"3224744.0162","m":"0.0000","n":"0.0000","c":"0.0000","v":"0.0000","e":"3224744.0162","mf":"3224744.0162"}}"#,
+                r#"{"error":[],"result":{"open":{"OQCLML-BW3P3-BUCMWZ":{"refid":null,"userref":0,"status":"open","opentm":1688666559.8974,"starttm":0,"expiretm":0,"descr":{"pair":"XBTUSD","type":"buy","ordertype":"limit","price":"30010.0","price2":"0","leverage":"none","order":"buy 1.25000000 XBTUSD @ limit 30010.0","close":""},"vol":"1.25000000","vol_exec":"0.37500000","cost":"11253.7","fee":"0.00000","price":"30010.0","stopprice":"0.00000","limitprice":"0.00000","misc":"","oflags":"fciq","trades":["TCCCTY-WE2O6-P3NB37"]}}}}"#,
+                r#"{"error":[],"result":{"closed":{"OQCLML-BW3P3-BUCMWZ":{"refid":null,"userref":0,"status":"closed","reason":null,"opentm":1688666559.8974,"closetm":1688666559.9011,"starttm":0,"expiretm":0,"descr":{"pair":"XBTUSD","type":"buy","ordertype":"limit","price":"30010.0","price2":"0","leverage":"none","order":"buy 1.25000000 XBTUSD @ limit 30010.0","close":""},"vol":"1.25000000","vol_exec":"1.25000000","cost":"37526.2","fee":"37.5","price":"30021.0","stopprice":"0.00000","limitprice":"0.00000","misc":"","oflags":"fciq"}},"count":1}}"#,
+                r#"{"error":[],"result":{"trades":{"THVRQM-33VKH-UCI7BS":{"ordertxid":"OQCLML-BW3P3-BUCMWZ","postxid":"TKH2SE-M7IF5-CFI7LT","pair":"XXBTZUSD","time":1688667796.8802,"type":"buy","ordertype":"limit","price":"30010.00000","cost":"600.20000","fee":"0.00000","vol":"0.02000000","margin":"0.00000","misc":"","trade_id":39482674,"maker":true},"TCWJEG-FL4SZ-3FKGH6":{"ordertxid":"OQCLML-BW3P3-BUCMWZ","postxid":"TKH2SE-M7IF5-CFI7LT","pair":"XXBTZUSD","time":1688667769.6396,"type":"buy","ordertype":"limit","price":"30010.00000","cost":"300.10000","fee":"0.00000","vol":"0.01000000","margin":"0.00000","misc":"","trade_id":39482673,"maker":true}},"count":2}}"#,
+                r#"{"error":[],"result":{"descr":{"order":"buy 1.25000000 XBTUSD @ limit 27500.0"},"txid":["OU22CG-KLAF2-FWUDD7"]}}"#,
+                r#"{"error":[],"result":{"count":1}}"#,
+                r#"{"error":["EOrder:Unknown order"]}"#,
+            ] {
+                requests.push(serve_http_once(&listener, response).await);
+            }
+            requests
+        });
+
+        let balances = client.balance().await.unwrap();
+        assert_eq!(balances["XXBT"], dec("0.0011"));
+        let balance = client.trade_balance(None).await.unwrap();
+        assert_eq!(balance.equity, dec("3224744.0162"));
+        assert_eq!(balance.margin_level, None);
+
+        let open = client.open_orders().await.unwrap();
+        assert_eq!(open[0].order_id, "OQCLML-BW3P3-BUCMWZ");
+        assert_eq!(open[0].status, Some(OrderStatus::Open));
+        assert_eq!(open[0].avg_price, Some(dec("30010")));
+
+        client.set_otp(Some("123456"));
+        let closed = client
+            .closed_orders(&HistoryRange::default())
+            .await
+            .unwrap();
+        assert_eq!(closed.count, 1);
+        assert_eq!(closed.entries[0].close_time, Some(dec("1688666559.9011")));
+
+        let trades = client
+            .trades_history(&HistoryRange::default())
+            .await
+            .unwrap();
+        assert_eq!(trades.count, 2);
+        assert_eq!(trades.entries[0].trade_id, "THVRQM-33VKH-UCI7BS");
+        assert_eq!(trades.entries[1].volume, dec("0.01"));
+        client.set_otp(None);
+
+        let order = AddOrder::limit(Side::Buy, "XBTUSD", dec("1.25"), dec("27500"));
+        let placed = client.add_order(&order).await.unwrap();
+        assert_eq!(placed.txid.as_deref(), Some("OU22CG-KLAF2-FWUDD7"));
+        assert_eq!(client.cancel_order("OU22CG-KLAF2-FWUDD7").await.unwrap(), 1);
+        match client.cancel_order("OU22CG-KLAF2-FWUDD7").await {
+            Err(Error::Api(errors)) => assert_eq!(errors, vec![ApiError::UnknownOrder]),
+            other => panic!("expected an API error, got {:?}", other),
+        }
+
+        let requests = server.await.unwrap();
+        for request in &requests {
+            assert_eq!(request.method, "POST");
+            assert_signed(request, &credentials);
+        }
+        assert_eq!(requests[0].path, "/0/private/Balance");
+        assert!(!requests[2].body.contains("otp"));
+        assert!(requests[3].body.contains("&otp=123456"));
+        assert!(!requests[5].body.contains("otp"));
+        assert!(requests[5]
+            .body
+            .ends_with("&ordertype=limit&pair=XBTUSD&price=27500&type=buy&volume=1.25"));
+        assert!(requests[6].body.ends_with("&txid=OU22CG-KLAF2-FWUDD7"));
+    }
+}
@@ -0,0 +1,271 @@
+use crate::error::Error;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Kraken verification tiers, which set the size and decay of the API counter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tier {
+    Starter,
+    Intermediate,
+    Pro,
+}
+
+/// What to do with a call that would push the counter past its maximum.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WhenLimited {
+    // Hold the call until enough of the counter has decayed
+    Wait,
+    // Fail with `Error::RateLimited` without sending anything
+    Reject,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateLimitConfig {
+    pub max_counter: f64,
+    // How much the counter drops every second
+    pub decay_per_second: f64,
+    pub when_limited: WhenLimited,
+}
+
+impl RateLimitConfig {
+    // Kraken's published limits for each tier
+    pub fn for_tier(tier: Tier) -> Self {
+        let (max_counter, decay_per_second) = match tier {
+            Tier::Starter => (15.0, 0.33),
+            Tier::Intermediate => (20.0, 0.5),
+            Tier::Pro => (20.0, 1.0),
+        };
+        RateLimitConfig {
+            max_counter,
+            decay_per_second,
+            when_limited: WhenLimited::Wait,
+        }
+    }
+}
+
+/// A snapshot of the modelled counter.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateLimitState {
+    // Decayed up to the moment the snapshot was taken
+    pub counter: f64,
+    pub max_counter: f64,
+    // Calls currently held back until the counter has room for them
+    pub waiting: usize,
+}
+
+// How much a private call adds to the counter. Ledger and trade history
+// queries cost two; order placement and cancellation are limited per pair
+// by the trading engine instead, which is not modelled, and cost nothing here.
+pub fn call_cost(method: &str) -> u32 {
+    match method {
+        "Ledgers" | "QueryLedgers" | "TradesHistory" | "QueryTrades" => 2,
+        "AddOrder"
+        | "AddOrderBatch"
+        | "EditOrder"
+        | "CancelOrder"
+        | "CancelOrderBatch"
+        | "CancelAll"
+        | "CancelAllOrdersAfter" => 0,
+        _ => 1,
+    }
+}
+
+/// Client-side model of Kraken's API call counter.
+///
+/// Every private call adds its cost to the counter, which decays linearly
+/// over time; Kraken rejects calls with `EAPI:Rate limit exceeded` once it
+/// would go over the tier's maximum. Calls are held back or rejected before
+/// that happens. Clones share the same counter, so one limiter can be given
+/// to every client using the key. Time is read from `tokio::time`, so tests
+/// can pause and advance it.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    counter: Arc<Mutex<Counter>>,
+}
+
+#[derive(Debug)]
+struct Counter {
+    value: f64,
+    updated: Instant,
+    waiting: usize,
+}
+
+impl Counter {
+    fn decay(&mut self, decay_per_second: f64) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.value = (self.value - elapsed * decay_per_second).max(0.0);
+        self.updated = now;
+    }
+}
+
+// One call held back in `acquire`
+struct Waiting<'a> {
+    counter: &'a Mutex<Counter>,
+}
+
+impl<'a> Waiting<'a> {
+    fn new(counter: &'a Mutex<Counter>) -> Self {
+        counter.lock().unwrap().waiting += 1;
+        Waiting { counter }
+    }
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.counter.lock().unwrap().waiting -= 1;
+    }
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        RateLimiter {
+            config,
+            counter: Arc::new(Mutex::new(Counter {
+                value: 0.0,
+                updated: Instant::now(),
+                waiting: 0,
+            })),
+        }
+    }
+
+    pub fn config(&self) -> &RateLimitConfig {
+        &self.config
+    }
+
+    pub fn state(&self) -> RateLimitState {
+        let mut counter = self.counter.lock().unwrap();
+        counter.decay(self.config.decay_per_second);
+        RateLimitState {
+            counter: counter.value,
+            max_counter: self.config.max_counter,
+            waiting: counter.waiting,
+        }
+    }
+
+    // Adds `cost` to the counter, or says how long until it would fit;
+    // `Duration::MAX` when it never will
+    pub fn try_acquire(&self, cost: u32) -> Result<(), Duration> {
+        let cost = f64::from(cost);
+        let mut counter = self.counter.lock().unwrap();
+        counter.decay(self.config.decay_per_second);
+        let excess = counter.value + cost - self.config.max_counter;
+        if excess <= 0.0 {
+            counter.value += cost;
+            Ok(())
+        } else if cost > self.config.max_counter || self.config.decay_per_second <= 0.0 {
+            Err(Duration::MAX)
+        } else {
+            Err(Duration::from_secs_f64(
+                excess / self.config.decay_per_second,
+            ))
+        }
+    }
+
+    // Room for `cost` on the counter, waited for or refused as configured
+    pub async fn acquire(&self, cost: u32) -> Result<(), Error> {
+        // Counts the call as waiting until it returns or is dropped
+        let mut waiting = None;
+        loop {
+            match self.try_acquire(cost) {
+                Ok(()) => return Ok(()),
+                Err(wait) if self.config.when_limited == WhenLimited::Reject => {
+                    return Err(Error::RateLimited(wait))
+                }
+                Err(Duration::MAX) => return Err(Error::RateLimited(Duration::MAX)),
+                Err(wait) => {
+                    waiting.get_or_insert_with(|| Waiting::new(&self.counter));
+                    tokio::time::sleep(wait).await;
+                }
+            }
+        }
+    }
+
+    // Kraken said the counter is full, e.g. because another process used the key
+    pub fn exceeded(&self) {
+        let mut counter = self.counter.lock().unwrap();
+        counter.decay(self.config.decay_per_second);
+        counter.value = counter.value.max(self.config.max_counter);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn starter(when_limited: WhenLimited) -> RateLimiter {
+        RateLimiter::new(RateLimitConfig {
+            when_limited,
+            ..RateLimitConfig::for_tier(Tier::Starter)
+        })
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_counter_fills_and_decays() {
+        let limiter = starter(WhenLimited::Reject);
+        for _ in 0..7 {
+            limiter.try_acquire(call_cost("TradesHistory")).unwrap();
+        }
+        limiter.try_acquire(call_cost("Balance")).unwrap();
+        assert_eq!(limiter.state().counter, 15.0);
+
+        // Orders are limited elsewhere and always go through
+        limiter.try_acquire(call_cost("AddOrder")).unwrap();
+        let wait = limiter.try_acquire(call_cost("Balance")).unwrap_err();
+        assert_eq!(wait, Duration::from_secs_f64(1.0 / 0.33));
+        assert!(matches!(
+            limiter.acquire(1).await,
+            Err(Error::RateLimited(_))
+        ));
+
+        tokio::time::advance(Duration::from_secs(3)).await;
+        assert!((limiter.state().counter - 14.01).abs() < 1e-9);
+        assert!(limiter.try_acquire(1).is_err());
+        tokio::time::advance(Duration::from_millis(100)).await;
+        limiter.try_acquire(1).unwrap();
+
+        tokio::time::advance(Duration::from_secs(60)).await;
+        assert_eq!(limiter.state().counter, 0.0);
+        limiter.exceeded();
+        assert_eq!(limiter.state().counter, 15.0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_waiting_calls_are_queued() {
+        let limiter = starter(WhenLimited::Wait);
+        for _ in 0..15 {
+            limiter.acquire(1).await.unwrap();
+        }
+
+        let start = Instant::now();
+        let queued = {
+            let limiter = limiter.clone();
+            tokio::spawn(async move {
+                limiter.acquire(2).await.unwrap();
+                limiter.acquire(2).await.unwrap();
+            })
+        };
+        tokio::task::yield_now().await;
+        assert_eq!(limiter.state().waiting, 1);
+
+        // A caller that gives up is no longer counted
+        let abandoned = {
+            let limiter = limiter.clone();
+            tokio::spawn(async move { limiter.acquire(1).await })
+        };
+        tokio::task::yield_now().await;
+        assert_eq!(limiter.state().waiting, 2);
+        abandoned.abort();
+        assert!(abandoned.await.unwrap_err().is_cancelled());
+        assert_eq!(limiter.state().waiting, 1);
+
+        queued.await.unwrap();
+        assert_eq!(limiter.state().waiting, 0);
+        // Four units had to decay first
+        let waited = start.elapsed().as_secs_f64();
+        assert!((waited - 4.0 / 0.33).abs() < 0.01, "waited {}s", waited);
+        assert!(limiter.acquire(20).await.is_err());
+    }
+}
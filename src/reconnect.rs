@@ -0,0 +1,105 @@
+use rand::Rng;
+use std::time::Duration;
+
+/// How the client retries after the connection drops.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReconnectConfig {
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    // Attempts per disconnect before giving up; `None` retries forever
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        ReconnectConfig {
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(60),
+            max_attempts: None,
+        }
+    }
+}
+
+// Exponential backoff with equal jitter: attempt n waits between half and all of
+// `initial_delay * 2^n`, capped at `max_delay`
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    config: ReconnectConfig,
+    attempt: u32,
+}
+
+impl Backoff {
+    pub fn new(config: ReconnectConfig) -> Self {
+        Backoff { config, attempt: 0 }
+    }
+
+    pub fn attempt(&self) -> u32 {
+        self.attempt
+    }
+
+    // Delay before the next attempt, or `None` once the attempts are used up
+    pub fn next_delay(&mut self) -> Option<Duration> {
+        if let Some(max_attempts) = self.config.max_attempts {
+            if self.attempt >= max_attempts {
+                return None;
+            }
+        }
+
+        let exponent = self.attempt.min(31);
+        let base = self
+            .config
+            .initial_delay
+            .saturating_mul(1 << exponent)
+            .min(self.config.max_delay);
+        self.attempt += 1;
+
+        let half = base / 2;
+        let jitter = rand::thread_rng().gen_range(0..=half.as_millis() as u64);
+        Some(half + Duration::from_millis(jitter))
+    }
+
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_grows_with_jitter_and_caps() {
+        let mut backoff = Backoff::new(ReconnectConfig {
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(1000),
+            max_attempts: None,
+        });
+
+        let expected_bases = [100, 200, 400, 800, 1000, 1000];
+        for base in expected_bases {
+            let delay = backoff.next_delay().unwrap().as_millis() as u64;
+            assert!(
+                delay >= base / 2 && delay <= base,
+                "{} outside [{}, {}]",
+                delay,
+                base / 2,
+                base
+            );
+        }
+        assert_eq!(backoff.attempt(), 6);
+
+        backoff.reset();
+        assert!(backoff.next_delay().unwrap() <= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn test_backoff_gives_up_after_max_attempts() {
+        let mut backoff = Backoff::new(ReconnectConfig {
+            max_attempts: Some(2),
+            ..ReconnectConfig::default()
+        });
+        assert!(backoff.next_delay().is_some());
+        assert!(backoff.next_delay().is_some());
+        assert!(backoff.next_delay().is_none());
+    }
+}
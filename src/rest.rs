@@ -0,0 +1,474 @@
+use crate::decimal::Decimal;
+use crate::error::{ApiError, Error};
+use crate::messages::{BookSnapshot, Ohlc, Spread, TickerClose, TickerQuote, TodayAnd24h, Trade};
+use serde::de::{self, DeserializeOwned};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+
+pub const REST_URL: &str = "https://api.kraken.com";
+
+/// Async client for Kraken's public REST endpoints.
+///
+/// Every response comes wrapped as `{"error": [...], "result": {...}}`;
+/// methods return the unwrapped result or `Error::Api` with the parsed errors.
+/// Pairs can be given in any form Kraken accepts, e.g. `XBTUSD` or `XBT/USD`,
+/// while results are keyed by Kraken's own names such as `XXBTZUSD`.
+#[derive(Debug, Clone)]
+pub struct RestClient {
+    http: reqwest::Client,
+    base_url: String,
+}
+
+impl Default for RestClient {
+    fn default() -> Self {
+        RestClient::new()
+    }
+}
+
+impl RestClient {
+    pub fn new() -> Self {
+        RestClient::with_base_url(REST_URL)
+    }
+
+    // For a proxy or a local stand-in in tests
+    pub fn with_base_url(base_url: &str) -> Self {
+        RestClient {
+            http: reqwest::Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+        }
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    // Shared with the private client so both reuse the same connections
+    pub(crate) fn http(&self) -> &reqwest::Client {
+        &self.http
+    }
+
+    pub async fn server_time(&self) -> Result<ServerTime, Error> {
+        self.public("Time", &[]).await
+    }
+
+    pub async fn system_status(&self) -> Result<SystemStatus, Error> {
+        self.public("SystemStatus", &[]).await
+    }
+
+    // All assets when `assets` is empty
+    pub async fn assets(&self, assets: &[&str]) -> Result<HashMap<String, AssetInfo>, Error> {
+        self.public("Assets", &list("asset", assets)).await
+    }
+
+    // All tradable pairs when `pairs` is empty
+    pub async fn asset_pairs(&self, pairs: &[&str]) -> Result<HashMap<String, AssetPair>, Error> {
+        self.public("AssetPairs", &list("pair", pairs)).await
+    }
+
+    pub async fn ticker(&self, pairs: &[&str]) -> Result<HashMap<String, TickerInfo>, Error> {
+        self.public("Ticker", &list("pair", pairs)).await
+    }
+
+    // Up to 720 candles of `interval` minutes; the last one is still open
+    pub async fn ohlc(
+        &self,
+        pair: &str,
+        interval: u32,
+        since: Option<u64>,
+    ) -> Result<PairHistory<Ohlc>, Error> {
+        let mut params = vec![
+            ("pair", pair.to_string()),
+            ("interval", interval.to_string()),
+        ];
+        params.extend(since.map(|since| ("since", since.to_string())));
+        let history: PairHistory<RestOhlc> = self.public("OHLC", &params).await?;
+
+        // REST candles only carry their start, the WebSocket ones their end too
+        let length = Decimal::from(i64::from(interval) * 60);
+        Ok(PairHistory {
+            pair: history.pair,
+            entries: history
+                .entries
+                .into_iter()
+                .map(
+                    |RestOhlc(time, open, high, low, close, vwap, volume, count)| Ohlc {
+                        time,
+                        end_time: time + length,
+                        open,
+                        high,
+                        low,
+                        close,
+                        vwap,
+                        volume,
+                        count,
+                    },
+                )
+                .collect(),
+            last: history.last,
+        })
+    }
+
+    // A snapshot that can initialize an `OrderBook`; `count` caps the levels per side
+    pub async fn depth(&self, pair: &str, count: Option<usize>) -> Result<BookSnapshot, Error> {
+        let mut params = vec![("pair", pair.to_string())];
+        params.extend(count.map(|count| ("count", count.to_string())));
+        let books: HashMap<String, BookSnapshot> = self.public("Depth", &params).await?;
+        books
+            .into_values()
+            .next()
+            .ok_or_else(|| invalid_result("no book in the response"))
+    }
+
+    // Up to 1000 trades after `since`, a trade ID or a timestamp in nanoseconds
+    pub async fn trades(
+        &self,
+        pair: &str,
+        since: Option<u64>,
+    ) -> Result<PairHistory<Trade>, Error> {
+        let mut params = vec![("pair", pair.to_string())];
+        params.extend(since.map(|since| ("since", since.to_string())));
+        self.public("Trades", &params).await
+    }
+
+    pub async fn spread(
+        &self,
+        pair: &str,
+        since: Option<u64>,
+    ) -> Result<PairHistory<Spread>, Error> {
+        let mut params = vec![("pair", pair.to_string())];
+        params.extend(since.map(|since| ("since", since.to_string())));
+        let history: PairHistory<RestSpread> = self.public("Spread", &params).await?;
+        Ok(PairHistory {
+            pair: history.pair,
+            entries: history
+                .entries
+                .into_iter()
+                .map(|RestSpread(timestamp, bid, ask)| Spread {
+                    bid,
+                    ask,
+                    timestamp,
+                    bid_volume: None,
+                    ask_volume: None,
+                })
+                .collect(),
+            last: history.last,
+        })
+    }
+
+    async fn public<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: &[(&str, String)],
+    ) -> Result<T, Error> {
+        let response = self
+            .http
+            .get(format!("{}/0/public/{}", self.base_url, method))
+            .query(params)
+            .send()
+            .await?;
+        let envelope: Envelope<T> = serde_json::from_slice(&response.bytes().await?)?;
+        envelope.into_result()
+    }
+}
+
+// Several assets or pairs go in one comma-separated parameter
+fn list(name: &'static str, values: &[&str]) -> Vec<(&'static str, String)> {
+    if values.is_empty() {
+        Vec::new()
+    } else {
+        vec![(name, values.join(","))]
+    }
+}
+
+fn invalid_result(message: &str) -> Error {
+    Error::Json(de::Error::custom(message))
+}
+
+/// `{"error": [...], "result": ...}`, the wrapper around every REST response.
+#[derive(Debug, Deserialize)]
+pub(crate) struct Envelope<T> {
+    error: Vec<String>,
+    result: Option<T>,
+}
+
+impl<T> Envelope<T> {
+    // Warnings, prefixed `W`, come in the same array but do not fail the call
+    pub(crate) fn into_result(self) -> Result<T, Error> {
+        let errors: Vec<ApiError> = self
+            .error
+            .iter()
+            .filter(|error| !error.starts_with('W'))
+            .map(|error| ApiError::parse(error))
+            .collect();
+        match self.result {
+            Some(result) if errors.is_empty() => Ok(result),
+            None if errors.is_empty() => Err(invalid_result("response has no result")),
+            _ => Err(Error::Api(errors)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ServerTime {
+    pub unixtime: u64,
+    pub rfc1123: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct SystemStatus {
+    pub status: SystemState,
+    // RFC 3339, e.g. `2023-07-06T18:52:00Z`
+    pub timestamp: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SystemState {
+    Online,
+    Maintenance,
+    CancelOnly,
+    PostOnly,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct AssetInfo {
+    pub aclass: String,
+    pub altname: String,
+    pub decimals: u32,
+    pub display_decimals: u32,
+    pub collateral_value: Option<Decimal>,
+    pub status: Option<String>,
+}
+
+// Only the fields the client works with; `pair_decimals` and `lot_decimals`
+// are what `OrderBook::with_precision` needs for v2 checksums
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct AssetPair {
+    pub altname: String,
+    // Name on the WebSocket, e.g. `XBT/USD`; missing for dark pool pairs
+    pub wsname: Option<String>,
+    pub base: String,
+    pub quote: String,
+    pub pair_decimals: u32,
+    pub lot_decimals: u32,
+    pub cost_decimals: Option<u32>,
+    pub ordermin: Option<Decimal>,
+    pub costmin: Option<Decimal>,
+    pub tick_size: Option<Decimal>,
+    pub status: Option<String>,
+}
+
+/// Like the WebSocket `Ticker`, except that `o` is only today's opening price.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct TickerInfo {
+    #[serde(rename = "a")]
+    pub ask: TickerQuote,
+    #[serde(rename = "b")]
+    pub bid: TickerQuote,
+    #[serde(rename = "c")]
+    pub close: TickerClose,
+    #[serde(rename = "v")]
+    pub volume: TodayAnd24h<Decimal>,
+    #[serde(rename = "p")]
+    pub vwap: TodayAnd24h<Decimal>,
+    #[serde(rename = "t")]
+    pub trades: TodayAnd24h<u64>,
+    #[serde(rename = "l")]
+    pub low: TodayAnd24h<Decimal>,
+    #[serde(rename = "h")]
+    pub high: TodayAnd24h<Decimal>,
+    #[serde(rename = "o")]
+    pub open: Decimal,
+}
+
+/// One page of a pair's history; pass `last` as `since` to get the next page.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PairHistory<T> {
+    pub pair: String,
+    pub entries: Vec<T>,
+    pub last: u64,
+}
+
+// `{"XXBTZUSD": [...], "last": ...}`, where `last` is a number or a string
+impl<'de, T: DeserializeOwned> Deserialize<'de> for PairHistory<T> {
+    fn deserialize<D: de::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut result = Map::deserialize(deserializer)?;
+        let last = match result.remove("last") {
+            Some(Value::String(last)) => last.parse().map_err(de::Error::custom)?,
+            Some(last) => last
+                .as_u64()
+                .ok_or_else(|| de::Error::custom(format!("invalid last: {}", last)))?,
+            None => return Err(de::Error::missing_field("last")),
+        };
+        let (pair, entries) = result
+            .into_iter()
+            .next()
+            .ok_or_else(|| de::Error::custom("no pair in the result"))?;
+        Ok(PairHistory {
+            pair,
+            entries: serde_json::from_value(entries).map_err(de::Error::custom)?,
+            last,
+        })
+    }
+}
+
+// `[time, open, high, low, close, vwap, volume, count]`
+#[derive(Deserialize)]
+struct RestOhlc(
+    Decimal,
+    Decimal,
+    Decimal,
+    Decimal,
+    Decimal,
+    Decimal,
+    Decimal,
+    u64,
+);
+
+// `[time, bid, ask]`
+#[derive(Deserialize)]
+struct RestSpread(Decimal, Decimal, Decimal);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::Side;
+    use crate::order_book::{BookStatus, OrderBook};
+    use crate::test_support::serve_http_once;
+    use tokio::net::TcpListener;
+
+    fn dec(s: &str) -> Decimal {
+        s.parse().unwrap()
+    }
+
+    // Example responses from Kraken's REST documentation, trimmed
+    const RESPONSES: &[&str] = &[
+        r#"{"error":[],"result":{"unixtime":1688669448,"rfc1123":"Thu, 06 Jul 23 18:50:48 +0000"}}"#,
+        r#"{"error":[],"result":{"status":"online","timestamp":"2023-07-06T18:52:00Z"}}"#,
+        r#"{"error":[],"result":{"XXBT":{"aclass":"currency","altname":"XBT","decimals":10,"display_decimals":5,"collateral_value":1,"status":"enabled"}}}"#,
+        r#"{"error":[],"result":{"XXBTZUSD":{"altname":"XBTUSD","wsname":"XBT/USD","aclass_base":"currency","base":"XXBT","aclass_quote":"currency","quote":"ZUSD","lot":"unit","cost_decimals":5,"pair_decimals":1,"lot_decimals":8,"lot_multiplier":1,"leverage_buy":[2,3,4,5],"leverage_sell":[2,3,4,5],"fees":[[0,0.26],[50000,0.24]],"fees_maker":[[0,0.16],[50000,0.14]],"fee_volume_currency":"ZUSD","margin_call":80,"margin_stop":40,"ordermin":"0.0001","costmin":"0.5","tick_size":"0.1","status":"online"}}}"#,
+        r#"{"error":[],"result":{"XXBTZUSD":{"a":["30300.10000","1","1.000"],"b":["30300.00000","1","1.000"],"c":["30303.20000","0.00067643"],"v":["4083.67001100","4412.73601799"],"p":["30706.77771","30689.13205"],"t":[34619,38907],"l":["29868.30000","29868.30000"],"h":["31631.00000","31631.00000"],"o":"30502.80000"}}}"#,
+        r#"{"error":[],"result":{"XXBTZUSD":[[1688671200,"30306.1","30306.2","30305.7","30305.7","30306.1","3.39243896",23],[1688671260,"30304.5","30304.5","30300.0","30300.0","30300.0","4.42996871",18]],"last":1688672160}}"#,
+        r#"{"error":[],"result":{"XXBTZUSD":{"asks":[["30384.10000","2.059",1688671659],["30387.90000","1.500",1688671380]],"bids":[["30297.00000","0.115",1688671656],["30296.70000","0.002",1688671674]]}}}"#,
+        r#"{"error":[],"result":{"XXBTZUSD":[["30243.40000","0.34507674",1688669597.8277369,"b","m","",61044952],["30243.30000","0.00376960",1688669598.2804112,"s","l","",61044953]],"last":"1688671969993150842"}}"#,
+        r#"{"error":[],"result":{"XXBTZUSD":[[1688671834,"30292.10000","30297.50000"],[1688671834,"30292.10000","30296.70000"]],"last":1688672106}}"#,
+    ];
+
+    #[tokio::test]
+    async fn test_public_endpoints() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client =
+            RestClient::with_base_url(&format!("http://{}/", listener.local_addr().unwrap()));
+        let server = tokio::spawn(async move {
+            let mut paths = Vec::new();
+            for response in RESPONSES {
+                paths.push(serve_http_once(&listener, response).await.path);
+            }
+            paths
+        });
+
+        assert_eq!(client.server_time().await.unwrap().unixtime, 1688669448);
+        assert_eq!(
+            client.system_status().await.unwrap().status,
+            SystemState::Online
+        );
+        assert_eq!(client.assets(&[]).await.unwrap()["XXBT"].decimals, 10);
+
+        let pairs = client.asset_pairs(&["XBTUSD"]).await.unwrap();
+        let pair = &pairs["XXBTZUSD"];
+        assert_eq!(pair.wsname.as_deref(), Some("XBT/USD"));
+        assert_eq!((pair.pair_decimals, pair.lot_decimals), (1, 8));
+        assert_eq!(pair.ordermin, Some(dec("0.0001")));
+
+        let tickers = client.ticker(&["XBTUSD", "ETHUSD"]).await.unwrap();
+        let ticker = &tickers["XXBTZUSD"];
+        assert_eq!(ticker.ask.whole_lot_volume, 1);
+        assert_eq!(ticker.trades.last_24h, 38907);
+        assert_eq!(ticker.open, dec("30502.8"));
+
+        let candles = client.ohlc("XBTUSD", 1, Some(1688671100)).await.unwrap();
+        assert_eq!(candles.pair, "XXBTZUSD");
+        assert_eq!(candles.last, 1688672160);
+        assert_eq!(candles.entries[1].end_time, dec("1688671320"));
+        assert_eq!(candles.entries[1].count, 18);
+
+        let snapshot = client.depth("XBTUSD", Some(2)).await.unwrap();
+        let mut book = OrderBook::with_precision(10, 1, 8);
+        book.initialize(&snapshot);
+        assert_eq!(book.status(), BookStatus::Synced);
+        assert_eq!(book.asks().next().unwrap().price, dec("30384.1"));
+        assert_eq!(book.bids().next().unwrap().volume, dec("0.115"));
+
+        let trades = client.trades("XBTUSD", None).await.unwrap();
+        assert_eq!(trades.last, 1688671969993150842);
+        assert_eq!(trades.entries[1].side, Side::Sell);
+        assert_eq!(trades.entries[1].trade_id, Some(61044953));
+
+        let spreads = client.spread("XBTUSD", None).await.unwrap();
+        assert_eq!(spreads.entries[0].timestamp, dec("1688671834"));
+        assert_eq!(spreads.entries[0].bid, dec("30292.1"));
+        assert_eq!(spreads.entries[0].ask, dec("30297.5"));
+
+        let paths = server.await.unwrap();
+        assert_eq!(paths[0], "/0/public/Time");
+        assert_eq!(paths[2], "/0/public/Assets");
+        assert_eq!(paths[4], "/0/public/Ticker?pair=XBTUSD%2CETHUSD");
+        assert_eq!(
+            paths[5],
+            "/0/public/OHLC?pair=XBTUSD&interval=1&since=1688671100"
+        );
+        assert_eq!(paths[6], "/0/public/Depth?pair=XBTUSD&count=2");
+    }
+
+    #[tokio::test]
+    async fn test_api_errors() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client =
+            RestClient::with_base_url(&format!("http://{}", listener.local_addr().unwrap()));
+        let server = tokio::spawn(async move {
+            serve_http_once(&listener, r#"{"error":["EQuery:Unknown asset pair"]}"#).await;
+            serve_http_once(
+                &listener,
+                r#"{"error":["EGeneral:Invalid arguments:interval","EService:Busy"]}"#,
+            )
+            .await;
+        });
+
+        match client.ticker(&["NOPE"]).await {
+            Err(Error::Api(errors)) => assert_eq!(errors, vec![ApiError::UnknownAssetPair]),
+            other => panic!("expected an API error, got {:?}", other),
+        }
+        let error = client.ohlc("XBTUSD", 2, None).await.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "api error: EGeneral:Invalid arguments, EService:Busy"
+        );
+        server.await.unwrap();
+    }
+
+    #[test]
+    fn test_envelope() {
+        let envelope: Envelope<ServerTime> = serde_json::from_str(
+            r#"{"error":["WGeneral:Deprecated"],"result":{"unixtime":1,"rfc1123":""}}"#,
+        )
+        .unwrap();
+        assert_eq!(envelope.into_result().unwrap().unixtime, 1);
+
+        let envelope: Envelope<ServerTime> = serde_json::from_str(r#"{"error":[]}"#).unwrap();
+        assert!(matches!(envelope.into_result(), Err(Error::Json(_))));
+
+        assert_eq!(
+            ApiError::parse("EAPI:Invalid nonce"),
+            ApiError::InvalidNonce
+        );
+        assert_eq!(
+            ApiError::parse("EOrder:Rate limit exceeded"),
+            ApiError::OrderRateLimitExceeded
+        );
+        assert_eq!(
+            ApiError::parse("EFunding:Unknown withdraw key"),
+            ApiError::Other("EFunding:Unknown withdraw key".to_string())
+        );
+    }
+}
@@ -0,0 +1,253 @@
+use crate::decimal::Decimal;
+use crate::messages::Spread;
+use crate::order_book::{BookStatus, OrderBook};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::time::Duration;
+use tokio::time::Instant;
+
+// Spread records kept per pair
+pub const DEFAULT_SPREAD_HISTORY: usize = 1000;
+
+/// Rolling `spread` channel history per pair, oldest first.
+#[derive(Debug, Clone)]
+pub struct SpreadHistory {
+    limit: usize,
+    spreads: HashMap<String, VecDeque<Spread>>,
+}
+
+impl Default for SpreadHistory {
+    fn default() -> Self {
+        SpreadHistory::new(DEFAULT_SPREAD_HISTORY)
+    }
+}
+
+impl SpreadHistory {
+    pub fn new(limit: usize) -> Self {
+        SpreadHistory {
+            limit,
+            spreads: HashMap::new(),
+        }
+    }
+
+    pub fn latest(&self, pair: &str) -> Option<&Spread> {
+        self.spreads.get(pair)?.back()
+    }
+
+    pub fn history(&self, pair: &str) -> impl Iterator<Item = &Spread> {
+        self.spreads.get(pair).into_iter().flatten()
+    }
+
+    pub fn push(&mut self, pair: &str, spread: Spread) {
+        let history = self.spreads.entry(pair.to_string()).or_default();
+        history.push_back(spread);
+        while history.len() > self.limit {
+            history.pop_front();
+        }
+    }
+}
+
+// Best prices reported by the spread channel that the reconstructed book does not show
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpreadDrift {
+    pub spread_bid: Decimal,
+    pub spread_ask: Decimal,
+    pub book_bid: Option<Decimal>,
+    pub book_ask: Option<Decimal>,
+}
+
+impl fmt::Display for SpreadDrift {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "spread {} / {}, book {} / {}",
+            self.spread_bid,
+            self.spread_ask,
+            optional(self.book_bid),
+            optional(self.book_ask)
+        )
+    }
+}
+
+/// How long spread and book have to disagree before it is reported as drift.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DriftConfig {
+    // Consecutive spread records that disagree with the book
+    pub records: u32,
+    // Or the time since the first of them, whichever is reached first
+    pub duration: Duration,
+}
+
+impl Default for DriftConfig {
+    fn default() -> Self {
+        DriftConfig {
+            records: 3,
+            duration: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Filters `check_spread` results per pair down to drifts that persist.
+#[derive(Debug, Clone, Default)]
+pub struct DriftFilter {
+    config: DriftConfig,
+    streaks: HashMap<String, Streak>,
+}
+
+// Disagreeing records in a row, and when the first of them arrived
+#[derive(Debug, Clone)]
+struct Streak {
+    records: u32,
+    since: Instant,
+}
+
+impl DriftFilter {
+    pub fn new(config: DriftConfig) -> Self {
+        DriftFilter {
+            config,
+            streaks: HashMap::new(),
+        }
+    }
+
+    // The drift once it has persisted long enough; a report starts a new streak
+    pub fn observe(&mut self, pair: &str, drift: Option<SpreadDrift>) -> Option<SpreadDrift> {
+        let Some(drift) = drift else {
+            self.streaks.remove(pair);
+            return None;
+        };
+        let streak = self
+            .streaks
+            .entry(pair.to_string())
+            .or_insert_with(|| Streak {
+                records: 0,
+                since: Instant::now(),
+            });
+        streak.records += 1;
+        if streak.records < self.config.records && streak.since.elapsed() < self.config.duration {
+            return None;
+        }
+        self.streaks.remove(pair);
+        Some(drift)
+    }
+}
+
+fn optional(price: Option<Decimal>) -> String {
+    price.map_or_else(|| "-".to_string(), |price| price.to_string())
+}
+
+// Compares a spread record with the top of a synced book. The two channels are
+// not sequenced against each other, so a single drift can just be a race; a
+// drift that persists means the book has gone wrong.
+pub fn check_spread(spread: &Spread, book: &OrderBook) -> Option<SpreadDrift> {
+    if book.status() != BookStatus::Synced {
+        return None;
+    }
+    let book_bid = book.bids().next().map(|level| level.price);
+    let book_ask = book.asks().next().map(|level| level.price);
+    if book_bid == Some(spread.bid) && book_ask == Some(spread.ask) {
+        return None;
+    }
+    Some(SpreadDrift {
+        spread_bid: spread.bid,
+        spread_ask: spread.ask,
+        book_bid,
+        book_ask,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::{ChannelData, ChannelMessage};
+
+    fn spread(bid: &str, ask: &str) -> Spread {
+        serde_json::from_value(serde_json::json!([
+            bid,
+            ask,
+            "1534614248.123678",
+            "0.1",
+            "0.2"
+        ]))
+        .unwrap()
+    }
+
+    fn synced_book() -> OrderBook {
+        let message: ChannelMessage = serde_json::from_str(
+            r#"[1,{"as":[["5698.40000","1.00000000","1534614248.1"]],"bs":[["5698.30000","2.00000000","1534614248.1"]]},"book-10","XBT/USD"]"#,
+        )
+        .unwrap();
+        let mut book = OrderBook::new(10);
+        match message.data {
+            ChannelData::BookSnapshot(snapshot) => book.initialize(&snapshot),
+            other => panic!("expected a snapshot, got {:?}", other),
+        }
+        book
+    }
+
+    #[test]
+    fn test_rolling_history() {
+        let mut history = SpreadHistory::new(2);
+        assert!(history.latest("XBT/USD").is_none());
+        history.push("XBT/USD", spread("5698.1", "5698.4"));
+        history.push("XBT/USD", spread("5698.2", "5698.4"));
+        history.push("XBT/USD", spread("5698.3", "5698.4"));
+
+        let bids: Vec<_> = history
+            .history("XBT/USD")
+            .map(|spread| spread.bid.to_string())
+            .collect();
+        assert_eq!(bids, vec!["5698.2", "5698.3"]);
+        assert_eq!(
+            history.latest("XBT/USD").unwrap().bid_volume,
+            Some("0.1".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn test_cross_check_against_book() {
+        let book = synced_book();
+        assert_eq!(
+            check_spread(&spread("5698.30000", "5698.40000"), &book),
+            None
+        );
+
+        let drift = check_spread(&spread("5698.30000", "5698.50000"), &book).unwrap();
+        assert_eq!(drift.book_ask, Some("5698.4".parse().unwrap()));
+        assert_eq!(
+            drift.to_string(),
+            "spread 5698.30000 / 5698.50000, book 5698.30000 / 5698.40000"
+        );
+
+        // Books waiting for a snapshot are not compared
+        assert_eq!(check_spread(&spread("1", "2"), &OrderBook::new(10)), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_drift_is_reported_once_it_persists() {
+        let book = synced_book();
+        let mut filter = DriftFilter::new(DriftConfig {
+            records: 3,
+            duration: Duration::from_millis(500),
+        });
+        let mut observe = |bid: &str, ask: &str| {
+            filter.observe("XBT/USD", check_spread(&spread(bid, ask), &book))
+        };
+
+        // A one-off mismatch, e.g. the spread channel running ahead of the book
+        assert_eq!(observe("5698.30000", "5698.50000"), None);
+        assert_eq!(observe("5698.30000", "5698.40000"), None);
+        assert_eq!(observe("5698.30000", "5698.50000"), None);
+        assert_eq!(observe("5698.30000", "5698.40000"), None);
+
+        // Three in a row
+        assert_eq!(observe("5698.30000", "5698.50000"), None);
+        assert_eq!(observe("5698.30000", "5698.60000"), None);
+        let drift = observe("5698.30000", "5698.70000").unwrap();
+        assert_eq!(drift.spread_ask, "5698.7".parse().unwrap());
+
+        // Or two that are far enough apart
+        assert_eq!(observe("5698.30000", "5698.50000"), None);
+        tokio::time::advance(Duration::from_millis(500)).await;
+        assert!(observe("5698.30000", "5698.50000").is_some());
+    }
+}
@@ -0,0 +1,433 @@
+use crate::error::Error;
+use crate::messages::{EventMessage, Request, Subscription};
+use std::collections::HashMap;
+use tokio::sync::oneshot;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SubscriptionState {
+    Pending,
+    Subscribed,
+    Unsubscribed,
+    // Kraken's `errorMessage`
+    Failed(String),
+}
+
+/// One pair on one channel, as last reported by Kraken.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubscriptionEntry {
+    // Empty for private channels, which are not per pair
+    pub pair: String,
+    pub subscription: Subscription,
+    pub state: SubscriptionState,
+    pub channel_id: Option<u64>,
+    pub channel_name: Option<String>,
+}
+
+pub(crate) type Responder = oneshot::Sender<Result<(), Error>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RequestKind {
+    Subscribe,
+    Unsubscribe,
+}
+
+// A request waiting for a subscriptionStatus for each of its pairs
+#[derive(Debug)]
+struct PendingRequest {
+    kind: RequestKind,
+    remaining_pairs: Vec<String>,
+    subscription: Subscription,
+    error: Option<String>,
+    responder: Option<Responder>,
+}
+
+/// Subscribe/unsubscribe requests keyed by `reqid`, and the resulting state of
+/// every subscription on the connection.
+#[derive(Debug, Default)]
+pub struct SubscriptionRegistry {
+    entries: Vec<SubscriptionEntry>,
+    pending: HashMap<u64, PendingRequest>,
+}
+
+impl SubscriptionRegistry {
+    pub fn new() -> Self {
+        SubscriptionRegistry::default()
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &SubscriptionEntry> {
+        self.entries.iter()
+    }
+
+    pub fn get(&self, pair: &str, subscription: &Subscription) -> Option<&SubscriptionEntry> {
+        self.entries
+            .iter()
+            .find(|entry| entry.pair == pair && &entry.subscription == subscription)
+    }
+
+    pub fn by_channel(&self, channel_id: u64) -> Option<&SubscriptionEntry> {
+        self.entries
+            .iter()
+            .find(|entry| entry.channel_id == Some(channel_id))
+    }
+
+    // Number of requests still waiting for Kraken's answer
+    pub fn pending_requests(&self) -> usize {
+        self.pending.len()
+    }
+
+    // Records a request before it is sent; the responder fires once every pair has a status
+    pub(crate) fn register(&mut self, request: &Request, responder: Option<Responder>) {
+        let (kind, reqid, pairs, subscription) = match request {
+            Request::Subscribe {
+                reqid,
+                pair,
+                subscription,
+            } => (RequestKind::Subscribe, reqid, pair, subscription),
+            Request::Unsubscribe {
+                reqid,
+                pair,
+                subscription,
+            } => (RequestKind::Unsubscribe, reqid, pair, subscription),
+            _ => return,
+        };
+        let pairs = entry_pairs(pairs);
+
+        if kind == RequestKind::Subscribe {
+            for pair in &pairs {
+                self.entry_mut(pair, subscription).state = SubscriptionState::Pending;
+            }
+        }
+        if let Some(reqid) = reqid {
+            self.pending.insert(
+                *reqid,
+                PendingRequest {
+                    kind,
+                    remaining_pairs: pairs,
+                    subscription: subscription.clone(),
+                    error: None,
+                    responder,
+                },
+            );
+        }
+    }
+
+    pub(crate) fn handle_event(&mut self, event: &EventMessage) {
+        match event {
+            EventMessage::SubscriptionStatus {
+                channel_id,
+                channel_name,
+                pair,
+                reqid,
+                status,
+                subscription,
+                error_message,
+            } => {
+                let state = match status.as_str() {
+                    "subscribed" => SubscriptionState::Subscribed,
+                    "unsubscribed" => SubscriptionState::Unsubscribed,
+                    _ => SubscriptionState::Failed(
+                        error_message.clone().unwrap_or_else(|| status.clone()),
+                    ),
+                };
+
+                let entry_pair = match (pair, subscription) {
+                    (Some(pair), _) => Some(pair.as_str()),
+                    (None, Some(subscription)) if subscription.is_private() => Some(""),
+                    _ => None,
+                };
+                if let (Some(pair), Some(subscription)) = (entry_pair, subscription) {
+                    let entry = self.entry_mut(pair, subscription);
+                    entry.state = state.clone();
+                    if state == SubscriptionState::Subscribed {
+                        entry.channel_id = *channel_id;
+                        entry.channel_name = channel_name.clone();
+                    } else {
+                        entry.channel_id = None;
+                    }
+                }
+                if let Some(reqid) = reqid {
+                    self.resolve_pair(*reqid, pair.as_deref(), state);
+                }
+            }
+            EventMessage::Error {
+                error_message,
+                reqid: Some(reqid),
+            } => {
+                if let Some(mut pending) = self.pending.remove(reqid) {
+                    respond(
+                        &mut pending,
+                        Err(Error::Subscription(error_message.clone())),
+                    );
+                }
+            }
+            _ => (),
+        }
+    }
+
+    // After a reconnect: pending unsubscribes are done (nothing is subscribed on the new
+    // connection), pending subscribes are resent with their reqid, and every other active
+    // subscription is resent grouped by channel
+    pub(crate) fn replay_requests(&mut self) -> Vec<Request> {
+        let mut requests = Vec::new();
+
+        let unsubscribes: Vec<u64> = self
+            .pending
+            .iter()
+            .filter(|(_, pending)| pending.kind == RequestKind::Unsubscribe)
+            .map(|(reqid, _)| *reqid)
+            .collect();
+        for reqid in unsubscribes {
+            let mut pending = self.pending.remove(&reqid).unwrap();
+            for pair in &pending.remaining_pairs {
+                self.entry_mut(pair, &pending.subscription).state = SubscriptionState::Unsubscribed;
+            }
+            respond(&mut pending, Ok(()));
+        }
+
+        for entry in &mut self.entries {
+            entry.channel_id = None;
+            if entry.state == SubscriptionState::Subscribed {
+                entry.state = SubscriptionState::Pending;
+            }
+        }
+
+        let mut reqids: Vec<&u64> = self.pending.keys().collect();
+        reqids.sort();
+        for reqid in reqids {
+            let pending = &self.pending[reqid];
+            requests.push(Request::Subscribe {
+                reqid: Some(*reqid),
+                pair: request_pairs(&pending.remaining_pairs),
+                subscription: pending.subscription.clone(),
+            });
+        }
+
+        for entry in &self.entries {
+            let covered = self.pending.values().any(|pending| {
+                pending.subscription == entry.subscription
+                    && pending.remaining_pairs.contains(&entry.pair)
+            });
+            if entry.state != SubscriptionState::Pending || covered {
+                continue;
+            }
+            let existing = requests.iter_mut().find_map(|request| match request {
+                Request::Subscribe {
+                    reqid: None,
+                    pair,
+                    subscription,
+                } if subscription == &entry.subscription => Some(pair),
+                _ => None,
+            });
+            match existing {
+                Some(pairs) => pairs.extend(request_pairs(std::slice::from_ref(&entry.pair))),
+                None => requests.push(Request::Subscribe {
+                    reqid: None,
+                    pair: request_pairs(std::slice::from_ref(&entry.pair)),
+                    subscription: entry.subscription.clone(),
+                }),
+            }
+        }
+        requests
+    }
+
+    // Fails every waiting request, e.g. when the connection is given up
+    pub(crate) fn fail_pending(&mut self) {
+        for (_, mut pending) in self.pending.drain() {
+            respond(&mut pending, Err(Error::ConnectionClosed));
+        }
+    }
+
+    fn resolve_pair(&mut self, reqid: u64, pair: Option<&str>, state: SubscriptionState) {
+        let Some(pending) = self.pending.get_mut(&reqid) else {
+            return;
+        };
+        match pair {
+            Some(pair) => pending.remaining_pairs.retain(|p| p != pair),
+            None => pending.remaining_pairs.clear(),
+        }
+        if let SubscriptionState::Failed(message) = state {
+            pending.error.get_or_insert(message);
+        }
+
+        if pending.remaining_pairs.is_empty() {
+            let mut pending = self.pending.remove(&reqid).unwrap();
+            let result = match pending.error.take() {
+                Some(message) => Err(Error::Subscription(message)),
+                None => Ok(()),
+            };
+            respond(&mut pending, result);
+        }
+    }
+
+    fn entry_mut(&mut self, pair: &str, subscription: &Subscription) -> &mut SubscriptionEntry {
+        let index = match self
+            .entries
+            .iter()
+            .position(|entry| entry.pair == pair && &entry.subscription == subscription)
+        {
+            Some(index) => index,
+            None => {
+                self.entries.push(SubscriptionEntry {
+                    pair: pair.to_string(),
+                    subscription: subscription.clone(),
+                    state: SubscriptionState::Pending,
+                    channel_id: None,
+                    channel_name: None,
+                });
+                self.entries.len() - 1
+            }
+        };
+        &mut self.entries[index]
+    }
+}
+
+// Requests for private channels list no pairs; they are tracked under an empty one
+fn entry_pairs(pairs: &[String]) -> Vec<String> {
+    if pairs.is_empty() {
+        vec![String::new()]
+    } else {
+        pairs.to_vec()
+    }
+}
+
+fn request_pairs(pairs: &[String]) -> Vec<String> {
+    pairs
+        .iter()
+        .filter(|pair| !pair.is_empty())
+        .cloned()
+        .collect()
+}
+
+fn respond(pending: &mut PendingRequest, result: Result<(), Error>) {
+    if let Some(responder) = pending.responder.take() {
+        // The caller may have stopped waiting
+        let _ = responder.send(result);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::Channel;
+
+    fn status(json: serde_json::Value) -> EventMessage {
+        serde_json::from_value(json).unwrap()
+    }
+
+    fn subscribe(reqid: Option<u64>, pairs: &[&str], channel: Channel) -> Request {
+        Request::Subscribe {
+            reqid,
+            pair: pairs.iter().map(|pair| pair.to_string()).collect(),
+            subscription: channel.subscription(),
+        }
+    }
+
+    #[test]
+    fn test_resolves_after_every_pair_answers() {
+        let mut registry = SubscriptionRegistry::new();
+        let (tx, mut rx) = oneshot::channel();
+        registry.register(
+            &subscribe(Some(5), &["XBT/USD", "ETH/USD"], Channel::Ticker),
+            Some(tx),
+        );
+        assert_eq!(registry.pending_requests(), 1);
+
+        registry.handle_event(&status(serde_json::json!({
+            "channelID": 10, "channelName": "ticker", "event": "subscriptionStatus",
+            "pair": "XBT/USD", "reqid": 5, "status": "subscribed", "subscription": {"name": "ticker"}
+        })));
+        assert!(rx.try_recv().is_err());
+
+        registry.handle_event(&status(serde_json::json!({
+            "channelID": 11, "channelName": "ticker", "event": "subscriptionStatus",
+            "pair": "ETH/USD", "reqid": 5, "status": "subscribed", "subscription": {"name": "ticker"}
+        })));
+        assert!(matches!(rx.try_recv(), Ok(Ok(()))));
+        assert_eq!(registry.pending_requests(), 0);
+
+        let entry = registry.by_channel(11).unwrap();
+        assert_eq!(entry.pair, "ETH/USD");
+        assert_eq!(entry.state, SubscriptionState::Subscribed);
+        assert_eq!(entry.channel_name.as_deref(), Some("ticker"));
+    }
+
+    #[test]
+    fn test_resolves_with_kraken_error_message() {
+        let mut registry = SubscriptionRegistry::new();
+        let (tx, mut rx) = oneshot::channel();
+        registry.register(&subscribe(Some(6), &["foobar"], Channel::Ticker), Some(tx));
+
+        registry.handle_event(&status(serde_json::json!({
+            "errorMessage": "Currency pair not supported foobar", "event": "subscriptionStatus",
+            "pair": "foobar", "reqid": 6, "status": "error", "subscription": {"name": "ticker"}
+        })));
+        match rx.try_recv() {
+            Ok(Err(Error::Subscription(message))) => {
+                assert_eq!(message, "Currency pair not supported foobar")
+            }
+            other => panic!("expected a subscription error, got {:?}", other),
+        }
+        assert_eq!(
+            registry
+                .get("foobar", &Channel::Ticker.subscription())
+                .unwrap()
+                .state,
+            SubscriptionState::Failed("Currency pair not supported foobar".to_string())
+        );
+
+        // Generic error events are matched by reqid as well
+        let (tx, mut rx) = oneshot::channel();
+        registry.register(&subscribe(Some(7), &["XBT/USD"], Channel::Trade), Some(tx));
+        registry.handle_event(&status(serde_json::json!({
+            "errorMessage": "Malformed request", "event": "error", "reqid": 7
+        })));
+        assert!(matches!(rx.try_recv(), Ok(Err(Error::Subscription(_)))));
+    }
+
+    #[test]
+    fn test_replay_after_reconnect() {
+        let mut registry = SubscriptionRegistry::new();
+        registry.register(
+            &subscribe(None, &["XBT/USD", "ETH/USD"], Channel::Trade),
+            None,
+        );
+        registry.handle_event(&status(serde_json::json!({
+            "channelID": 1, "channelName": "trade", "event": "subscriptionStatus",
+            "pair": "XBT/USD", "status": "subscribed", "subscription": {"name": "trade"}
+        })));
+
+        let (tx, mut rx) = oneshot::channel();
+        registry.register(
+            &subscribe(Some(9), &["XBT/USD"], Channel::Book { depth: 10 }),
+            Some(tx),
+        );
+        let (unsubscribe_tx, mut unsubscribe_rx) = oneshot::channel();
+        registry.register(
+            &Request::Unsubscribe {
+                reqid: Some(10),
+                pair: vec!["ETH/USD".to_string()],
+                subscription: Channel::Trade.subscription(),
+            },
+            Some(unsubscribe_tx),
+        );
+
+        let requests = registry.replay_requests();
+        assert_eq!(
+            requests,
+            vec![
+                subscribe(Some(9), &["XBT/USD"], Channel::Book { depth: 10 }),
+                subscribe(None, &["XBT/USD"], Channel::Trade),
+            ]
+        );
+        assert!(matches!(unsubscribe_rx.try_recv(), Ok(Ok(()))));
+        assert!(rx.try_recv().is_err());
+        assert!(registry.by_channel(1).is_none());
+        assert_eq!(
+            registry
+                .get("ETH/USD", &Channel::Trade.subscription())
+                .unwrap()
+                .state,
+            SubscriptionState::Unsubscribed
+        );
+    }
+}
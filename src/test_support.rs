@@ -0,0 +1,133 @@
+// Local WebSocket and HTTP stand-ins for client tests
+use futures_util::{SinkExt, StreamExt};
+use serde_json::Value;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_tungstenite::{accept_async, tungstenite::protocol::Message, WebSocketStream};
+
+pub type ServerStream = WebSocketStream<TcpStream>;
+
+// Listens on a free local port and returns the `ws://` url clients should connect to
+pub async fn bind() -> (String, TcpListener) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("ws://{}", listener.local_addr().unwrap());
+    (url, listener)
+}
+
+pub async fn accept(listener: &TcpListener) -> ServerStream {
+    let (stream, _) = listener.accept().await.unwrap();
+    accept_async(stream).await.unwrap()
+}
+
+// Next text frame sent by the client, parsed as JSON
+pub async fn next_json(ws: &mut ServerStream) -> Value {
+    loop {
+        match ws.next().await.unwrap().unwrap() {
+            Message::Text(text) => return serde_json::from_str(&text).unwrap(),
+            Message::Close(_) => panic!("client closed the connection"),
+            _ => continue,
+        }
+    }
+}
+
+pub async fn send_json(ws: &mut ServerStream, value: Value) {
+    ws.send(Message::Text(value.to_string())).await.unwrap();
+}
+
+// What Kraken answers to a single-pair subscribe or unsubscribe request
+pub fn subscription_status(request: &Value, status: &str, channel_id: u64) -> Value {
+    let subscription = &request["subscription"];
+    let name = subscription["name"].as_str().unwrap();
+    let channel_name = match name {
+        "book" => format!("book-{}", subscription["depth"]),
+        "ohlc" => format!("ohlc-{}", subscription["interval"]),
+        _ => name.to_string(),
+    };
+    let mut reply = serde_json::json!({
+        "channelID": channel_id,
+        "channelName": channel_name,
+        "event": "subscriptionStatus",
+        "pair": request["pair"][0],
+        "status": status,
+        "subscription": subscription,
+    });
+    if !request["reqid"].is_null() {
+        reply["reqid"] = request["reqid"].clone();
+    }
+    reply
+}
+
+// An HTTP request as received by `serve_http_once`
+#[derive(Debug)]
+pub struct HttpRequest {
+    pub method: String,
+    pub path: String,
+    // Names are lowercased
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl HttpRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+// Answers a single HTTP/1.1 request with a 200 JSON response and closes the connection
+pub async fn serve_http_once(listener: &TcpListener, body: &str) -> HttpRequest {
+    let (mut stream, _) = listener.accept().await.unwrap();
+
+    let mut received = Vec::new();
+    let header_end = loop {
+        let mut chunk = [0; 4096];
+        let read = stream.read(&mut chunk).await.unwrap();
+        assert!(
+            read > 0,
+            "connection closed before the request headers ended"
+        );
+        received.extend_from_slice(&chunk[..read]);
+        if let Some(end) = received.windows(4).position(|window| window == b"\r\n\r\n") {
+            break end + 4;
+        }
+    };
+
+    let head = String::from_utf8(received[..header_end].to_vec()).unwrap();
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().unwrap().split(' ');
+    let method = request_line.next().unwrap().to_string();
+    let path = request_line.next().unwrap().to_string();
+    let headers: Vec<(String, String)> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_lowercase(), value.trim().to_string()))
+        .collect();
+
+    let length: usize = headers
+        .iter()
+        .find(|(name, _)| name == "content-length")
+        .map_or(0, |(_, value)| value.parse().unwrap());
+    while received.len() < header_end + length {
+        let mut chunk = [0; 4096];
+        let read = stream.read(&mut chunk).await.unwrap();
+        assert!(read > 0, "connection closed before the request body ended");
+        received.extend_from_slice(&chunk[..read]);
+    }
+    let body_received = String::from_utf8(received[header_end..].to_vec()).unwrap();
+
+    let response = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await.unwrap();
+    stream.shutdown().await.unwrap();
+
+    HttpRequest {
+        method,
+        path,
+        headers,
+        body: body_received,
+    }
+}
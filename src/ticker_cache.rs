@@ -0,0 +1,71 @@
+use crate::messages::Ticker;
+use std::collections::HashMap;
+
+/// Latest `ticker` frame for each subscribed pair.
+///
+/// Kraken sends the whole ticker on every change, so each frame simply
+/// replaces the previous one.
+#[derive(Debug, Clone, Default)]
+pub struct TickerCache {
+    tickers: HashMap<String, Ticker>,
+}
+
+impl TickerCache {
+    pub fn new() -> Self {
+        TickerCache::default()
+    }
+
+    pub fn get(&self, pair: &str) -> Option<&Ticker> {
+        self.tickers.get(pair)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Ticker)> {
+        self.tickers
+            .iter()
+            .map(|(pair, ticker)| (pair.as_str(), ticker))
+    }
+
+    pub fn update(&mut self, pair: &str, ticker: Ticker) {
+        self.tickers.insert(pair.to_string(), ticker);
+    }
+
+    // Forgets a pair, e.g. after unsubscribing from its ticker
+    pub fn remove(&mut self, pair: &str) -> Option<Ticker> {
+        self.tickers.remove(pair)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::{ChannelData, ChannelMessage};
+
+    fn ticker(close: &str) -> Ticker {
+        let frame = format!(
+            r#"[340,{{"a":["5525.40000",1,"1.000"],"b":["5525.10000",1,"1.000"],"c":["{}","0.00398963"],"v":["2634.11501494","3591.17907851"],"p":["5631.44067","5653.78939"],"t":[11493,16267],"l":["5505.00000","5505.00000"],"h":["5783.00000","5783.00000"],"o":["5760.70000","5763.40000"]}},"ticker","XBT/USD"]"#,
+            close
+        );
+        match serde_json::from_str::<ChannelMessage>(&frame).unwrap().data {
+            ChannelData::Ticker(ticker) => *ticker,
+            other => panic!("expected a ticker, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_latest_ticker_per_pair() {
+        let mut cache = TickerCache::new();
+        assert!(cache.get("XBT/USD").is_none());
+
+        cache.update("XBT/USD", ticker("5525.40000"));
+        cache.update("XBT/USD", ticker("5526.00000"));
+        cache.update("ETH/USD", ticker("180.00000"));
+
+        let xbt = cache.get("XBT/USD").unwrap();
+        assert_eq!(xbt.close.price, "5526".parse().unwrap());
+        assert_eq!(xbt.trades.last_24h, 16267);
+        assert_eq!(cache.iter().count(), 2);
+
+        assert!(cache.remove("ETH/USD").is_some());
+        assert!(cache.get("ETH/USD").is_none());
+    }
+}
@@ -0,0 +1,689 @@
+use crate::decimal::Decimal;
+use crate::error::Error;
+use crate::messages::{
+    BookEntry, BookSnapshot, BookUpdate, ChannelData, ChannelMessage, EventMessage, Level3Change,
+    Level3Order, Level3Snapshot, Level3Update, OrderEvent, OrderType, Request, Side, Subscription,
+    Trade, WsMessage,
+};
+use serde::de::Error as _;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+
+/// Translation between Kraken's WebSocket v2 wire format and the v1 types the
+/// client works with.
+///
+/// v2 requests are `{"method", "params", "req_id"}` objects and channel data
+/// arrives as `{"channel", "type": "snapshot" | "update", "data": [...]}` with
+/// prices and quantities as JSON numbers. Books keep their v1 representation,
+/// so `OrderBook` validates v2 checksums the same way: the top ten levels of
+/// each side are formatted at the pair's precision (`price_precision` and
+/// `qty_precision` from the `instrument` channel), which is why v2 books should
+/// be created with `OrderBook::with_precision`.
+///
+/// Only the `book`, `level3` and `trade` channels and pings are supported so far;
+/// `encode` refuses ticker, spread, ohlc and private subscriptions and order
+/// requests with `Error::Unsupported`, which the client checks before queueing.
+#[derive(Debug, Default)]
+pub struct Decoder {
+    // v2 book frames do not name their depth; it is taken from the subscribe result
+    book_depths: HashMap<String, usize>,
+}
+
+// v2 answers every book subscription with this depth unless asked otherwise
+const DEFAULT_BOOK_DEPTH: usize = 10;
+
+// Serializes a v1 request as its v2 equivalent
+pub fn encode(request: &Request) -> Result<String, Error> {
+    let (method, reqid, params) = match request {
+        Request::Subscribe {
+            reqid,
+            pair,
+            subscription,
+        } => (
+            "subscribe",
+            reqid,
+            Some(subscription_params(pair, subscription)?),
+        ),
+        Request::Unsubscribe {
+            reqid,
+            pair,
+            subscription,
+        } => (
+            "unsubscribe",
+            reqid,
+            Some(subscription_params(pair, subscription)?),
+        ),
+        Request::Ping { reqid } => ("ping", reqid, None),
+        other => return Err(Error::Unsupported(format!("{:?} over v2", other))),
+    };
+
+    let mut message = Map::new();
+    message.insert("method".to_string(), json!(method));
+    if let Some(params) = params {
+        message.insert("params".to_string(), params);
+    }
+    if let Some(reqid) = reqid {
+        message.insert("req_id".to_string(), json!(reqid));
+    }
+    Ok(Value::Object(message).to_string())
+}
+
+fn subscription_params(pairs: &[String], subscription: &Subscription) -> Result<Value, Error> {
+    if !matches!(subscription.name.as_str(), "book" | "level3" | "trade") {
+        return Err(Error::Unsupported(format!(
+            "{} channel over v2",
+            subscription.name
+        )));
+    }
+    let mut params = json!({"channel": subscription.name, "symbol": pairs});
+    if let Some(depth) = subscription.depth {
+        params["depth"] = json!(depth);
+    }
+    if let Some(token) = &subscription.token {
+        params["token"] = json!(token);
+    }
+    Ok(params)
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Decoder::default()
+    }
+
+    // One v2 frame can carry data for several symbols, which become one message each
+    pub fn decode(&mut self, text: &str) -> Result<Vec<WsMessage>, serde_json::Error> {
+        let value: Value = serde_json::from_str(text)?;
+        if value.get("method").is_some() {
+            let response = Response::deserialize(value)?;
+            return Ok(vec![WsMessage::Event(self.response_event(response))]);
+        }
+
+        let frame = Frame::deserialize(value)?;
+        match frame.channel.as_str() {
+            "heartbeat" => Ok(vec![WsMessage::Event(EventMessage::Heartbeat)]),
+            "status" => {
+                let statuses: Vec<Status> = serde_json::from_value(frame.data)?;
+                Ok(statuses
+                    .into_iter()
+                    .map(|status| {
+                        WsMessage::Event(EventMessage::SystemStatus {
+                            connection_id: status.connection_id,
+                            status: status.system,
+                            version: status.api_version,
+                        })
+                    })
+                    .collect())
+            }
+            "book" => {
+                let books: Vec<BookData> = serde_json::from_value(frame.data)?;
+                let snapshot = frame.kind.as_deref() == Some("snapshot");
+                books
+                    .into_iter()
+                    .map(|book| Ok(WsMessage::Channel(self.book_message(book, snapshot)?)))
+                    .collect()
+            }
+            "level3" => {
+                let books: Vec<Level3Data> = serde_json::from_value(frame.data)?;
+                let snapshot = frame.kind.as_deref() == Some("snapshot");
+                books
+                    .into_iter()
+                    .map(|book| Ok(WsMessage::Channel(level3_message(book, snapshot)?)))
+                    .collect()
+            }
+            "trade" => {
+                let trades: Vec<TradeData> = serde_json::from_value(frame.data)?;
+                trade_messages(trades)
+            }
+            other => Err(serde_json::Error::custom(format!(
+                "unsupported v2 channel: {}",
+                other
+            ))),
+        }
+    }
+
+    fn response_event(&mut self, response: Response) -> EventMessage {
+        let reqid = response.req_id;
+        let (status, result) = match (response.method.as_str(), response.success) {
+            ("pong", _) => return EventMessage::Pong { reqid },
+            ("subscribe", true) => ("subscribed", response.result),
+            ("unsubscribe", true) => ("unsubscribed", response.result),
+            ("subscribe" | "unsubscribe", false) => ("error", response.result),
+            (_, success) => {
+                return EventMessage::Error {
+                    error_message: response.error.unwrap_or_else(|| {
+                        format!("{} returned success: {}", response.method, success)
+                    }),
+                    reqid,
+                }
+            }
+        };
+
+        let subscription = result.as_ref().map(|result| Subscription {
+            name: result.channel.clone(),
+            depth: result.depth,
+            interval: result.interval,
+            token: None,
+        });
+        let pair = result
+            .as_ref()
+            .and_then(|result| result.symbol.clone())
+            .or(response.symbol);
+        if let (Some(subscription), Some(pair)) = (&subscription, &pair) {
+            if subscription.name == "book" {
+                match status {
+                    "subscribed" => {
+                        let depth = subscription.depth.unwrap_or(DEFAULT_BOOK_DEPTH);
+                        self.book_depths.insert(pair.clone(), depth);
+                    }
+                    "unsubscribed" => {
+                        self.book_depths.remove(pair);
+                    }
+                    _ => (),
+                }
+            }
+        }
+
+        EventMessage::SubscriptionStatus {
+            // v2 has no channel IDs; data is matched by symbol
+            channel_id: None,
+            channel_name: subscription.as_ref().map(channel_name),
+            pair,
+            reqid,
+            status: status.to_string(),
+            subscription,
+            error_message: response.error,
+        }
+    }
+
+    fn book_message(
+        &self,
+        book: BookData,
+        snapshot: bool,
+    ) -> Result<ChannelMessage, serde_json::Error> {
+        let depth = self
+            .book_depths
+            .get(&book.symbol)
+            .copied()
+            .unwrap_or(DEFAULT_BOOK_DEPTH);
+        let timestamp = book.timestamp.as_deref().map(parse_time).transpose()?;
+        let entries = |levels: Vec<Level>| -> Vec<BookEntry> {
+            levels
+                .into_iter()
+                .map(|level| BookEntry {
+                    price: level.price,
+                    volume: level.qty,
+                    timestamp,
+                    republish: false,
+                })
+                .collect()
+        };
+
+        let data = if snapshot {
+            ChannelData::BookSnapshot(BookSnapshot {
+                asks: entries(book.asks),
+                bids: entries(book.bids),
+                checksum: book.checksum,
+            })
+        } else {
+            ChannelData::BookUpdate(BookUpdate {
+                asks: entries(book.asks),
+                bids: entries(book.bids),
+                checksum: book.checksum,
+            })
+        };
+        Ok(ChannelMessage {
+            channel_id: 0,
+            channel_name: format!("book-{}", depth),
+            pair: book.symbol,
+            data,
+        })
+    }
+}
+
+fn level3_message(book: Level3Data, snapshot: bool) -> Result<ChannelMessage, serde_json::Error> {
+    let orders = |entries: Vec<Level3Entry>| {
+        entries
+            .into_iter()
+            .map(|entry| {
+                let order = Level3Order {
+                    order_id: entry.order_id,
+                    price: entry.limit_price,
+                    volume: entry.order_qty,
+                    timestamp: parse_time(&entry.timestamp)?,
+                };
+                Ok((entry.event, order))
+            })
+            .collect::<Result<Vec<(Option<OrderEvent>, Level3Order)>, serde_json::Error>>()
+    };
+    let changes = |entries: Vec<Level3Entry>| -> Result<Vec<Level3Change>, serde_json::Error> {
+        Ok(orders(entries)?
+            .into_iter()
+            .map(|(event, order)| Level3Change {
+                event: event.unwrap_or(OrderEvent::Add),
+                order,
+            })
+            .collect())
+    };
+    let without_events =
+        |entries: Vec<Level3Entry>| -> Result<Vec<Level3Order>, serde_json::Error> {
+            Ok(orders(entries)?
+                .into_iter()
+                .map(|(_, order)| order)
+                .collect())
+        };
+
+    let data = if snapshot {
+        ChannelData::Level3Snapshot(Level3Snapshot {
+            asks: without_events(book.asks)?,
+            bids: without_events(book.bids)?,
+            checksum: book.checksum,
+        })
+    } else {
+        ChannelData::Level3Update(Level3Update {
+            asks: changes(book.asks)?,
+            bids: changes(book.bids)?,
+            checksum: book.checksum,
+        })
+    };
+    Ok(ChannelMessage {
+        channel_id: 0,
+        channel_name: "level3".to_string(),
+        pair: book.symbol,
+        data,
+    })
+}
+
+// The v1 channel name the rest of the client keys on, e.g. `book-10`
+fn channel_name(subscription: &Subscription) -> String {
+    match subscription.depth {
+        Some(depth) => format!("{}-{}", subscription.name, depth),
+        None => subscription.name.clone(),
+    }
+}
+
+// Consecutive trades of the same symbol are kept in one message, as in v1
+fn trade_messages(trades: Vec<TradeData>) -> Result<Vec<WsMessage>, serde_json::Error> {
+    let mut messages: Vec<ChannelMessage> = Vec::new();
+    for trade in trades {
+        let time = parse_time(&trade.timestamp)?;
+        let converted = Trade {
+            price: trade.price,
+            volume: trade.qty,
+            time,
+            side: trade.side,
+            order_type: trade.ord_type,
+            misc: String::new(),
+            trade_id: trade.trade_id,
+        };
+        match messages.last_mut() {
+            Some(ChannelMessage {
+                pair,
+                data: ChannelData::Trade(trades),
+                ..
+            }) if *pair == trade.symbol => trades.push(converted),
+            _ => messages.push(ChannelMessage {
+                channel_id: 0,
+                channel_name: "trade".to_string(),
+                pair: trade.symbol,
+                data: ChannelData::Trade(vec![converted]),
+            }),
+        }
+    }
+    Ok(messages.into_iter().map(WsMessage::Channel).collect())
+}
+
+// Like `unix_time`, failing the frame on a timestamp it cannot read
+fn parse_time(timestamp: &str) -> Result<Decimal, serde_json::Error> {
+    unix_time(timestamp)
+        .ok_or_else(|| serde_json::Error::custom(format!("invalid timestamp: {}", timestamp)))
+}
+
+// `2023-09-25T07:49:37.708706Z` -> seconds since the epoch, the way v1 reports times
+fn unix_time(timestamp: &str) -> Option<Decimal> {
+    let (date, time) = timestamp.strip_suffix('Z')?.split_once('T')?;
+    let date: Vec<i64> = date
+        .split('-')
+        .map(|part| part.parse().ok())
+        .collect::<Option<_>>()?;
+    let (clock, fraction) = time.split_once('.').unwrap_or((time, ""));
+    let clock: Vec<i64> = clock
+        .split(':')
+        .map(|part| part.parse().ok())
+        .collect::<Option<_>>()?;
+    let ([year, month, day], [hour, minute, second]) = (date.as_slice(), clock.as_slice()) else {
+        return None;
+    };
+
+    let seconds =
+        days_from_civil(*year, *month, *day) * 86_400 + hour * 3_600 + minute * 60 + second;
+    let text = if fraction.is_empty() {
+        seconds.to_string()
+    } else {
+        format!("{}.{}", seconds, fraction)
+    };
+    text.parse().ok()
+}
+
+// Days between 1970-01-01 and a proleptic Gregorian date (Howard Hinnant's algorithm)
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+// `{"method": "subscribe", "result": {...}, "success": true, "req_id": 1, ...}`
+#[derive(Deserialize)]
+struct Response {
+    method: String,
+    req_id: Option<u64>,
+    #[serde(default)]
+    success: bool,
+    result: Option<ResponseResult>,
+    error: Option<String>,
+    // Some errors name the symbol outside of `result`
+    symbol: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ResponseResult {
+    channel: String,
+    symbol: Option<String>,
+    depth: Option<usize>,
+    interval: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct Frame {
+    channel: String,
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    #[serde(default)]
+    data: Value,
+}
+
+#[derive(Deserialize)]
+struct Status {
+    api_version: Option<String>,
+    connection_id: Option<u64>,
+    system: String,
+}
+
+#[derive(Deserialize)]
+struct BookData {
+    symbol: String,
+    #[serde(default)]
+    bids: Vec<Level>,
+    #[serde(default)]
+    asks: Vec<Level>,
+    checksum: Option<u32>,
+    // Only sent with updates
+    timestamp: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Level {
+    price: Decimal,
+    qty: Decimal,
+}
+
+#[derive(Deserialize)]
+struct Level3Data {
+    symbol: String,
+    #[serde(default)]
+    bids: Vec<Level3Entry>,
+    #[serde(default)]
+    asks: Vec<Level3Entry>,
+    checksum: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct Level3Entry {
+    // Only sent with updates
+    event: Option<OrderEvent>,
+    order_id: String,
+    limit_price: Decimal,
+    order_qty: Decimal,
+    timestamp: String,
+}
+
+#[derive(Deserialize)]
+struct TradeData {
+    symbol: String,
+    side: Side,
+    price: Decimal,
+    qty: Decimal,
+    ord_type: OrderType,
+    trade_id: Option<u64>,
+    timestamp: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::Channel;
+    use crate::order_book::{BookStatus, OrderBook};
+
+    fn dec(s: &str) -> Decimal {
+        s.parse().unwrap()
+    }
+
+    fn decode_one(decoder: &mut Decoder, value: Value) -> WsMessage {
+        let mut messages = decoder.decode(&value.to_string()).unwrap();
+        assert_eq!(messages.len(), 1);
+        messages.remove(0)
+    }
+
+    #[test]
+    fn test_encode_requests() {
+        let subscribe = Request::Subscribe {
+            reqid: Some(3),
+            pair: vec!["BTC/USD".to_string()],
+            subscription: Channel::Book { depth: 25 }.subscription(),
+        };
+        assert_eq!(
+            serde_json::from_str::<Value>(&encode(&subscribe).unwrap()).unwrap(),
+            json!({"method": "subscribe", "params": {"channel": "book", "symbol": ["BTC/USD"], "depth": 25}, "req_id": 3})
+        );
+        assert_eq!(
+            encode(&Request::Ping { reqid: None }).unwrap(),
+            r#"{"method":"ping"}"#
+        );
+        let spread = Request::Subscribe {
+            reqid: None,
+            pair: vec!["BTC/USD".to_string()],
+            subscription: Channel::Spread.subscription(),
+        };
+        assert!(matches!(encode(&spread), Err(Error::Unsupported(_))));
+    }
+
+    #[test]
+    fn test_decode_responses() {
+        let mut decoder = Decoder::new();
+        assert_eq!(
+            decode_one(
+                &mut decoder,
+                json!({"method": "subscribe", "result": {"channel": "book", "depth": 25, "snapshot": true, "symbol": "BTC/USD"}, "success": true, "time_in": "2023-09-25T09:04:31.742599Z", "time_out": "2023-09-25T09:04:31.742648Z", "req_id": 3})
+            ),
+            WsMessage::Event(EventMessage::SubscriptionStatus {
+                channel_id: None,
+                channel_name: Some("book-25".to_string()),
+                pair: Some("BTC/USD".to_string()),
+                reqid: Some(3),
+                status: "subscribed".to_string(),
+                subscription: Some(Channel::Book { depth: 25 }.subscription()),
+                error_message: None,
+            })
+        );
+        match decode_one(
+            &mut decoder,
+            json!({"error": "Currency pair not supported", "method": "subscribe", "req_id": 4, "success": false, "symbol": "BTC/USDx", "time_in": "2023-09-25T09:04:31.742599Z", "time_out": "2023-09-25T09:04:31.742648Z"}),
+        ) {
+            WsMessage::Event(EventMessage::SubscriptionStatus {
+                pair,
+                status,
+                error_message,
+                ..
+            }) => {
+                assert_eq!(pair.as_deref(), Some("BTC/USDx"));
+                assert_eq!(status, "error");
+                assert_eq!(
+                    error_message.as_deref(),
+                    Some("Currency pair not supported")
+                );
+            }
+            other => panic!("expected a subscription status, got {:?}", other),
+        }
+        assert_eq!(
+            decode_one(
+                &mut decoder,
+                json!({"method": "pong", "req_id": 9, "time_in": "2023-09-24T14:10:23.799685Z", "time_out": "2023-09-24T14:10:23.799703Z"})
+            ),
+            WsMessage::Event(EventMessage::Pong { reqid: Some(9) })
+        );
+        assert_eq!(
+            decode_one(&mut decoder, json!({"channel": "heartbeat"})),
+            WsMessage::Event(EventMessage::Heartbeat)
+        );
+        assert_eq!(
+            decode_one(
+                &mut decoder,
+                json!({"channel": "status", "data": [{"api_version": "v2", "connection_id": 12393906104898154338u64, "system": "online", "version": "2.0.0"}], "type": "update"})
+            ),
+            WsMessage::Event(EventMessage::SystemStatus {
+                connection_id: Some(12393906104898154338),
+                status: "online".to_string(),
+                version: Some("v2".to_string()),
+            })
+        );
+        assert!(decoder
+            .decode(r#"{"channel": "instrument", "type": "snapshot", "data": {}}"#)
+            .is_err());
+    }
+
+    #[test]
+    fn test_decode_trades() {
+        let mut decoder = Decoder::new();
+        let message = decode_one(
+            &mut decoder,
+            json!({"channel": "trade", "type": "update", "data": [
+                {"symbol": "MATIC/USD", "side": "sell", "price": 0.5117, "qty": 40.0, "ord_type": "market", "trade_id": 4665906, "timestamp": "2023-09-25T07:49:37.708706Z"},
+                {"symbol": "MATIC/USD", "side": "buy", "price": 0.5118, "qty": 12.5, "ord_type": "limit", "trade_id": 4665907, "timestamp": "2023-09-25T07:49:38Z"}
+            ]}),
+        );
+        let WsMessage::Channel(message) = message else {
+            panic!("expected channel data, got {:?}", message);
+        };
+        assert_eq!(message.pair, "MATIC/USD");
+        let ChannelData::Trade(trades) = message.data else {
+            panic!("expected trades");
+        };
+        assert_eq!(trades.len(), 2);
+        assert_eq!(trades[0].price, dec("0.5117"));
+        assert_eq!(trades[0].time, dec("1695628177.708706"));
+        assert_eq!(trades[0].side, Side::Sell);
+        assert_eq!(trades[0].order_type, OrderType::Market);
+        assert_eq!(trades[1].time, dec("1695628178"));
+        assert_eq!(trades[1].order_type, OrderType::Limit);
+    }
+
+    #[test]
+    fn test_decode_level3() {
+        let mut decoder = Decoder::new();
+        let update = decode_one(
+            &mut decoder,
+            json!({"channel": "level3", "type": "update", "data": [{"checksum": 2143854316u32, "symbol": "BTC/USD",
+                "bids": [{"event": "modify", "order_id": "OUNV3B-HZFFJ-JXKUZD", "limit_price": 45283.5, "order_qty": 0.05, "timestamp": "2023-12-08T09:00:00.000000Z"}],
+                "asks": [{"event": "delete", "order_id": "O6ZQNQ-BXL4E-5WGINO", "limit_price": 45285.2, "order_qty": 0.5, "timestamp": "2023-12-08T09:00:00.000000Z"}]}]}),
+        );
+        let WsMessage::Channel(ChannelMessage {
+            pair,
+            data: ChannelData::Level3Update(update),
+            ..
+        }) = update
+        else {
+            panic!("expected a level3 update, got {:?}", update);
+        };
+        assert_eq!(pair, "BTC/USD");
+        assert_eq!(update.checksum, Some(2143854316));
+        assert_eq!(update.bids[0].event, OrderEvent::Modify);
+        assert_eq!(update.bids[0].order.volume, dec("0.05"));
+        assert_eq!(update.bids[0].order.timestamp, dec("1702026000.000000"));
+        assert_eq!(update.asks[0].event, OrderEvent::Delete);
+
+        // An unreadable timestamp fails the frame instead of becoming zero
+        let garbled = json!({"channel": "level3", "type": "update", "data": [{"checksum": 2143854316u32, "symbol": "BTC/USD",
+            "bids": [{"event": "add", "order_id": "OUNV3B-HZFFJ-JXKUZD", "limit_price": 45283.5, "order_qty": 0.05, "timestamp": "yesterday"}],
+            "asks": []}]});
+        assert!(decoder.decode(&garbled.to_string()).is_err());
+    }
+
+    #[test]
+    fn test_book_checksum_at_pair_precision() {
+        let mut decoder = Decoder::new();
+        decoder
+            .decode(r#"{"method": "subscribe", "result": {"channel": "book", "depth": 10, "snapshot": true, "symbol": "MATIC/USD"}, "success": true, "req_id": 1}"#)
+            .unwrap();
+        let expected = |input: &str| {
+            let mut hasher = crc32fast::Hasher::new();
+            hasher.update(input.as_bytes());
+            hasher.finalize()
+        };
+        let snapshot_checksum =
+            expected("5668441079769741566946554041248756664831754963565665665822734739");
+        let snapshot = decode_one(
+            &mut decoder,
+            json!({"channel": "book", "type": "snapshot", "data": [{"symbol": "MATIC/USD",
+                "bids": [{"price": 0.5666, "qty": 4831.75496356}, {"price": 0.5665, "qty": 6658.22734739}],
+                "asks": [{"price": 0.5668, "qty": 4410.79769741}, {"price": 0.5669, "qty": 4655.40412487}],
+                "checksum": snapshot_checksum}]}),
+        );
+        let WsMessage::Channel(ChannelMessage {
+            channel_name,
+            data: ChannelData::BookSnapshot(snapshot),
+            ..
+        }) = snapshot
+        else {
+            panic!("expected a book snapshot, got {:?}", snapshot);
+        };
+        assert_eq!(channel_name, "book-10");
+
+        assert_eq!(snapshot.checksum, Some(snapshot_checksum));
+
+        // MATIC/USD trades with 4 price and 8 quantity decimals
+        let mut book = OrderBook::with_precision(10, 4, 8);
+        let mut corrupt = snapshot.clone();
+        corrupt.checksum = Some(snapshot_checksum ^ 1);
+        assert!(book.apply_snapshot(&corrupt).is_err());
+        assert_eq!(book.status(), BookStatus::Stale);
+        assert_eq!(book.apply_snapshot(&snapshot), Ok(()));
+        assert_eq!(book.status(), BookStatus::Synced);
+
+        // A numeric update carrying the checksum of the resulting book
+        let checksum = expected("56684410797697415669465540412487566648317549635656655000000000");
+        let update = decode_one(
+            &mut decoder,
+            json!({"channel": "book", "type": "update", "data": [{"symbol": "MATIC/USD",
+                "bids": [{"price": 0.5665, "qty": 50.0}], "asks": [],
+                "checksum": checksum, "timestamp": "2023-10-06T17:35:55.440295Z"}]}),
+        );
+        let WsMessage::Channel(ChannelMessage {
+            data: ChannelData::BookUpdate(update),
+            ..
+        }) = update
+        else {
+            panic!("expected a book update, got {:?}", update);
+        };
+        assert_eq!(update.bids[0].timestamp, Some(dec("1696613755.440295")));
+        assert_eq!(book.apply_update(&update), Ok(()));
+        assert_eq!(book.status(), BookStatus::Synced);
+
+        let mut stale = update.clone();
+        stale.checksum = Some(checksum ^ 1);
+        assert!(book.apply_update(&stale).is_err());
+        assert_eq!(book.status(), BookStatus::Stale);
+    }
+}